@@ -0,0 +1,52 @@
+use std::{fs, io::Write, path::Path};
+
+use zip::{result::ZipResult, write::SimpleFileOptions, AesMode, CompressionMethod, ZipWriter};
+
+use crate::style;
+
+/// Bundles a set of files into a single AES-256 encrypted ZIP archive.
+///
+/// Each file is stored under its file name only (without directories), so the archive extracts
+/// flat, wherever it's opened.
+///
+/// # Arguments
+///
+/// * `archive_path` - Where to write the ZIP archive.
+/// * `files` - The paths of the files to include.
+/// * `password` - The password required to extract the files.
+///
+pub fn zip_encrypted(archive_path: &str, files: &[String], password: &str) -> ZipResult<()> {
+    let mut zip = ZipWriter::new(fs::File::create(archive_path)?);
+    let options = SimpleFileOptions::default()
+        .compression_method(CompressionMethod::Deflated)
+        .with_aes_encryption(AesMode::Aes256, password);
+
+    for file in files {
+        let name = Path::new(file)
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_else(|| file.to_owned());
+        zip.start_file(name, options)?;
+        zip.write_all(&fs::read(file)?)?;
+    }
+
+    zip.finish()?;
+    Ok(())
+}
+
+/// Prompts for an archive password (without echoing it), asking twice to rule out typos
+pub fn ask_password() -> String {
+    loop {
+        let password = rpassword::prompt_password("Enter a password for the ZIP archive: ").unwrap_or_default();
+        if password.is_empty() {
+            eprintln!("{}", style::warning("The password can't be empty - try again!"));
+            continue;
+        }
+        let confirmation = rpassword::prompt_password("Confirm the password: ").unwrap_or_default();
+        if password == confirmation {
+            humanln!();
+            return password;
+        }
+        eprintln!("{}", style::warning("Passwords don't match - try again!"));
+    }
+}
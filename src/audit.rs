@@ -0,0 +1,108 @@
+use std::io;
+
+use secp256k1::{PublicKey, Secp256k1};
+
+use crate::{
+    code::verify_check_char,
+    coins::CoinParams,
+    derive::Deriver,
+    derive_valid_key,
+    output::read_csv,
+    pubkey_to_address,
+    rpc::ReconnectingRpc,
+    style,
+};
+
+/// Audits a saved CSV before its codes are distributed: every code is re-derived, and its address
+/// checked.
+///
+/// Each row is checked to be for the selected coin, to have a valid check character (if expected),
+/// to derive a valid key, and to derive an address of the coin's format. If the CSV has an `address`
+/// column, the derived address must match it. Optionally, the address's balance is checked against the
+/// row's value too, which requires the wallet to know the address (for example, after a `--watch-only`
+/// import).
+///
+/// Without either, a code derived with the wrong namespace, scheme or iteration target still derives a
+/// well-formed address, so nothing ties the codes to their funds: this is warned about up-front.
+///
+/// # Arguments
+///
+/// * `path` - The CSV to audit.
+/// * `coin_params` - A reference to the CoinParams for the selected coin.
+/// * `deriver` - The Deriver for the scheme the codes were generated with.
+/// * `check_char` - Whether the codes end in a check character.
+/// * `rpc` - The RPC client of a wallet watching the addresses, to check their balances.
+///
+/// # Returns
+///
+/// The number of rows with problems, and whether every code was tied to its address or funds (rather
+/// than only checked for well-formedness).
+///
+pub fn audit_csv(
+    path: &str,
+    coin_params: &CoinParams,
+    deriver: &Deriver,
+    check_char: bool,
+    mut rpc: Option<&mut ReconnectingRpc>,
+) -> io::Result<(usize, bool)> {
+    let records = read_csv(path)?;
+    let verified = rpc.is_some() || records.iter().all(|record| record.address.is_some());
+    if !verified {
+        eprintln!(
+            "{}",
+            style::warning(
+                "Warning: without an address column or \"--check-balance\", only the codes' well-formedness can be checked - \
+                 codes derived with the wrong namespace, derivation or iteration target would still pass!"
+            )
+        );
+    }
+    let secp = Secp256k1::new();
+    let total = records.len();
+    let mut problems = 0;
+
+    for (i, record) in records.iter().enumerate() {
+        let mut report = |problem: String| {
+            eprintln!("{}", style::error(format!("Row {} of {}: '{}' - {}", i + 1, total, record.code, problem)));
+            problems += 1;
+        };
+
+        if record.coin != coin_params.ticker.to_lowercase() {
+            report(format!("is for \"{}\", not {}", record.coin, coin_params.ticker));
+            continue;
+        }
+        if check_char && !verify_check_char(&record.code) {
+            report(String::from("has an invalid check character"));
+            continue;
+        }
+        let Some(private) = derive_valid_key(&deriver.derive(&record.code)) else {
+            report(String::from("derives an invalid key"));
+            continue;
+        };
+        let address = pubkey_to_address(PublicKey::from_secret_key(&secp, &private), coin_params.pub_key_byte, coin_params.compressed);
+        if let Err(e) = coin_params.check_address(&address) {
+            report(e);
+            continue;
+        }
+        if let Some(expected) = record.address.as_ref().filter(|expected| **expected != address) {
+            report(format!("derives {}, but the CSV lists {}", address, expected));
+            continue;
+        }
+
+        // Funded codes should hold (at least) their value
+        if let (Some(rpc), true) = (rpc.as_deref_mut(), record.value > 0.0) {
+            match rpc.call(|c| c.getreceivedbyaddress(&address, Some(0))) {
+                Ok(received) if received >= record.value => {}
+                Ok(received) => {
+                    report(format!("{} has received {} {}, expected {}", address, received, coin_params.ticker, record.value));
+                    continue;
+                }
+                Err(e) => {
+                    report(format!("couldn't check the balance of {}: {}", address, e));
+                    continue;
+                }
+            }
+        }
+        humanln!("Row {} of {}: '{}' - OK ({})", i + 1, total, record.code, address);
+    }
+    Ok((problems, verified))
+}
@@ -0,0 +1,99 @@
+use std::time::{Duration, Instant};
+
+use crate::hash::{sha256, sha256_rounds};
+
+/// How many SHA256 rounds to time when benchmarking
+pub const BENCHMARK_ROUNDS: u64 = 250_000;
+
+/// Measures this machine's single-threaded SHA256 throughput, using the same chained hashing as
+/// promo key derivation.
+///
+/// # Returns
+///
+/// The number of hashes per second.
+///
+pub fn benchmark_hash_rate() -> f64 {
+    let seed = sha256(b"batcher-benchmark");
+    let start = Instant::now();
+    let hash = sha256_rounds(seed, BENCHMARK_ROUNDS);
+    let elapsed = start.elapsed().as_secs_f64().max(f64::EPSILON);
+
+    // Make sure the loop isn't optimised away
+    std::hint::black_box(hash);
+
+    BENCHMARK_ROUNDS as f64 / elapsed
+}
+
+/// Estimates how long it would take to derive a number of codes, at a given hash rate
+pub fn estimate_runtime(total_codes: u64, iterations: u64, hash_rate: f64) -> Duration {
+    Duration::from_secs_f64(total_codes as f64 * iterations as f64 / hash_rate)
+}
+
+/// Benchmarks this machine and prints a preflight estimate for a run: how many worker threads it
+/// uses, and roughly how long deriving every code will take.
+///
+/// # Arguments
+///
+/// * `total_codes` - The number of codes to generate.
+/// * `hashes_per_code` - The SHA256 iterations each code costs, on average (including vanity attempts).
+/// * `threads` - The number of worker threads deriving in parallel.
+///
+pub fn print_preflight(total_codes: u64, hashes_per_code: f64, threads: usize) {
+    humanln!("Benchmarking derivation speed...");
+    let hash_rate = benchmark_hash_rate();
+    let runtime = Duration::from_secs_f64(total_codes as f64 * hashes_per_code / (hash_rate * threads as f64));
+    humanln!(
+        "Preflight: {} worker thread(s) at ~{:.0} hashes/s each, so ~{} to derive {} codes",
+        threads,
+        hash_rate,
+        format_duration(runtime),
+        total_codes
+    );
+}
+
+/// Formats a duration in a human-friendly "1h 2m 3s" style
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    let (hours, minutes, seconds) = (secs / 3600, (secs % 3600) / 60, secs % 60);
+    if hours > 0 {
+        format!("{}h {}m {}s", hours, minutes, seconds)
+    } else if minutes > 0 {
+        format!("{}m {}s", minutes, seconds)
+    } else {
+        format!("{:.1}s", duration.as_secs_f64())
+    }
+}
+
+/// Computes the iteration count that would take roughly `target_ms` to derive at a given hash rate,
+/// rounded to the nearest 100,000 for a tidy constant (but never zero).
+pub fn iterations_for_time(target_ms: u64, hash_rate: f64) -> u64 {
+    let exact = hash_rate * target_ms as f64 / 1000.0;
+    ((exact / 100_000.0).round() as u64 * 100_000).max(100_000)
+}
+
+/// Benchmarks this machine and prints the iteration target needed for derivations to take `target_ms`
+pub fn print_tune(target_ms: u64) {
+    humanln!("Benchmarking SHA256 throughput...");
+    let hash_rate = benchmark_hash_rate();
+    let iterations = iterations_for_time(target_ms, hash_rate);
+    humanln!("Measured: {:.0} hashes/s (single-threaded)", hash_rate);
+    humanln!(
+        "For ~{}ms per code on this machine, use {} iterations, e.g: `pub const PROMO_TARGETS: [u64; 1] = [{}];`",
+        target_ms,
+        iterations,
+        format_thousands(iterations)
+    );
+}
+
+/// Formats a number with '_' thousands separators, as in a Rust integer literal
+fn format_thousands(n: u64) -> String {
+    let digits = n.to_string();
+    let mut out = String::new();
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i) % 3 == 0 {
+            out.push('_');
+        }
+        out.push(c);
+    }
+    out
+}
@@ -0,0 +1,755 @@
+use std::str::FromStr;
+
+use crate::{
+    config::{apply_env, apply_file_config, load_file_config},
+    derive::DerivationScheme,
+    export::KeyExportFormat,
+    fiat::DEFAULT_FIAT_CURRENCY,
+    filter::ValueRange,
+    qr::{QrFormat, QrPayload},
+    fill::{FailureAction, FailurePolicy, DEFAULT_MAX_OUTPUTS},
+    output::OutputFormat,
+    style,
+};
+
+/// The default time budget for `tune`, in milliseconds
+pub const DEFAULT_TUNE_TARGET_MS: u64 = 2000;
+
+/// The options most runs need, printed by `--help` after the commands (the README lists every one)
+const COMMON_OPTIONS: &str = "\
+Common options:
+  --coin <ticker>                The coin to use
+  --prefix <value>               The code prefix (\"\" for none)
+  --output-dir <dir>             Where to save output files
+  --output-format <format>       csv, json or jsonl
+  --fee <amount>                 The fee added to each code's value
+  --threads <n>                  How many threads derive codes
+  --dry-run, --plan-only         Plan the batches, without generating anything
+  --no-fill                      Generate codes without funding them
+  --template <name>              Run a saved batch template
+  -h, --help                     Print this summary
+  -V, --version                  Print Batcher's version
+
+Options may also be set in batcher.toml, or as BATCHER_* environment variables.
+";
+
+/// What Batcher has been asked to do
+#[derive(Default, PartialEq)]
+pub enum Command {
+    /// Plan, generate and fund promo codes (the default)
+    #[default]
+    Run,
+    /// Benchmark SHA256 and suggest an iteration target for a time budget
+    Tune { target_ms: u64 },
+    /// Plan batches, then compare them against an existing CSV without generating anything
+    PlanDiff { csv: String },
+    /// Fund the addresses listed in a CSV, writing the TX IDs back into it
+    Fund { csv: String },
+    /// Re-derive every code in a CSV, checking each derives a valid (and optionally funded) address
+    Audit { csv: String },
+    /// Merge several batch CSVs into one, de-duplicating by code
+    Merge { out: String, inputs: Vec<String> },
+    /// Check a WIF against the `wif_hash` column of the CSV it was saved in
+    VerifyWif { wif: String, csv: String },
+    /// Print the Merkle inclusion proof of a code, against the root of the CSV it was saved in
+    Prove { code: String, csv: String },
+    /// Copy the rows of a CSV whose value is within `--min-value`/`--max-value` into a new CSV
+    Filter { csv: String, out: String },
+    /// Locate and parse a coin's config, reporting what it resolves to without contacting the daemon
+    CheckConfig,
+    /// Derive a pool of unfunded codes into a reserve file, for drawing from later
+    Reserve { path: String, count: u64 },
+    /// Fund codes from a reserve file with a value, issuing them on demand
+    Draw { path: String, count: usize, value: f64 },
+}
+
+/// Every command, as `(name, arguments, description)`: the parser checks names against it, and `--help` lists it
+const COMMANDS: &[(&str, &str, &str)] = &[
+    ("tune", "[--target-ms <ms>]", "Suggest an iteration target for a time budget"),
+    ("plan-diff", "<csv>", "Compare a plan against an existing CSV, generating nothing"),
+    ("fund", "<csv>", "Fund the addresses listed in a CSV"),
+    ("audit", "<csv>", "Re-derive and check every code in a CSV"),
+    ("merge", "<out> <csv>...", "Merge batch CSVs, de-duplicating by code"),
+    ("verify-wif", "<wif> <csv>", "Check a WIF against the CSV it was saved in"),
+    ("prove", "<code> <csv>", "Print a code's Merkle inclusion proof"),
+    ("filter", "<csv> <out>", "Copy the rows within --min-value/--max-value"),
+    ("check-config", "", "Report a coin's resolved RPC config, offline"),
+    ("reserve", "<file> <count>", "Derive unfunded codes into a reserve file"),
+    ("draw", "<file> <count> <value>", "Fund and issue codes from a reserve file"),
+    ("help", "", "Print this summary"),
+];
+
+/// Prints the summary for `--help`: the commands, from the same table the parser checks them against, then
+/// the common options
+fn print_usage() {
+    humanln!("Usage: batcher [command] [options]");
+    humanln!();
+    humanln!("With no command, Batcher plans, generates and funds promo codes, asking for anything the options don't cover.");
+    humanln!();
+    humanln!("Commands:");
+    for (name, args, description) in COMMANDS {
+        humanln!("  {:<30} {}", format!("{} {}", name, args).trim_end(), description);
+    }
+    humanln!();
+    human!("{}", COMMON_OPTIONS);
+}
+
+impl Command {
+    /// The command's name, as given on the command line (the default run has none)
+    pub fn name(&self) -> Option<&'static str> {
+        match self {
+            Command::Run => None,
+            Command::Tune { .. } => Some("tune"),
+            Command::PlanDiff { .. } => Some("plan-diff"),
+            Command::Fund { .. } => Some("fund"),
+            Command::Audit { .. } => Some("audit"),
+            Command::Merge { .. } => Some("merge"),
+            Command::VerifyWif { .. } => Some("verify-wif"),
+            Command::Prove { .. } => Some("prove"),
+            Command::Filter { .. } => Some("filter"),
+            Command::CheckConfig => Some("check-config"),
+            Command::Reserve { .. } => Some("reserve"),
+            Command::Draw { .. } => Some("draw"),
+        }
+    }
+}
+
+/// Command-line options that tweak Batcher's behaviour.
+///
+/// Anything not covered here is asked interactively during the run. Defaults may also come from a
+/// `batcher.toml` config file or `BATCHER_*` environment variables, which flags always override.
+///
+pub struct CliArgs {
+    /// The command to perform
+    pub command: Command,
+    /// Sign each promo code with its own key, as an auditable proof-of-generation
+    pub sign_codes: bool,
+    /// Never clear the terminal between batch prompts, keeping scrollback (and redirected logs) intact
+    pub no_clear: bool,
+    /// Import every generated address into the wallet as watch-only, to monitor redemptions
+    pub watch_only: bool,
+    /// Rescan the chain once after the watch-only imports (slow, but picks up existing history)
+    pub rescan: bool,
+    /// The promo code prefix, skipping the interactive prompt (an empty string means no prefix)
+    pub prefix: Option<String>,
+    /// The number of random characters in each code, overriding the prefix-based default
+    pub code_length: Option<usize>,
+    /// Bundle the output files into a password-protected ZIP archive
+    pub zip: bool,
+    /// The wallet to fund from, for daemons with multiple wallets loaded
+    pub wallet: Option<String>,
+    /// Back the wallet up to this path (on the daemon's machine) before the first fill
+    pub backup_wallet: Option<String>,
+    /// Emit JSON progress events (one per finished code) to this file ("-" for stdout)
+    pub progress_json: Option<String>,
+    /// Only export codes worth this range of values (with `filter`, or as a filtered copy of the save file)
+    pub value_range: ValueRange,
+    /// The ticker of the coin to use, skipping the coin selection prompt
+    pub coin: Option<String>,
+    /// The directory to save output files into
+    pub output_dir: Option<String>,
+    /// The format the batch is saved in
+    pub output_format: OutputFormat,
+    /// Gzip-compress the save file, streaming rows through the encoder
+    pub gzip: bool,
+    /// Continue an interrupted run from its state file, instead of planning a new one
+    pub resume: bool,
+    /// Add a column of each WIF's SHA256 to CSVs, rather than the WIF itself
+    pub wif_hash: bool,
+    /// Add a column of when each code was completed
+    pub timestamps: bool,
+    /// Add a column of the iteration target each code was derived with
+    pub record_target: bool,
+    /// Derive every key twice, stopping if the two ever differ
+    pub double_check: bool,
+    /// Ask for a maximum value for each batch, giving each code a random value up to it
+    pub random_values: bool,
+    /// Ask for an optional note for each batch, written alongside each of its codes
+    pub batch_notes: bool,
+    /// Also save the batch as a CSV to this path
+    pub csv_out: Option<String>,
+    /// Also save the batch as a JSON array to this path
+    pub json_out: Option<String>,
+    /// Also save every promo's WIF to this (owner-only) file
+    pub wif_vault: Option<String>,
+    /// Hold rows in memory, writing them out every this many codes (0 for only at the end)
+    pub buffer_writes: Option<usize>,
+    /// Mint batches worth no more than the coin's dust threshold without asking
+    pub allow_dust: bool,
+    /// After each batch, print its summary and ask before starting the next
+    pub pause_between_batches: bool,
+    /// Export the codes in the redeemer's format (`coin,code,value`) to this file
+    pub redeemer_export: Option<String>,
+    /// Export each funded code's payment URI (`code,address,uri`) to this file
+    pub uri_export: Option<String>,
+    /// End the CSV header with a trailing comma, as older versions did
+    pub csv_trailing_comma: bool,
+    /// The network fee added to each fill for the redeemer, overriding the coin's default
+    pub fee: Option<f64>,
+    /// The price of one coin in fiat, for showing totals' fiat equivalents
+    pub fiat_rate: Option<f64>,
+    /// The fiat currency `fiat_rate` is in
+    pub fiat_currency: String,
+    /// Enter batch values in fiat, converting them to coins at `fiat_rate`
+    pub fiat_values: bool,
+    /// The total value (in coins) the batches may spend, letting values be entered as a share of what's left
+    pub budget: Option<f64>,
+    /// Append a Luhn mod N check character to each code, so redeemers can catch typos
+    pub check_char: bool,
+    /// Generate numeric-only codes, with enough digits to keep the default codes' entropy
+    pub numeric: bool,
+    /// Export every key (labelled with its code) to this JSON file, for importing into a backup wallet
+    pub export_keys: Option<String>,
+    /// Which wallet import RPC the key export is for
+    pub export_keys_format: KeyExportFormat,
+    /// Only plan the batches and print a summary, without generating or funding anything
+    pub plan_only: bool,
+    /// Fund codes in groups with `sendmany`, rather than one `sendtoaddress` per code
+    pub sendmany: bool,
+    /// The maximum number of outputs in each `sendmany` TX
+    pub max_outputs: usize,
+    /// Disable colored output, even at a terminal
+    pub no_color: bool,
+    /// Emit errors, warnings and the final summary as JSON, one object per line
+    pub json_errors: bool,
+    /// Mask codes and WIFs in console output, leaving the full values to the saved file
+    pub redact: bool,
+    /// Only fund promos from coins with at least this many confirmations
+    pub min_conf: Option<u32>,
+    /// Once funding is done, wait until every funding TX has this many confirmations
+    pub wait_confirmations: Option<u64>,
+    /// The number of seconds between confirmation checks, while waiting for confirmations
+    pub poll_interval: Option<u64>,
+    /// The key derivation scheme to use
+    pub derivation: DerivationScheme,
+    /// Save a QR code of each promo code into this directory
+    pub qr_dir: Option<String>,
+    /// Save a QR code of each promo's WIF into this (owner-only) directory, for cold-storage backups
+    pub wif_qr_dir: Option<String>,
+    /// The image format of saved QR codes
+    pub qr_format: QrFormat,
+    /// What each promo's QR code encodes
+    pub qr_payload: QrPayload,
+    /// Periodically save in-progress derivations to this file, resuming from it after a crash
+    pub checkpoint: Option<String>,
+    /// Send exactly each code's value, with the network fee subtracted from it
+    pub subtract_fee: bool,
+    /// Require re-typing the total before runs costing at least this much
+    pub confirm_threshold: Option<f64>,
+    /// Require typing the coin ticker before mainnet runs costing at least this much
+    pub mainnet_guard: Option<f64>,
+    /// Show the funding wallet's name and balance, requiring confirmation before anything is funded
+    pub confirm_wallet: bool,
+    /// Only keep codes whose address starts with this string
+    pub vanity: Option<String>,
+    /// How many times the RPC client retries a failed request
+    pub rpc_retries: Option<u32>,
+    /// The delay between the RPC client's retries, in milliseconds
+    pub rpc_retry_delay: Option<u64>,
+    /// The RPC client's request timeout, in milliseconds
+    pub rpc_timeout: Option<u64>,
+    /// Generate codes with their planned values, but never fund them (or contact the daemon)
+    pub no_fill: bool,
+    /// Give up on funding a code after this many failed attempts, rather than retrying forever
+    pub max_fill_attempts: Option<u32>,
+    /// Fail this fraction of funding attempts on purpose, to exercise error handling (testing builds only)
+    pub simulate_failures: Option<f64>,
+    /// Use these (public, private) version bytes instead of the coin's, for test networks (testing builds only)
+    pub version_bytes: Option<(u8, u8)>,
+    /// Lock each fill's change, so consecutive fills don't chain promo addresses together on-chain
+    pub avoid_clustering: bool,
+    /// Trim trailing zeros from CSV values, rather than printing every decimal the coin supports
+    pub trim_values: bool,
+    /// The number of worker threads for parallel derivation, instead of one per CPU core
+    pub threads: Option<usize>,
+    /// Start planning from this saved template
+    pub template: Option<String>,
+    /// Save the finished plan as a template with this name
+    pub save_template: Option<String>,
+    /// Abort the run on the first failed fill, instead of retrying
+    pub strict: bool,
+    /// What happens once a fill has failed
+    pub on_failure: FailurePolicy,
+    /// What the prompt failure policy does without a terminal to ask at
+    pub on_failure_fallback: FailureAction,
+    /// Cross-check the coin's address version byte against the daemon's wallet addresses
+    pub check_coin_params: bool,
+    /// Print a block explorer link for each funding TX
+    pub explorer_links: bool,
+    /// Enter interactive decimals with a comma (e.g. "2,5"), as in many locales; outputs still use a point
+    pub decimal_comma: bool,
+    /// When auditing, also check each funded code's address has received its value
+    pub check_balance: bool,
+    /// Mix this namespace into every key derivation, isolating the run's keys from other campaigns
+    pub namespace: Option<String>,
+    /// Record every funding RPC call and its response to this file, as an audit trail
+    pub log_file: Option<String>,
+}
+
+impl Default for CliArgs {
+    fn default() -> Self {
+        CliArgs {
+            command: Command::default(),
+            sign_codes: false,
+            no_clear: false,
+            watch_only: false,
+            rescan: false,
+            prefix: None,
+            code_length: None,
+            zip: false,
+            wallet: None,
+            backup_wallet: None,
+            progress_json: None,
+            value_range: ValueRange::default(),
+            coin: None,
+            output_dir: None,
+            output_format: OutputFormat::default(),
+            gzip: false,
+            resume: false,
+            wif_hash: false,
+            timestamps: false,
+            record_target: false,
+            double_check: false,
+            random_values: false,
+            batch_notes: false,
+            csv_out: None,
+            json_out: None,
+            wif_vault: None,
+            buffer_writes: None,
+            allow_dust: false,
+            pause_between_batches: false,
+            redeemer_export: None,
+            uri_export: None,
+            csv_trailing_comma: false,
+            fee: None,
+            fiat_rate: None,
+            fiat_currency: String::from(DEFAULT_FIAT_CURRENCY),
+            fiat_values: false,
+            budget: None,
+            check_char: false,
+            numeric: false,
+            export_keys: None,
+            export_keys_format: KeyExportFormat::default(),
+            plan_only: false,
+            sendmany: false,
+            max_outputs: DEFAULT_MAX_OUTPUTS,
+            no_color: false,
+            json_errors: false,
+            redact: false,
+            min_conf: None,
+            wait_confirmations: None,
+            poll_interval: None,
+            derivation: DerivationScheme::default(),
+            qr_dir: None,
+            wif_qr_dir: None,
+            qr_format: QrFormat::default(),
+            qr_payload: QrPayload::default(),
+            checkpoint: None,
+            subtract_fee: false,
+            confirm_threshold: None,
+            mainnet_guard: None,
+            confirm_wallet: false,
+            vanity: None,
+            rpc_retries: None,
+            rpc_retry_delay: None,
+            rpc_timeout: None,
+            no_fill: false,
+            max_fill_attempts: None,
+            simulate_failures: None,
+            version_bytes: None,
+            avoid_clustering: false,
+            trim_values: false,
+            threads: None,
+            template: None,
+            save_template: None,
+            strict: false,
+            on_failure: FailurePolicy::default(),
+            on_failure_fallback: FailureAction::default(),
+            check_coin_params: false,
+            explorer_links: false,
+            decimal_comma: false,
+            check_balance: false,
+            namespace: None,
+            log_file: None,
+        }
+    }
+}
+
+/// Parses the process arguments into a `CliArgs` struct, ignoring (and warning about) unknown ones.
+///
+/// Precedence is: built-in defaults < config file < environment variables < command-line flags.
+///
+pub fn parse_args() -> CliArgs {
+    let mut cli = CliArgs::default();
+    // Decided before anything else, so even errors in the arguments themselves are structured
+    style::init_json_errors(std::env::args().any(|arg| arg == "--json-errors"));
+    apply_file_config(&mut cli, load_file_config());
+    apply_env(&mut cli);
+
+    let mut args = std::env::args().skip(1).peekable();
+    while let Some(arg) = args.next() {
+        // Only one command runs at a time, so a second would otherwise silently replace the first
+        if let (true, Some(command)) = (COMMANDS.iter().any(|(name, ..)| *name == arg), cli.command.name()) {
+            eprintln!("{}", style::usage_error(format!("Only one command can be given, but found both \"{}\" and \"{}\"", command, arg)));
+            std::process::exit(1);
+        }
+        match arg.as_str() {
+            "--sign" => cli.sign_codes = true,
+            "--no-clear" => cli.no_clear = true,
+            "--no-color" => cli.no_color = true,
+            "--json-errors" => cli.json_errors = true,
+            "--redact" => cli.redact = true,
+            "--watch-only" => cli.watch_only = true,
+            "--rescan" => cli.rescan = true,
+            "--zip" => cli.zip = true,
+            "--plan-only" | "--dry-run" => cli.plan_only = true,
+            "--help" | "-h" | "help" => {
+                print_usage();
+                std::process::exit(0);
+            }
+            "--version" | "-V" => {
+                humanln!("batcher {}", env!("CARGO_PKG_VERSION"));
+                std::process::exit(0);
+            }
+            "--sendmany" => cli.sendmany = true,
+            "--subtract-fee" => cli.subtract_fee = true,
+            "--no-fill" => cli.no_fill = true,
+            "--avoid-clustering" => cli.avoid_clustering = true,
+            "--strict" => cli.strict = true,
+            "--gzip" => cli.gzip = true,
+            "--csv-trailing-comma" => cli.csv_trailing_comma = true,
+            "--batch-notes" => cli.batch_notes = true,
+            "--random-values" => cli.random_values = true,
+            "--wif-hash" => cli.wif_hash = true,
+            "--timestamps" => cli.timestamps = true,
+            "--record-target" => cli.record_target = true,
+            "--double-check" => cli.double_check = true,
+            "--continue" => cli.resume = true,
+            "--csv" => cli.csv_out = Some(expect_value(&arg, args.next())),
+            "--json" => cli.json_out = Some(expect_value(&arg, args.next())),
+            "--wif-vault" => cli.wif_vault = Some(expect_value(&arg, args.next())),
+            "--allow-dust" => cli.allow_dust = true,
+            "--pause-between-batches" => cli.pause_between_batches = true,
+            "--redeemer-export" => cli.redeemer_export = Some(expect_value(&arg, args.next())),
+            "--buffer-writes" => cli.buffer_writes = Some(parse_value(&arg, &expect_value(&arg, args.next()))),
+            "--explorer-links" => cli.explorer_links = true,
+            "--decimal-comma" => cli.decimal_comma = true,
+            "--check-balance" => cli.check_balance = true,
+            "--namespace" => cli.namespace = Some(expect_value(&arg, args.next())),
+            "--check-coin-params" => cli.check_coin_params = true,
+            "--trim-values" => cli.trim_values = true,
+            "--min-conf" => cli.min_conf = Some(parse_value(&arg, &expect_value(&arg, args.next()))),
+            "--wait-confirmations" => cli.wait_confirmations = Some(parse_value(&arg, &expect_value(&arg, args.next()))),
+            "--poll-interval" => cli.poll_interval = Some(parse_value(&arg, &expect_value(&arg, args.next()))),
+            "--max-outputs" => cli.max_outputs = parse_value(&arg, &expect_value(&arg, args.next())),
+            "--check-char" => cli.check_char = true,
+            "--numeric" => cli.numeric = true,
+            "--wallet" => cli.wallet = Some(expect_value(&arg, args.next())),
+            "--backup-wallet" => cli.backup_wallet = Some(expect_value(&arg, args.next())),
+            "--progress-json" => cli.progress_json = Some(expect_value(&arg, args.next())),
+            "--prefix" => cli.prefix = Some(expect_value(&arg, args.next())),
+            "--code-length" => cli.code_length = Some(parse_value(&arg, &expect_value(&arg, args.next()))),
+            "--export-keys" => cli.export_keys = Some(expect_value(&arg, args.next())),
+            "--export-keys-format" => {
+                cli.export_keys_format = match expect_value(&arg, args.next()).as_str() {
+                    "importmulti" => KeyExportFormat::ImportMulti,
+                    "descriptors" => KeyExportFormat::Descriptors,
+                    other => {
+                        eprintln!("{}", style::usage_error(format!("Unknown key export format \"{}\", expected \"importmulti\" or \"descriptors\"", other)));
+                        std::process::exit(1);
+                    }
+                }
+            }
+            "--derivation" => {
+                cli.derivation = match expect_value(&arg, args.next()).as_str() {
+                    "v1" => DerivationScheme::V1,
+                    "v2" => DerivationScheme::V2,
+                    other => {
+                        eprintln!("{}", style::usage_error(format!("Unknown derivation scheme \"{}\", expected \"v1\" or \"v2\"", other)));
+                        std::process::exit(1);
+                    }
+                }
+            }
+            "--qr-dir" => cli.qr_dir = Some(expect_value(&arg, args.next())),
+            "--wif-qr-dir" => cli.wif_qr_dir = Some(expect_value(&arg, args.next())),
+            "--qr-format" => {
+                cli.qr_format = match expect_value(&arg, args.next()).as_str() {
+                    "png" => QrFormat::Png,
+                    "svg" => QrFormat::Svg,
+                    other => {
+                        eprintln!("{}", style::usage_error(format!("Unknown QR format \"{}\", expected \"png\" or \"svg\"", other)));
+                        std::process::exit(1);
+                    }
+                }
+            }
+            "--qr-payload" => {
+                cli.qr_payload = match expect_value(&arg, args.next()).as_str() {
+                    "code" => QrPayload::Code,
+                    "uri" => QrPayload::Uri,
+                    other => {
+                        eprintln!("{}", style::usage_error(format!("Unknown QR payload \"{}\", expected \"code\" or \"uri\"", other)));
+                        std::process::exit(1);
+                    }
+                }
+            }
+            "--uri-export" => cli.uri_export = Some(expect_value(&arg, args.next())),
+            "--checkpoint" => cli.checkpoint = Some(expect_value(&arg, args.next())),
+            "audit" => cli.command = Command::Audit { csv: expect_value(&arg, args.next()) },
+            "merge" => {
+                let out = expect_value(&arg, args.next());
+                let mut inputs = Vec::new();
+                while let Some(input) = args.next_if(|next| !next.starts_with("--")) {
+                    inputs.push(input);
+                }
+                if inputs.is_empty() {
+                    eprintln!("{}", style::usage_error(format!("\"merge\" needs at least one CSV to merge into \"{}\"", out)));
+                    std::process::exit(1);
+                }
+                cli.command = Command::Merge { out, inputs };
+            }
+            "verify-wif" => {
+                let wif = expect_value(&arg, args.next());
+                cli.command = Command::VerifyWif { wif, csv: expect_value(&arg, args.next()) };
+            }
+            "filter" => {
+                let csv = expect_value(&arg, args.next());
+                cli.command = Command::Filter { csv, out: expect_value(&arg, args.next()) };
+            }
+            "--min-value" => cli.value_range.min = Some(parse_value(&arg, &expect_value(&arg, args.next()))),
+            "--max-value" => cli.value_range.max = Some(parse_value(&arg, &expect_value(&arg, args.next()))),
+            "prove" => {
+                let code = expect_value(&arg, args.next());
+                cli.command = Command::Prove { code, csv: expect_value(&arg, args.next()) };
+            }
+            "fund" => cli.command = Command::Fund { csv: expect_value(&arg, args.next()) },
+            "plan-diff" => cli.command = Command::PlanDiff { csv: expect_value(&arg, args.next()) },
+            "tune" => cli.command = Command::Tune { target_ms: DEFAULT_TUNE_TARGET_MS },
+            "check-config" => cli.command = Command::CheckConfig,
+            "reserve" => {
+                let path = expect_value(&arg, args.next());
+                cli.command = Command::Reserve { path, count: parse_value(&arg, &expect_value(&arg, args.next())) };
+            }
+            "draw" => {
+                let path = expect_value(&arg, args.next());
+                let count = parse_value(&arg, &expect_value(&arg, args.next()));
+                cli.command = Command::Draw { path, count, value: parse_value(&arg, &expect_value(&arg, args.next())) };
+            }
+            "--target-ms" => {
+                let target_ms = parse_value(&arg, &expect_value(&arg, args.next()));
+                match cli.command {
+                    Command::Tune { .. } => cli.command = Command::Tune { target_ms },
+                    _ => {
+                        eprintln!("{}", style::usage_error("\"--target-ms\" is only valid after \"tune\""));
+                        std::process::exit(1);
+                    }
+                }
+            }
+            "--mainnet-guard" => cli.mainnet_guard = Some(parse_value(&arg, &expect_value(&arg, args.next()))),
+            "--confirm-wallet" => cli.confirm_wallet = true,
+            "--confirm-threshold" => cli.confirm_threshold = Some(parse_value(&arg, &expect_value(&arg, args.next()))),
+            "--vanity" => cli.vanity = Some(expect_value(&arg, args.next())),
+            "--rpc-retries" => cli.rpc_retries = Some(parse_value(&arg, &expect_value(&arg, args.next()))),
+            "--rpc-retry-delay" => cli.rpc_retry_delay = Some(parse_value(&arg, &expect_value(&arg, args.next()))),
+            "--rpc-timeout" => cli.rpc_timeout = Some(parse_value(&arg, &expect_value(&arg, args.next()))),
+            "--max-fill-attempts" => cli.max_fill_attempts = Some(parse_value(&arg, &expect_value(&arg, args.next()))),
+            #[cfg(feature = "simulate-failures")]
+            "--simulate-failures" => {
+                let rate: f64 = parse_value(&arg, &expect_value(&arg, args.next()));
+                if !(0.0..=1.0).contains(&rate) {
+                    eprintln!("{}", style::usage_error("The simulated failure rate must be between 0 and 1"));
+                    std::process::exit(1);
+                }
+                cli.simulate_failures = Some(rate);
+            }
+            #[cfg(feature = "regtest-tests")]
+            "--version-bytes" => {
+                let value = expect_value(&arg, args.next());
+                let Some((pub_byte, priv_byte)) = value.split_once(',') else {
+                    eprintln!("{}", style::usage_error("--version-bytes expects \"<public>,<private>\", e.g. \"139,239\""));
+                    std::process::exit(1);
+                };
+                cli.version_bytes = Some((parse_value(&arg, pub_byte.trim()), parse_value(&arg, priv_byte.trim())));
+            }
+            "--threads" => cli.threads = Some(parse_value(&arg, &expect_value(&arg, args.next()))),
+            "--template" => cli.template = Some(expect_value(&arg, args.next())),
+            "--save-template" => cli.save_template = Some(expect_value(&arg, args.next())),
+            "--on-failure" => {
+                cli.on_failure = match expect_value(&arg, args.next()).as_str() {
+                    "retry" => FailurePolicy::Retry,
+                    "prompt" => FailurePolicy::Prompt,
+                    other => {
+                        eprintln!("{}", style::usage_error(format!("Unknown failure policy \"{}\", expected \"retry\" or \"prompt\"", other)));
+                        std::process::exit(1);
+                    }
+                }
+            }
+            "--on-failure-fallback" => {
+                cli.on_failure_fallback = match expect_value(&arg, args.next()).as_str() {
+                    "retry" => FailureAction::Retry,
+                    "skip" => FailureAction::Skip,
+                    "abort" => FailureAction::Abort,
+                    other => {
+                        eprintln!("{}", style::usage_error(format!("Unknown failure action \"{}\", expected \"retry\", \"skip\" or \"abort\"", other)));
+                        std::process::exit(1);
+                    }
+                }
+            }
+            "--log-file" => cli.log_file = Some(expect_value(&arg, args.next())),
+            "--coin" => cli.coin = Some(expect_value(&arg, args.next())),
+            "--output-dir" => cli.output_dir = Some(expect_value(&arg, args.next())),
+            "--output-format" => {
+                cli.output_format = match expect_value(&arg, args.next()).as_str() {
+                    "csv" => OutputFormat::Csv,
+                    "json" => OutputFormat::Json,
+                    "jsonl" => OutputFormat::Jsonl,
+                    other => {
+                        eprintln!("{}", style::usage_error(format!("Unknown output format \"{}\", expected \"csv\", \"json\" or \"jsonl\"", other)));
+                        std::process::exit(1);
+                    }
+                }
+            }
+            "--fee" => cli.fee = Some(parse_value(&arg, &expect_value(&arg, args.next()))),
+            "--fiat-rate" => cli.fiat_rate = Some(parse_value(&arg, &expect_value(&arg, args.next()))),
+            "--fiat-currency" => cli.fiat_currency = expect_value(&arg, args.next()),
+            "--fiat-values" => cli.fiat_values = true,
+            "--budget" => cli.budget = Some(parse_value(&arg, &expect_value(&arg, args.next()))),
+            _ => eprintln!("{}", style::warning(format!("Unknown argument: \"{}\" - ignoring", arg))),
+        }
+    }
+    if cli.fiat_rate.is_some_and(|rate| !rate.is_finite() || rate <= 0.0) {
+        eprintln!("{}", style::usage_error("The fiat rate must be above zero"));
+        std::process::exit(1);
+    }
+    // The check character is drawn from the alphanumeric charset, which would break a numeric-only code
+    if cli.numeric && cli.check_char {
+        eprintln!("{}", style::usage_error("\"--check-char\" can't be used with \"--numeric\", as check characters may be letters"));
+        std::process::exit(1);
+    }
+    if let (Some(min), Some(max)) = (cli.value_range.min, cli.value_range.max) {
+        if min > max {
+            eprintln!("{}", style::usage_error(format!("\"--min-value\" ({}) is above \"--max-value\" ({})", min, max)));
+            std::process::exit(1);
+        }
+    }
+    if matches!(cli.command, Command::Filter { .. }) && !cli.value_range.is_set() {
+        eprintln!("{}", style::usage_error("\"filter\" needs a \"--min-value\" and/or \"--max-value\" to filter by"));
+        std::process::exit(1);
+    }
+    if matches!(cli.command, Command::Reserve { count: 0, .. } | Command::Draw { count: 0, .. }) {
+        eprintln!("{}", style::usage_error("At least 1 code must be reserved or drawn"));
+        std::process::exit(1);
+    }
+    if let Command::Draw { value, .. } = cli.command {
+        if !value.is_finite() || value <= 0.0 {
+            eprintln!("{}", style::usage_error("Drawn codes must be funded with a value above zero"));
+            std::process::exit(1);
+        }
+    }
+    if cli.rpc_timeout == Some(0) {
+        eprintln!("{}", style::usage_error("The RPC timeout must be above zero"));
+        std::process::exit(1);
+    }
+    if cli.poll_interval == Some(0) {
+        eprintln!("{}", style::usage_error("The poll interval must be at least 1 second"));
+        std::process::exit(1);
+    }
+    if cli.poll_interval.is_some() && cli.wait_confirmations.is_none() {
+        eprintln!("{}", style::usage_error("\"--poll-interval\" only applies while waiting, with \"--wait-confirmations\""));
+        std::process::exit(1);
+    }
+    if cli.budget.is_some_and(|budget| !budget.is_finite() || budget <= 0.0) {
+        eprintln!("{}", style::usage_error("The budget must be above zero"));
+        std::process::exit(1);
+    }
+    if cli.fiat_values && cli.fiat_rate.is_none() {
+        eprintln!("{}", style::usage_error("\"--fiat-values\" needs a rate to convert at, from \"--fiat-rate\""));
+        std::process::exit(1);
+    }
+    if cli.code_length == Some(0) {
+        eprintln!("{}", style::usage_error("The code length must be at least 1"));
+        std::process::exit(1);
+    }
+    if cli.subtract_fee && (cli.sendmany || cli.min_conf.is_some()) {
+        eprintln!("{}", style::usage_error("\"--subtract-fee\" can't be combined with \"--sendmany\" or \"--min-conf\""));
+        std::process::exit(1);
+    }
+    if cli.vanity.is_some() && cli.checkpoint.is_some() {
+        eprintln!("{}", style::usage_error("\"--vanity\" can't be combined with \"--checkpoint\", as parallel searches can't share a checkpoint"));
+        std::process::exit(1);
+    }
+    if cli.derivation == DerivationScheme::V2 && cli.checkpoint.is_some() {
+        eprintln!("{}", style::usage_error("\"--derivation v2\" can't be combined with \"--checkpoint\", as v2 codes finish long before their first checkpoint"));
+        std::process::exit(1);
+    }
+    if cli.no_fill && (cli.watch_only || cli.check_coin_params || matches!(cli.command, Command::Fund { .. } | Command::Draw { .. })) {
+        eprintln!("{}", style::usage_error("\"--no-fill\" never contacts the daemon, so it can't be combined with \"--watch-only\", \"--check-coin-params\", \"fund\" or \"draw\""));
+        std::process::exit(1);
+    }
+    if cli.gzip && cli.output_format == OutputFormat::Json {
+        eprintln!("{}", style::usage_error("\"--gzip\" can only stream appended rows, so it needs \"--output-format csv\" or \"jsonl\""));
+        std::process::exit(1);
+    }
+    if cli.wif_qr_dir.is_some() && cli.wif_qr_dir == cli.qr_dir {
+        eprintln!("{}", style::usage_error("\"--wif-qr-dir\" must be a separate directory from \"--qr-dir\", so secrets are never mixed in with public QRs"));
+        std::process::exit(1);
+    }
+    if cli.resume && (cli.command != Command::Run || cli.plan_only || cli.template.is_some()) {
+        eprintln!("{}", style::usage_error("\"--continue\" picks up an interrupted run's plan, so it can't be combined with other commands, \"--plan-only\" or \"--template\""));
+        std::process::exit(1);
+    }
+    if cli.resume && (cli.gzip || cli.csv_out.is_some() || cli.json_out.is_some() || cli.wif_vault.is_some()) {
+        eprintln!("{}", style::usage_error("\"--continue\" can only append to the interrupted run's save file, not \"--gzip\", \"--csv\", \"--json\" or \"--wif-vault\" outputs"));
+        std::process::exit(1);
+    }
+    if cli.namespace.as_deref() == Some("") {
+        eprintln!("{}", style::usage_error("The namespace can't be empty"));
+        std::process::exit(1);
+    }
+    if cli.threads == Some(0) {
+        eprintln!("{}", style::usage_error("At least 1 thread is needed"));
+        std::process::exit(1);
+    }
+    if cli.strict && cli.max_fill_attempts.is_some() {
+        eprintln!("{}", style::usage_error("\"--strict\" never retries, so it can't be combined with \"--max-fill-attempts\""));
+        std::process::exit(1);
+    }
+    if cli.on_failure == FailurePolicy::Prompt && (cli.strict || cli.max_fill_attempts.is_some()) {
+        eprintln!("{}", style::usage_error("\"--on-failure prompt\" decides when to give up by asking, so it can't be combined with \"--strict\" or \"--max-fill-attempts\""));
+        std::process::exit(1);
+    }
+    if cli.max_fill_attempts == Some(0) {
+        eprintln!("{}", style::usage_error("The maximum fill attempts must be at least 1"));
+        std::process::exit(1);
+    }
+    if cli.max_outputs == 0 {
+        eprintln!("{}", style::usage_error("The maximum outputs per TX must be at least 1"));
+        std::process::exit(1);
+    }
+    if cli.fee.is_some_and(|fee| fee < 0.0) {
+        eprintln!("{}", style::usage_error("The fee can't be negative"));
+        std::process::exit(1);
+    }
+    cli
+}
+
+/// Parses an option's value, exiting with a clear message if it's not valid for its type
+pub fn parse_value<T: FromStr>(name: &str, value: &str) -> T {
+    match value.parse() {
+        Ok(parsed) => parsed,
+        Err(_) => {
+            eprintln!("{}", style::usage_error(format!("Invalid value \"{}\" for \"{}\"", value, name)));
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Unwraps the value following a flag, exiting with a clear message if it's missing
+fn expect_value(flag: &str, value: Option<String>) -> String {
+    match value {
+        Some(value) => value,
+        None => {
+            eprintln!("{}", style::usage_error(format!("Missing value for \"{}\"", flag)));
+            std::process::exit(1);
+        }
+    }
+}
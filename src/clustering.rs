@@ -0,0 +1,90 @@
+use serde_json::json;
+
+use crate::{rpc::ReconnectingRpc, style};
+
+/// The wallet's change outputs from this run's fills, locked so later fills can't spend them.
+///
+/// Without coin control, a wallet funds each fill from the previous fill's change, chaining every
+/// promo address into one trivially-linkable cluster on-chain. Locking each fill's change (with
+/// `lockunspent`) forces the next fill onto a different UTXO, for as long as the wallet has any.
+///
+/// Locks only live in the daemon's memory, so a restarted daemon forgets them on its own.
+///
+#[derive(Default)]
+pub struct ChangeLocks {
+    /// The locked outputs, as `(txid, vout)`
+    locked: Vec<(String, u32)>,
+}
+
+impl ChangeLocks {
+    /// Whether any outputs are currently locked
+    pub fn is_empty(&self) -> bool {
+        self.locked.is_empty()
+    }
+
+    /// Locks every wallet output of a fill TX (its change), so later fills are funded from other UTXOs.
+    ///
+    /// Failures are only warned about, as the fill itself has already succeeded.
+    ///
+    pub fn lock_change(&mut self, rpc: &mut ReconnectingRpc, tx_id: &str) {
+        let unspent = match rpc.call(|c| c.listunspent(Some(0), None, None)) {
+            Ok(unspent) => unspent,
+            Err(e) => {
+                eprintln!(
+                    "{}",
+                    style::warning(format!(" - Couldn't list the wallet's UTXOs to lock the change: {}", e))
+                );
+                return;
+            }
+        };
+        let change: Vec<(String, u32)> = unspent
+            .into_iter()
+            .filter(|utxo| utxo.txid.to_string() == tx_id)
+            .map(|utxo| (utxo.txid.to_string(), utxo.vout))
+            .collect();
+        if change.is_empty() {
+            return;
+        }
+
+        let outputs = json!(change.iter().map(|(txid, vout)| json!({ "txid": txid, "vout": vout })).collect::<Vec<_>>());
+        match rpc.call(|c| c.lockunspent(false, Some(outputs))) {
+            Ok(_) => {
+                humanln!(" - Locked {} change output(s), the next fill will use a different UTXO", change.len());
+                self.locked.extend(change);
+            }
+            Err(e) => eprintln!(
+                "{}",
+                style::warning(format!(" - Couldn't lock the change of {}: {}", tx_id, e))
+            ),
+        }
+    }
+
+    /// Unlocks every output locked this run, so the wallet can spend them again
+    pub fn unlock_all(&mut self, rpc: &mut ReconnectingRpc) {
+        if self.locked.is_empty() {
+            return;
+        }
+        let outputs = json!(self
+            .locked
+            .iter()
+            .map(|(txid, vout)| json!({ "txid": txid, "vout": vout }))
+            .collect::<Vec<_>>());
+        match rpc.call(|c| c.lockunspent(true, Some(outputs))) {
+            Ok(_) => humanln!("Unlocked {} change output(s)", self.locked.len()),
+            Err(e) => eprintln!(
+                "{}",
+                style::warning(format!(
+                    "Couldn't unlock this run's change outputs ({}), they'll unlock when the daemon restarts (or via `lockunspent true`)",
+                    e
+                ))
+            ),
+        }
+        // Either way, they're no longer ours to track (so a failed unlock is never retried in a loop)
+        self.locked.clear();
+    }
+}
+
+/// Whether an RPC error means the wallet doesn't have enough spendable coins for a send
+pub fn is_insufficient_funds(error: &str) -> bool {
+    error.to_lowercase().contains("insufficient funds")
+}
@@ -0,0 +1,146 @@
+use crate::{get_rand_chars, MAP_ALPHANUMERIC};
+
+/// The charset of numeric-only codes, for systems (such as scratch cards) that can't take letters
+pub const MAP_NUMERIC: &str = "0123456789";
+
+/// The shape of the promo codes being generated.
+///
+/// This covers everything needed to assemble a code string; the key derivation itself always hashes
+/// the full, final code.
+///
+pub struct CodeFormat {
+    /// The prefix placed before the random characters (joined with a '-'), or empty for none
+    pub prefix: String,
+    /// The number of random characters per code
+    pub length: usize,
+    /// Whether to append a Luhn mod N check character, for typo detection by redeemers
+    pub check_char: bool,
+    /// The characters the random part is drawn from (`MAP_ALPHANUMERIC`, or `MAP_NUMERIC`)
+    pub charset: &'static str,
+}
+
+impl CodeFormat {
+    /// Assembles a new random code in this format
+    pub fn generate(&self) -> String {
+        let mut code = if self.prefix.is_empty() {
+            get_rand_chars(self.charset, self.length)
+        } else {
+            self.prefix.to_owned() + "-" + &get_rand_chars(self.charset, self.length)
+        };
+        if self.check_char {
+            code.push(luhn_check_char(&code));
+        }
+        code
+    }
+
+    /// Summarises the format (prefix, length, check character and charset), so a checkpointed code
+    /// is only resumed by a run generating codes in the same format
+    pub fn fingerprint(&self) -> String {
+        format!("{}:{}:{}:{}", self.prefix, self.length, self.check_char, self.charset)
+    }
+}
+
+/// Computes a Luhn mod N check character over the alphanumeric charset.
+///
+/// Only characters within `MAP_ALPHANUMERIC` contribute (the '-' after a prefix is skipped), which
+/// catches any single mistyped character, and most swaps of adjacent characters. `validate_prefix`
+/// keeps every other character of a checked code within it.
+///
+/// # Arguments
+///
+/// * `input` - The code to compute the check character for.
+///
+/// # Returns
+///
+/// The check character, itself from `MAP_ALPHANUMERIC`.
+///
+pub fn luhn_check_char(input: &str) -> char {
+    let n = MAP_ALPHANUMERIC.len();
+    let mut factor = 2;
+    let mut sum = 0;
+
+    // Walk right-to-left, doubling every other code point and summing its base-N digits
+    for c in input.chars().rev() {
+        let code_point = match MAP_ALPHANUMERIC.find(c) {
+            Some(point) => point,
+            None => continue,
+        };
+        let addend = factor * code_point;
+        sum += addend / n + addend % n;
+        factor = if factor == 2 { 1 } else { 2 };
+    }
+
+    let check = (n - sum % n) % n;
+    MAP_ALPHANUMERIC.chars().nth(check).unwrap()
+}
+
+/// Checks that a code's final character is the correct Luhn mod N check character for the rest.
+///
+/// This is what a redeemer would run before attempting a (slow) derivation.
+///
+pub fn verify_check_char(code: &str) -> bool {
+    match code.chars().last() {
+        Some(check) => luhn_check_char(&code[..code.len() - check.len_utf8()]) == check,
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::validate_prefix;
+
+    #[test]
+    fn check_char_matches_known_vectors() {
+        assert_eq!(luhn_check_char("abc"), '5');
+        assert_eq!(luhn_check_char("xyz789"), 'H');
+        // The '-' after a prefix is skipped, not counted
+        assert_eq!(luhn_check_char("PIV-xyz789"), 'w');
+        assert!(verify_check_char("PIV-xyz789w"));
+        assert!(!verify_check_char(""));
+    }
+
+    #[test]
+    fn check_char_catches_any_single_substitution() {
+        let code = "PIV-xyz789w";
+        for (i, original) in code.char_indices().filter(|(_, c)| *c != '-') {
+            for replacement in MAP_ALPHANUMERIC.chars().filter(|c| *c != original) {
+                let typo = format!("{}{}{}", &code[..i], replacement, &code[i + 1..]);
+                assert!(!verify_check_char(&typo), "{} passed the check", typo);
+            }
+        }
+    }
+
+    #[test]
+    fn check_char_catches_adjacent_swaps_in_the_random_part() {
+        let code = "PIV-xyz789w";
+        for i in 4..code.len() - 1 {
+            let swapped = format!("{}{}{}{}", &code[..i], &code[i + 1..i + 2], &code[i..i + 1], &code[i + 2..]);
+            assert!(!verify_check_char(&swapped), "{} passed the check", swapped);
+        }
+    }
+
+    #[test]
+    fn generated_codes_pass_their_own_check() {
+        let format = CodeFormat {
+            prefix: "PIV".to_string(),
+            length: 10,
+            check_char: true,
+            charset: MAP_ALPHANUMERIC,
+        };
+        for _ in 0..50 {
+            assert!(verify_check_char(&format.generate()));
+        }
+    }
+
+    #[test]
+    fn checked_prefixes_must_stay_within_the_charset() {
+        assert!(validate_prefix("PIV", true).is_ok());
+        assert!(validate_prefix("", true).is_ok());
+        // '0', 'O', 'I', 'l', '_' and an inner '-' are invisible to the check character
+        for prefix in ["PIV0", "PROMO", "PIVI", "lucky", "PIV_X", "PIV-X"] {
+            assert!(validate_prefix(prefix, true).is_err(), "{} was accepted", prefix);
+            assert!(validate_prefix(prefix, false).is_ok(), "{} was rejected", prefix);
+        }
+    }
+}
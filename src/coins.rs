@@ -1,3 +1,6 @@
+use base58::ToBase58;
+use serde_json::{json, Value};
+
 /// Struct that represents a cryptocurrency's parameters
 #[derive(Clone, Debug)]
 pub struct CoinParams {
@@ -15,13 +18,97 @@ pub struct CoinParams {
     pub pub_key_byte: u8,
     /// Private key version byte for WIF format
     pub priv_key_byte: u8,
+    /// Whether the coin's wallets use compressed public keys, which decides both its addresses and
+    /// the compression flag byte its WIFs end with
+    pub compressed: bool,
+    /// The number of decimal places the coin supports (e.g. 8, for satoshis)
+    pub decimals: u32,
     /// The network fee paid for the redeemer client
     pub promo_fee: f64,
+    /// The value at or below which an output costs more to spend than it's worth (as in Bitcoin
+    /// Core: three times the fee of spending it, at the minimum relay fee)
+    pub dust_threshold: f64,
+    /// The oldest daemon version (as reported by `getnetworkinfo`) known to support Batcher's RPC calls, if known
+    pub min_daemon_version: Option<u64>,
+    /// The string every address of this coin starts with, asserted for each generated address as a
+    /// safety check on `pub_key_byte`
+    pub expected_address_prefix: Option<String>,
+    /// A block explorer's URL for a TX, with `{txid}` in place of the TX ID, if known
+    pub explorer_tx_url: Option<String>,
+    /// The scheme of the coin's payment URIs (e.g. "pivx", for `pivx:<address>?amount=<value>`), if known
+    pub uri_scheme: Option<String>,
+    /// The daemon's own RPC method for sending to a single address, if it doesn't use `sendtoaddress`
+    pub send_rpc: Option<SendRpc>,
+}
+
+/// The arguments a send RPC method takes, in order
+#[derive(Clone, Copy, Debug)]
+pub enum SendArgs {
+    /// `(address, amount)`, like `sendtoaddress`
+    AddressAmount,
+    /// `(account, address, amount)`, sending from the default ("") account, like `sendfrom`
+    AccountAddressAmount,
+    /// `({address: amount})`, like `sendmany` without a from-account
+    Outputs,
+}
+
+/// A send RPC method that differs from `sendtoaddress`, for forks with a divergent RPC surface.
+///
+/// Whatever its shape, the method must return the TX ID of the send.
+///
+#[derive(Clone, Debug)]
+pub struct SendRpc {
+    /// The method's name
+    pub method: String,
+    /// The arguments it takes
+    pub args: SendArgs,
+}
+
+impl SendRpc {
+    /// Builds the method's parameters for sending an amount to an address
+    pub fn params(&self, address: &str, amount: f64) -> Value {
+        match self.args {
+            SendArgs::AddressAmount => json!([address, amount]),
+            SendArgs::AccountAddressAmount => json!(["", address, amount]),
+            SendArgs::Outputs => json!([{ address: amount }]),
+        }
+    }
 }
 
-/// Get a list of all supported coins
+impl CoinParams {
+    /// Checks that an address starts with this coin's expected prefix (if one is declared).
+    ///
+    /// A mismatch means `pub_key_byte` is wrong, and funding the address could lose coins.
+    ///
+    pub fn check_address(&self, address: &str) -> Result<(), String> {
+        match self.expected_address_prefix {
+            Some(ref prefix) if !address.starts_with(prefix.as_str()) => Err(format!(
+                "Address \"{}\" doesn't start with \"{}\", as expected for {}: check its pub_key_byte ({})",
+                address, prefix, self.name, self.pub_key_byte
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns the block explorer link for a TX, if the coin has a known explorer
+    pub fn explorer_link(&self, tx_id: &str) -> Option<String> {
+        self.explorer_tx_url.as_ref().map(|url| url.replace("{txid}", tx_id))
+    }
+
+    /// Returns a BIP21-style payment URI for an address (with the amount, if there is one), if the
+    /// coin has a known URI scheme
+    pub fn payment_uri(&self, address: &str, value: f64) -> Option<String> {
+        self.uri_scheme.as_ref().map(|scheme| match value > 0.0 {
+            true => format!("{}:{}?amount={:.*}", scheme, address, self.decimals as usize, value),
+            false => format!("{}:{}", scheme, address),
+        })
+    }
+}
+
+/// Get a list of all supported coins, sorted by ticker so the coin menu's numbering stays stable
+/// wherever new coins are added
 pub fn get_supported_coins() -> Vec<CoinParams> {
-    vec![
+    let mut coins = vec![
         CoinParams {
             name: "PIVX".to_string(),
             ticker: "PIV".to_string(),
@@ -30,7 +117,15 @@ pub fn get_supported_coins() -> Vec<CoinParams> {
             default_rpc_port: 51473,
             pub_key_byte: 30,
             priv_key_byte: 212,
+            compressed: true,
+            decimals: 8,
             promo_fee: 0.00010000,
+            dust_threshold: 0.00005460,
+            min_daemon_version: Some(5_000_000),
+            expected_address_prefix: Some("D".to_string()),
+            explorer_tx_url: Some("https://explorer.pivx.org/tx/{txid}".to_string()),
+            uri_scheme: Some("pivx".to_string()),
+            send_rpc: None,
         },
         CoinParams {
             name: "DogeCoin".to_string(),
@@ -40,7 +135,15 @@ pub fn get_supported_coins() -> Vec<CoinParams> {
             default_rpc_port: 22555,
             pub_key_byte: 30,
             priv_key_byte: 158,
+            compressed: true,
+            decimals: 8,
             promo_fee: 0.01000000,
+            dust_threshold: 0.01000000,
+            min_daemon_version: Some(1_140_000),
+            expected_address_prefix: Some("D".to_string()),
+            explorer_tx_url: Some("https://blockchair.com/dogecoin/transaction/{txid}".to_string()),
+            uri_scheme: Some("dogecoin".to_string()),
+            send_rpc: None,
         },
         CoinParams {
             name: "Metrix".to_string(),
@@ -50,7 +153,15 @@ pub fn get_supported_coins() -> Vec<CoinParams> {
             default_rpc_port: 33831,
             pub_key_byte: 50,
             priv_key_byte: 85,
+            compressed: true,
+            decimals: 8,
             promo_fee: 2.25000000,
+            dust_threshold: 0.00546000,
+            min_daemon_version: None,
+            expected_address_prefix: Some("M".to_string()),
+            explorer_tx_url: None,
+            uri_scheme: None,
+            send_rpc: None,
         },
         CoinParams {
             name: "PepeCoin".to_string(),
@@ -60,7 +171,15 @@ pub fn get_supported_coins() -> Vec<CoinParams> {
             default_rpc_port: 33873,
             pub_key_byte: 56,
             priv_key_byte: 158,
+            compressed: true,
+            decimals: 8,
             promo_fee: 0.01000000,
+            dust_threshold: 0.01000000,
+            min_daemon_version: None,
+            expected_address_prefix: Some("P".to_string()),
+            explorer_tx_url: None,
+            uri_scheme: None,
+            send_rpc: None,
         },
         CoinParams {
             name: "StakeCubeCoin".to_string(),
@@ -70,7 +189,15 @@ pub fn get_supported_coins() -> Vec<CoinParams> {
             default_rpc_port: 39999,
             pub_key_byte: 125,
             priv_key_byte: 253,
+            compressed: true,
+            decimals: 8,
             promo_fee: 0.00010000,
+            dust_threshold: 0.00005460,
+            min_daemon_version: None,
+            expected_address_prefix: Some("s".to_string()),
+            explorer_tx_url: None,
+            uri_scheme: None,
+            send_rpc: None,
         },
         CoinParams {
             name: "NewMNSCoin".to_string(),
@@ -80,7 +207,140 @@ pub fn get_supported_coins() -> Vec<CoinParams> {
             default_rpc_port: 14259,
             pub_key_byte: 53,
             priv_key_byte: 82,
+            compressed: true,
+            decimals: 8,
             promo_fee: 0.00010000,
+            dust_threshold: 0.00005460,
+            min_daemon_version: None,
+            expected_address_prefix: Some("N".to_string()),
+            explorer_tx_url: None,
+            uri_scheme: None,
+            send_rpc: None,
         },
-    ]
-}
\ No newline at end of file
+    ];
+    coins.sort_by(|a, b| a.ticker.cmp(&b.ticker));
+    coins
+}
+
+/// Finds a supported coin by its ticker (case-insensitive), if there's such a coin
+pub fn lookup_coin(ticker: &str) -> Option<CoinParams> {
+    get_supported_coins().into_iter().find(|coin| coin.ticker.eq_ignore_ascii_case(ticker))
+}
+
+/// The version bytes of every built-in coin, as `(ticker, pub_key_byte, priv_key_byte)`.
+///
+/// These are pinned separately from `get_supported_coins`, so that an accidental edit there (such as
+/// swapping a coin's two bytes) is caught at startup, rather than producing wrong WIFs and addresses.
+///
+const PINNED_VERSION_BYTES: [(&str, u8, u8); 6] = [
+    ("PIV", 30, 212),
+    ("DOGE", 30, 158),
+    ("MRX", 50, 85),
+    ("PEP", 56, 158),
+    ("SCC", 125, 253),
+    ("NMNSC", 53, 82),
+];
+
+/// Checks a coin table for suspicious configurations: duplicate tickers, identical version bytes,
+/// built-in coins whose bytes differ from the pinned table, and address prefixes that `pub_key_byte`
+/// can't actually produce.
+///
+/// # Returns
+///
+/// A description of every problem found (empty if the table looks sane).
+///
+pub fn check_coin_table(coins: &[CoinParams]) -> Vec<String> {
+    let mut problems = Vec::new();
+    for (i, coin) in coins.iter().enumerate() {
+        if coins[..i].iter().any(|other| other.ticker.eq_ignore_ascii_case(&coin.ticker)) {
+            problems.push(format!("{} is listed more than once", coin.ticker));
+        }
+        if coin.pub_key_byte == coin.priv_key_byte {
+            problems.push(format!(
+                "{}'s pub_key_byte and priv_key_byte are identical ({})",
+                coin.ticker, coin.pub_key_byte
+            ));
+        }
+        if let Some(&(_, pub_key_byte, priv_key_byte)) =
+            PINNED_VERSION_BYTES.iter().find(|(ticker, _, _)| *ticker == coin.ticker)
+        {
+            if (coin.pub_key_byte, coin.priv_key_byte) != (pub_key_byte, priv_key_byte) {
+                problems.push(format!(
+                    "{}'s version bytes ({}, {}) aren't the pinned ({}, {}){}",
+                    coin.ticker,
+                    coin.pub_key_byte,
+                    coin.priv_key_byte,
+                    pub_key_byte,
+                    priv_key_byte,
+                    if (coin.priv_key_byte, coin.pub_key_byte) == (pub_key_byte, priv_key_byte) {
+                        ", they look swapped"
+                    } else {
+                        ""
+                    }
+                ));
+            }
+        }
+
+        // The lowest and highest possible addresses bound what every address can start with
+        if let Some(ref prefix) = coin.expected_address_prefix {
+            let lowest = [&[coin.pub_key_byte][..], &[0x00; 24]].concat().to_base58();
+            let highest = [&[coin.pub_key_byte][..], &[0xff; 24]].concat().to_base58();
+            if !lowest.starts_with(prefix.as_str()) || !highest.starts_with(prefix.as_str()) {
+                problems.push(format!(
+                    "{}'s addresses (from pub_key_byte {}) can't all start with \"{}\"",
+                    coin.ticker, coin.pub_key_byte, prefix
+                ));
+            }
+        }
+    }
+    problems
+}
+
+/// Coins for other modules' tests to use
+#[cfg(test)]
+pub mod fixtures {
+    use super::*;
+
+    /// The built-in PIVX params
+    pub fn piv() -> CoinParams {
+        get_supported_coins().into_iter().find(|coin| coin.ticker == "PIV").unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{fixtures::piv, *};
+
+    #[test]
+    fn supported_coins_pass_the_table_check() {
+        assert_eq!(check_coin_table(&get_supported_coins()), Vec::<String>::new());
+    }
+
+    #[test]
+    fn swapped_version_bytes_are_caught() {
+        let piv = piv();
+        let swapped = CoinParams {
+            pub_key_byte: piv.priv_key_byte,
+            priv_key_byte: piv.pub_key_byte,
+            expected_address_prefix: None,
+            ..piv
+        };
+        let problems = check_coin_table(&[swapped]);
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].ends_with("they look swapped"), "{}", problems[0]);
+    }
+
+    #[test]
+    fn identical_version_bytes_are_caught() {
+        let piv = piv();
+        let identical = CoinParams {
+            ticker: String::from("TEST"),
+            priv_key_byte: piv.pub_key_byte,
+            ..piv
+        };
+        assert_eq!(
+            check_coin_table(&[identical]),
+            vec![String::from("TEST's pub_key_byte and priv_key_byte are identical (30)")]
+        );
+    }
+}
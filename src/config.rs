@@ -0,0 +1,125 @@
+use std::{env::home_dir, fs, path::PathBuf};
+
+use serde::Deserialize;
+
+use crate::cli::CliArgs;
+
+/// The name of Batcher's config file
+pub const CONFIG_FILE_NAME: &str = "batcher.toml";
+
+/// Default options, loaded from a `batcher.toml` config file.
+///
+/// Every field is optional; anything set here can be overridden by environment variables and
+/// command-line flags, in that order.
+///
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct FileConfig {
+    pub coin: Option<String>,
+    pub prefix: Option<String>,
+    pub output_dir: Option<String>,
+    pub fee: Option<f64>,
+    pub code_length: Option<usize>,
+    pub wallet: Option<String>,
+    pub no_clear: Option<bool>,
+    pub sign: Option<bool>,
+    pub confirm_threshold: Option<f64>,
+    pub mainnet_guard: Option<f64>,
+    pub confirm_wallet: Option<bool>,
+}
+
+/// Returns Batcher's platform config directory
+pub fn config_dir() -> PathBuf {
+    let mut conf_dir = home_dir().unwrap_or_default();
+    if cfg!(target_os = "windows") {
+        conf_dir.push("AppData\\Roaming\\Batcher");
+    } else if cfg!(target_os = "macos") {
+        conf_dir.push("Library/Application Support/Batcher/");
+    } else {
+        conf_dir.push(".config/batcher");
+    }
+    conf_dir
+}
+
+/// Returns the paths a config file is searched at, in order of priority
+pub fn config_paths() -> Vec<PathBuf> {
+    vec![PathBuf::from(CONFIG_FILE_NAME), config_dir().join(CONFIG_FILE_NAME)]
+}
+
+/// Loads the first config file found, exiting if it exists but can't be parsed
+pub fn load_file_config() -> FileConfig {
+    for path in config_paths() {
+        let contents = match fs::read_to_string(&path) {
+            Ok(c) => c,
+            Err(_) => continue,
+        };
+        return match toml::from_str(&contents) {
+            Ok(config) => {
+                humanln!("Loaded defaults from \"{}\"", path.display());
+                config
+            }
+            Err(e) => {
+                eprintln!("{}", crate::style::error(format!("Failed to parse \"{}\": {}", path.display(), e)));
+                std::process::exit(1);
+            }
+        };
+    }
+    FileConfig::default()
+}
+
+/// Applies a config file's defaults onto the options
+pub fn apply_file_config(cli: &mut CliArgs, config: FileConfig) {
+    cli.coin = config.coin.or(cli.coin.take());
+    cli.prefix = config.prefix.or(cli.prefix.take());
+    cli.output_dir = config.output_dir.or(cli.output_dir.take());
+    cli.fee = config.fee.or(cli.fee);
+    cli.code_length = config.code_length.or(cli.code_length);
+    cli.wallet = config.wallet.or(cli.wallet.take());
+    cli.no_clear = config.no_clear.unwrap_or(cli.no_clear);
+    cli.sign_codes = config.sign.unwrap_or(cli.sign_codes);
+    cli.confirm_threshold = config.confirm_threshold.or(cli.confirm_threshold);
+    cli.mainnet_guard = config.mainnet_guard.or(cli.mainnet_guard);
+    cli.confirm_wallet = config.confirm_wallet.unwrap_or(cli.confirm_wallet);
+}
+
+/// Applies any `BATCHER_*` environment variables onto the options
+pub fn apply_env(cli: &mut CliArgs) {
+    if let Ok(coin) = std::env::var("BATCHER_COIN") {
+        cli.coin = Some(coin);
+    }
+    if let Ok(prefix) = std::env::var("BATCHER_PREFIX") {
+        cli.prefix = Some(prefix);
+    }
+    if let Ok(output_dir) = std::env::var("BATCHER_OUTPUT_DIR") {
+        cli.output_dir = Some(output_dir);
+    }
+    if let Ok(fee) = std::env::var("BATCHER_FEE") {
+        cli.fee = Some(crate::cli::parse_value("BATCHER_FEE", &fee));
+    }
+    if let Ok(code_length) = std::env::var("BATCHER_CODE_LENGTH") {
+        cli.code_length = Some(crate::cli::parse_value("BATCHER_CODE_LENGTH", &code_length));
+    }
+    if let Ok(wallet) = std::env::var("BATCHER_WALLET") {
+        cli.wallet = Some(wallet);
+    }
+    if let Ok(threshold) = std::env::var("BATCHER_CONFIRM_THRESHOLD") {
+        cli.confirm_threshold = Some(crate::cli::parse_value("BATCHER_CONFIRM_THRESHOLD", &threshold));
+    }
+    if let Ok(guard) = std::env::var("BATCHER_MAINNET_GUARD") {
+        cli.mainnet_guard = Some(crate::cli::parse_value("BATCHER_MAINNET_GUARD", &guard));
+    }
+    if let Ok(confirm_wallet) = std::env::var("BATCHER_CONFIRM_WALLET") {
+        cli.confirm_wallet = is_truthy(&confirm_wallet);
+    }
+    if let Ok(no_clear) = std::env::var("BATCHER_NO_CLEAR") {
+        cli.no_clear = is_truthy(&no_clear);
+    }
+    if let Ok(sign) = std::env::var("BATCHER_SIGN") {
+        cli.sign_codes = is_truthy(&sign);
+    }
+}
+
+/// Interprets an environment variable as a boolean ("1", "true", "yes" and "y" are true)
+fn is_truthy(value: &str) -> bool {
+    matches!(value.to_ascii_lowercase().as_str(), "1" | "true" | "yes" | "y")
+}
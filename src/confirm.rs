@@ -0,0 +1,71 @@
+use std::{thread, time::Duration};
+
+use crate::{rpc::ReconnectingRpc, style};
+
+/// The default number of seconds between each check of the funding TXs' confirmations
+pub const DEFAULT_POLL_INTERVAL: u64 = 30;
+
+/// The longest the poll interval backs off to, while the daemon keeps reporting errors
+const MAX_POLL_INTERVAL: u64 = 600;
+
+/// Waits until every funding TX of a run has at least the given number of confirmations, so the
+/// codes are only handed out once their funds are settled.
+///
+/// Each TX is checked with `gettransaction` every `poll_interval` seconds. While the daemon reports
+/// errors, the interval doubles (up to `MAX_POLL_INTERVAL`) to go easy on a struggling daemon, and
+/// resets once it answers again. TXs the wallet reports as conflicted are given up on, with a warning.
+///
+/// # Arguments
+///
+/// * `rpc` - The daemon connection.
+/// * `tx_ids` - The run's funding TX IDs (duplicates, from grouped fills, are only checked once).
+/// * `confirmations` - The number of confirmations to wait for.
+/// * `poll_interval` - The number of seconds between checks.
+///
+pub fn wait_for_confirmations(rpc: &mut ReconnectingRpc, tx_ids: &[String], confirmations: u64, poll_interval: u64) {
+    let mut pending: Vec<&String> = tx_ids.iter().collect();
+    pending.sort();
+    pending.dedup();
+    let total = pending.len();
+    let mut interval = poll_interval;
+
+    humanln!("Waiting for {} funding TXs to reach {} confirmations...", total, confirmations);
+    loop {
+        let mut failed = false;
+        let mut still_pending = Vec::new();
+        for tx_id in pending {
+            let tx_confirmations = match rpc.call_raw("gettransaction", serde_json::json!([tx_id])) {
+                Ok(tx) => tx["confirmations"].as_i64().unwrap_or(0),
+                Err(e) => {
+                    eprintln!("{}", style::warning(format!(" - Couldn't check TX {}: {}", tx_id, e)));
+                    failed = true;
+                    still_pending.push(tx_id);
+                    continue;
+                }
+            };
+            if tx_confirmations < 0 {
+                eprintln!(
+                    "{}",
+                    style::warning(format!(" - TX {} conflicts with the chain and will never confirm, check its codes!", tx_id))
+                );
+            } else if (tx_confirmations as u64) < confirmations {
+                still_pending.push(tx_id);
+            }
+        }
+        pending = still_pending;
+
+        if pending.is_empty() {
+            humanln!("{}", style::success(format!("Every funding TX has {} or more confirmations!", confirmations)));
+            return;
+        }
+        interval = if failed { (interval * 2).min(MAX_POLL_INTERVAL.max(poll_interval)) } else { poll_interval };
+        humanln!(
+            " - {} of {} TXs confirmed, checking again in {}s{}",
+            total - pending.len(),
+            total,
+            interval,
+            if failed { " (backing off, as the daemon reported errors)" } else { "" }
+        );
+        thread::sleep(Duration::from_secs(interval));
+    }
+}
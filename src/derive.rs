@@ -0,0 +1,287 @@
+use std::fs;
+
+use bitcoin_hashes::{hex::FromHex, hex::ToHex};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    derive_key_bytes,
+    hash::{sha256, sha256_rounds},
+    code::CodeFormat,
+    style, PROMO_TARGETS,
+};
+
+/// How many SHA256 rounds pass between each checkpoint save
+pub const CHECKPOINT_INTERVAL: u64 = 1_000_000;
+
+/// The saved state of a code's in-progress derivation, so a crash doesn't lose millions of rounds
+#[derive(Serialize, Deserialize)]
+pub struct DerivationCheckpoint {
+    /// The code being derived
+    pub code: String,
+    /// The number of rounds completed so far
+    pub rounds: u64,
+    /// The intermediate hash after `rounds` rounds, as hex
+    pub state: String,
+    /// The fingerprint of the code's format (see `CodeFormat::fingerprint`), empty in older checkpoints
+    #[serde(default)]
+    pub format: String,
+}
+
+/// The bits of entropy v2 codes carry on top of v1 codes, as extra random characters (see
+/// `random_code_length`)
+pub const V2_EXTRA_BITS: u32 = 10;
+
+/// The SHA256 rounds applied per code by the v2 scheme, on top of the cached prefix state.
+///
+/// The prefix is usually public (it's printed on every code), so its cached state adds no secrecy:
+/// the random suffix is all an attacker has to guess. Each guess costs `2^V2_EXTRA_BITS` times less
+/// than under v1, but v2 codes have `V2_EXTRA_BITS` more bits of suffix to guess, so brute-forcing a
+/// v2 code costs at least as much as a v1 code of the default length: only honest derivation gets cheaper.
+///
+pub const V2_SUFFIX_ITERATIONS: u64 = PROMO_TARGETS[PROMO_TARGETS.len() - 1] >> V2_EXTRA_BITS;
+
+/// The versioned schemes used to derive a promo's key from its code.
+///
+/// Keys derived under different schemes are entirely different, so redeemers must use the same
+/// scheme that generated a code.
+///
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum DerivationScheme {
+    /// The original scheme: chained SHA256 over the full code, `PROMO_TARGETS` times
+    #[default]
+    V1,
+    /// The prefix-cached scheme: the prefix is derived once (with the full target), and each code
+    /// only iterates `V2_SUFFIX_ITERATIONS` times over SHA256(prefix state || suffix), with longer
+    /// codes making up for the cheaper iterations
+    V2,
+}
+
+impl DerivationScheme {
+    /// The scheme's version number, as recorded in outputs
+    pub fn version(&self) -> u8 {
+        match self {
+            DerivationScheme::V1 => 1,
+            DerivationScheme::V2 => 2,
+        }
+    }
+}
+
+/// Derives promo keys under a scheme, holding any state that's shared between codes.
+pub struct Deriver {
+    scheme: DerivationScheme,
+    /// The prefix (without the '-' separator) that the cached state was derived from
+    prefix: String,
+    /// The v2 prefix state, computed once up-front
+    prefix_state: Option<[u8; 32]>,
+    /// Where to periodically save in-progress derivations, if anywhere
+    checkpoint_path: Option<String>,
+    /// The fingerprint of the run's code format, saved with each checkpoint
+    checkpoint_format: String,
+    /// The run's namespace, mixed into every derivation so identical codes from other campaigns derive different keys
+    namespace: Option<String>,
+    /// Whether every key is derived a second time, and compared, before use
+    double_check: bool,
+}
+
+impl Deriver {
+    /// Prepares a deriver, precomputing the prefix state if the scheme needs it.
+    ///
+    /// For v2, this costs one full derivation, which is then saved on every code in the run.
+    ///
+    pub fn new(scheme: DerivationScheme, prefix: &str) -> Self {
+        let prefix_state = match scheme {
+            DerivationScheme::V1 => None,
+            DerivationScheme::V2 => Some(derive_key_bytes(prefix, *PROMO_TARGETS.last().unwrap())),
+        };
+        Deriver {
+            scheme,
+            prefix: prefix.to_owned(),
+            prefix_state,
+            checkpoint_path: None,
+            checkpoint_format: String::new(),
+            namespace: None,
+            double_check: false,
+        }
+    }
+
+    /// Mixes a namespace into every derivation, as `<code>#<namespace>` (or, for v2, `<suffix>#<namespace>`).
+    ///
+    /// The codes themselves stay clean, but their keys change completely: they can only be redeemed
+    /// by a redeemer that knows the namespace.
+    ///
+    pub fn with_namespace(mut self, namespace: &str) -> Self {
+        self.namespace = Some(namespace.to_owned());
+        self
+    }
+
+    /// The input a code is hashed from: the code, plus the namespace if there is one
+    fn namespaced(&self, code: &str) -> String {
+        match self.namespace {
+            Some(ref namespace) => format!("{}#{}", code, namespace),
+            None => code.to_owned(),
+        }
+    }
+
+    /// Enables checkpointing of v1 derivations to a file, resuming from it if one already exists (and
+    /// was saved by a run with the same code format)
+    pub fn with_checkpoint(mut self, path: &str, format: &CodeFormat) -> Self {
+        self.checkpoint_path = Some(path.to_owned());
+        self.checkpoint_format = format.fingerprint();
+        self
+    }
+
+    /// Returns the code of an unfinished derivation left behind by a previous run, if any.
+    ///
+    /// Deriving it again picks up from the saved state, rather than from scratch. A checkpoint saved
+    /// with another code format (a different prefix, length, check character or charset) is ignored,
+    /// so the run never issues a code that isn't in its own format.
+    ///
+    pub fn resumable_code(&self) -> Option<String> {
+        let checkpoint = load_checkpoint(self.checkpoint_path.as_deref()?)?;
+        if checkpoint.format != self.checkpoint_format {
+            humanln!(" - Ignoring the checkpoint, which was saved for codes in another format");
+            return None;
+        }
+
+        // Checkpoints hold the namespaced input, so only resume one from this namespace
+        match self.namespace {
+            Some(ref namespace) => checkpoint
+                .code
+                .strip_suffix(&format!("#{}", namespace))
+                .map(str::to_owned),
+            None => Some(checkpoint.code),
+        }
+    }
+
+    /// Derives every key twice, so a transient memory or hardware fault during the long hash loop is
+    /// caught before the key is funded. This roughly doubles derivation time.
+    pub fn with_double_check(mut self) -> Self {
+        self.double_check = true;
+        self
+    }
+
+    /// The iteration target codes are derived with: the latest of the `PROMO_TARGETS`, iterated in full
+    /// by v1 codes and by the v2 prefix
+    pub fn target(&self) -> u64 {
+        *PROMO_TARGETS.last().unwrap()
+    }
+
+    /// The SHA256 iterations each code costs to derive (after any one-off prefix precomputation)
+    pub fn iterations_per_code(&self) -> u64 {
+        match self.scheme {
+            DerivationScheme::V1 => self.target(),
+            DerivationScheme::V2 => V2_SUFFIX_ITERATIONS,
+        }
+    }
+
+    /// Whether keys should be derived twice
+    pub fn double_checks(&self) -> bool {
+        self.double_check
+    }
+
+    /// Re-derives the cached v2 prefix state from scratch, confirming it wasn't corrupted (v1 has none)
+    pub fn check_prefix_state(&self) -> bool {
+        match self.prefix_state {
+            None => true,
+            Some(prefix_state) => derive_key_bytes(&self.prefix, *PROMO_TARGETS.last().unwrap()) == prefix_state,
+        }
+    }
+
+    /// Derives a code's key bytes a second time, independently of `derive`: never from a checkpoint,
+    /// which would just repeat the first derivation's work
+    pub fn derive_again(&self, code: &str) -> [u8; 32] {
+        match self.prefix_state {
+            None => derive_key_bytes(&self.namespaced(code), self.target()),
+            Some(_) => self.derive(code),
+        }
+    }
+
+    /// The scheme this deriver uses
+    pub fn scheme(&self) -> DerivationScheme {
+        self.scheme
+    }
+
+    /// Derives the raw private key bytes for a full promo code (including its prefix)
+    pub fn derive(&self, code: &str) -> [u8; 32] {
+        match self.prefix_state {
+            None => match self.checkpoint_path {
+                Some(ref path) => {
+                    derive_with_checkpoints(&self.namespaced(code), self.target(), path, &self.checkpoint_format)
+                }
+                None => derive_key_bytes(&self.namespaced(code), self.target()),
+            },
+            Some(prefix_state) => {
+                let suffix = code
+                    .strip_prefix(&self.prefix)
+                    .and_then(|rest| rest.strip_prefix('-'))
+                    .unwrap_or(code);
+
+                // Seed with the cached prefix state and the suffix, then iterate over the result
+                let mut seed = prefix_state.to_vec();
+                seed.extend_from_slice(self.namespaced(suffix).as_bytes());
+                sha256_rounds(sha256(&seed), V2_SUFFIX_ITERATIONS - 1)
+            }
+        }
+    }
+}
+
+/// Loads a derivation checkpoint, if one exists and is readable
+pub fn load_checkpoint(path: &str) -> Option<DerivationCheckpoint> {
+    let contents = fs::read_to_string(path).ok()?;
+    serde_json::from_str(&contents).ok()
+}
+
+/// Derives a code's key bytes exactly like `derive_key_bytes`, but saves a checkpoint every
+/// `CHECKPOINT_INTERVAL` rounds, and resumes from an existing checkpoint of the same code.
+///
+/// The checkpoint is removed once the derivation completes.
+///
+/// # Arguments
+///
+/// * `code` - The full promo code.
+/// * `iterations` - The total number of SHA256 rounds (including the first hash of the code).
+/// * `path` - Where the checkpoint is saved.
+/// * `format` - The fingerprint of the code's format, saved with the checkpoint.
+///
+/// # Returns
+///
+/// The 32 derived key bytes.
+///
+pub fn derive_with_checkpoints(code: &str, iterations: u64, path: &str, format: &str) -> [u8; 32] {
+    // Resume from a checkpoint of this exact code (and format), otherwise start with the code's first hash
+    let resumed = load_checkpoint(path)
+        .filter(|checkpoint| checkpoint.code == code && checkpoint.format == format && checkpoint.rounds <= iterations)
+        .and_then(|checkpoint| Some((checkpoint.rounds, <[u8; 32]>::from_hex(&checkpoint.state).ok()?)));
+    let (mut rounds, mut promo_key) = match resumed {
+        Some((rounds, state)) => {
+            humanln!(" - Resuming '{}' from iteration {} of {}", style::code(code), rounds, iterations);
+            (rounds, state)
+        }
+        None => (1, sha256(code.as_bytes())),
+    };
+
+    while rounds < iterations {
+        promo_key = sha256(&promo_key);
+        rounds += 1;
+
+        if rounds % CHECKPOINT_INTERVAL == 0 {
+            let checkpoint = DerivationCheckpoint {
+                code: code.to_owned(),
+                rounds,
+                state: promo_key.to_hex(),
+                format: format.to_owned(),
+            };
+            // Written aside then renamed over the last one, so dying mid-write never corrupts it
+            let tmp_path = format!("{}.tmp", path);
+            let saved = fs::write(&tmp_path, serde_json::to_string(&checkpoint).unwrap())
+                .and_then(|_| fs::rename(&tmp_path, path));
+            if let Err(e) = saved {
+                eprintln!("{}", style::warning(format!(" - Failed to save checkpoint \"{}\": {}", path, e)));
+            }
+        }
+    }
+
+    // This code is done: a stale checkpoint must never be resumed
+    fs::remove_file(path).unwrap_or_default();
+    promo_key
+}
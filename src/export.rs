@@ -0,0 +1,228 @@
+use std::fs;
+
+use serde_json::{json, Value};
+
+use crate::{coins::CoinParams, secret_to_wif, OptimisedPromoKeypair};
+
+/// The characters allowed in output descriptors, in checksum order
+const DESCRIPTOR_INPUT_CHARSET: &str =
+    "0123456789()[],'/*abcdefgh@:$%{}IJKLMNOPQRSTUVWXYZ&+-.;<=>?!^_|~ijklmnopqrstuvwxyzABCDEFGH`#\"\\ ";
+
+/// The characters used for output descriptor checksums
+const DESCRIPTOR_CHECKSUM_CHARSET: &str = "qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+/// The wallet RPC format to export keys for
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+pub enum KeyExportFormat {
+    /// For legacy wallets, via `importmulti`
+    #[default]
+    ImportMulti,
+    /// For descriptor wallets, via `importdescriptors`
+    Descriptors,
+}
+
+/// Builds the wallet import requests for a set of promos, as expected by `importmulti` or
+/// `importdescriptors`.
+///
+/// Each key is labelled with its promo code, and timestamped at `timestamp` (the generation time),
+/// so the importing wallet only rescans from when the keys came to exist.
+///
+/// # Arguments
+///
+/// * `promos` - The promos whose keys should be exported.
+/// * `coin_params` - A reference to the CoinParams for the selected coin.
+/// * `format` - Which RPC the export is intended for.
+/// * `timestamp` - The UNIX time the keys were created.
+///
+/// # Returns
+///
+/// A JSON array of import requests, ready to pass as the RPC's first argument.
+///
+pub fn wallet_import_requests(
+    promos: &[OptimisedPromoKeypair],
+    coin_params: &CoinParams,
+    format: KeyExportFormat,
+    timestamp: u64,
+) -> Value {
+    let requests = promos
+        .iter()
+        .map(|promo| {
+            let wif = secret_to_wif(promo.private, coin_params.priv_key_byte, coin_params.compressed);
+            match format {
+                KeyExportFormat::ImportMulti => json!({
+                    "scriptPubKey": { "address": promo.public },
+                    "keys": [wif],
+                    "timestamp": timestamp,
+                    "label": promo.code,
+                }),
+                KeyExportFormat::Descriptors => {
+                    let desc = format!("pkh({})", wif);
+                    json!({
+                        "desc": format!("{}#{}", desc, descriptor_checksum(&desc)),
+                        "timestamp": timestamp,
+                        "label": promo.code,
+                    })
+                }
+            }
+        })
+        .collect();
+    Value::Array(requests)
+}
+
+/// Writes the wallet import requests for a set of promos to a JSON file
+pub fn export_wallet_keys(
+    path: &str,
+    promos: &[OptimisedPromoKeypair],
+    coin_params: &CoinParams,
+    format: KeyExportFormat,
+    timestamp: u64,
+) -> std::io::Result<()> {
+    let requests = wallet_import_requests(promos, coin_params, format, timestamp);
+    fs::write(path, serde_json::to_string_pretty(&requests)?)
+}
+
+/// The header of a redeemer export, in the exact order the redeemer reads its columns
+pub const REDEEMER_HEADER: &str = "coin,code,value";
+
+/// Writes the codes a redeemer can hand out, as a CSV of `coin,code,value`: the lowercase coin
+/// ticker, the full promo code, and the value it was funded with (to the coin's precision).
+///
+/// Only codes that hold (or, for generate-only runs, are meant to hold) a value are included, so
+/// every listed code is worth redeeming. Addresses, keys and TX IDs are left out, since the
+/// redeemer derives everything from the code itself.
+///
+/// # Arguments
+///
+/// * `path` - The file to write.
+/// * `promos` - The generated promos.
+/// * `coin_params` - A reference to the CoinParams for the selected coin.
+/// * `unfunded` - Whether codes were generated without funding, so are listed by their intended value.
+///
+/// # Returns
+///
+/// How many codes were exported.
+///
+pub fn export_redeemer_codes(
+    path: &str,
+    promos: &[OptimisedPromoKeypair],
+    coin_params: &CoinParams,
+    unfunded: bool,
+) -> std::io::Result<usize> {
+    let mut contents = format!("{}\n", REDEEMER_HEADER);
+    let mut count = 0;
+    for promo in promos.iter().filter(|promo| promo.value > 0.0 && (promo.tx_id.is_some() || unfunded)) {
+        contents.push_str(&format!(
+            "{},{},{:.*}\n",
+            coin_params.ticker.to_lowercase(),
+            promo.code,
+            coin_params.decimals as usize,
+            promo.value
+        ));
+        count += 1;
+    }
+    fs::write(path, contents)?;
+    Ok(count)
+}
+
+/// The header of a payment URI export
+pub const URI_HEADER: &str = "code,address,uri";
+
+/// Writes each funded code's payment URI (see `CoinParams::payment_uri`), as a CSV of
+/// `code,address,uri`, for auditors to scan or open each address straight into a wallet.
+///
+/// As with the redeemer export, only codes that hold (or, for generate-only runs, are meant to
+/// hold) a value are included.
+///
+/// # Arguments
+///
+/// * `path` - The file to write.
+/// * `promos` - The generated promos.
+/// * `coin_params` - A reference to the CoinParams for the selected coin, which must have a URI scheme.
+/// * `unfunded` - Whether codes were generated without funding, so are listed by their intended value.
+///
+/// # Returns
+///
+/// How many codes were exported.
+///
+pub fn export_payment_uris(
+    path: &str,
+    promos: &[OptimisedPromoKeypair],
+    coin_params: &CoinParams,
+    unfunded: bool,
+) -> std::io::Result<usize> {
+    let mut contents = format!("{}\n", URI_HEADER);
+    let mut count = 0;
+    for promo in promos.iter().filter(|promo| promo.value > 0.0 && (promo.tx_id.is_some() || unfunded)) {
+        let Some(uri) = coin_params.payment_uri(&promo.public, promo.value) else {
+            break;
+        };
+        contents.push_str(&format!("{},{},{}\n", promo.code, promo.public, uri));
+        count += 1;
+    }
+    fs::write(path, contents)?;
+    Ok(count)
+}
+
+/// The BCH-style polymod step used by output descriptor checksums
+fn descriptor_polymod(c: u64, val: u64) -> u64 {
+    let c0 = c >> 35;
+    let mut c = ((c & 0x7ffffffff) << 5) ^ val;
+    if c0 & 1 != 0 {
+        c ^= 0xf5dee51989;
+    }
+    if c0 & 2 != 0 {
+        c ^= 0xa9fdca3312;
+    }
+    if c0 & 4 != 0 {
+        c ^= 0x1bab10e32d;
+    }
+    if c0 & 8 != 0 {
+        c ^= 0x3706b1677a;
+    }
+    if c0 & 16 != 0 {
+        c ^= 0x644d626ffd;
+    }
+    c
+}
+
+/// Computes the 8-character checksum required by `importdescriptors` for a descriptor.
+///
+/// # Arguments
+///
+/// * `desc` - The descriptor, without any existing checksum.
+///
+/// # Returns
+///
+/// The checksum, to be appended after a '#'.
+///
+pub fn descriptor_checksum(desc: &str) -> String {
+    let mut c: u64 = 1;
+    let mut cls: u64 = 0;
+    let mut cls_count = 0;
+    for ch in desc.chars() {
+        // Every character in our descriptors (WIFs and "pkh()") is within the charset
+        let pos = DESCRIPTOR_INPUT_CHARSET.find(ch).unwrap_or(0) as u64;
+        c = descriptor_polymod(c, pos & 31);
+        cls = cls * 3 + (pos >> 5);
+        cls_count += 1;
+        if cls_count == 3 {
+            c = descriptor_polymod(c, cls);
+            cls = 0;
+            cls_count = 0;
+        }
+    }
+    if cls_count > 0 {
+        c = descriptor_polymod(c, cls);
+    }
+    for _ in 0..8 {
+        c = descriptor_polymod(c, 0);
+    }
+    c ^= 1;
+
+    (0..8)
+        .map(|j| {
+            let index = ((c >> (5 * (7 - j))) & 31) as usize;
+            DESCRIPTOR_CHECKSUM_CHARSET.as_bytes()[index] as char
+        })
+        .collect()
+}
@@ -0,0 +1,113 @@
+use serde::{Deserialize, Serialize};
+
+use crate::utc::{format_utc, now};
+
+/// The currency fiat equivalents are shown in, unless another is given
+pub const DEFAULT_FIAT_CURRENCY: &str = "USD";
+
+/// Where an operator-given rate came from, as shown with it and saved in the run's state
+pub const MANUAL_RATE_SOURCE: &str = "--fiat-rate";
+
+/// The price of one coin in a fiat currency, for reporting spend in the currency stakeholders care about.
+///
+/// Batcher never fetches prices itself: the rate is the one the operator passed, and is stamped with
+/// the time it was given, so every summary shows exactly which rate its fiat figures came from. The
+/// rate is saved with an interrupted run's state, so a continued run reports in the same rate.
+///
+#[derive(Clone, Serialize, Deserialize)]
+pub struct FiatRate {
+    /// The fiat currency code, uppercase (e.g. "USD")
+    pub currency: String,
+    /// The price of one coin, in the fiat currency
+    pub price: f64,
+    /// The UNIX time the rate was given
+    pub as_of: u64,
+    /// Where the rate came from (always `MANUAL_RATE_SOURCE`, since Batcher never fetches one)
+    pub source: String,
+}
+
+impl FiatRate {
+    /// Stamps an operator-given rate with the current time
+    pub fn new(currency: &str, price: f64) -> Self {
+        FiatRate {
+            currency: currency.to_uppercase(),
+            price,
+            as_of: now(),
+            source: String::from(MANUAL_RATE_SOURCE),
+        }
+    }
+
+    /// Converts an amount of coins into the fiat currency
+    pub fn convert(&self, coins: f64) -> f64 {
+        coins * self.price
+    }
+
+    /// Converts a fiat amount into coins, rounded half-up to the coin's precision (so an amount exactly
+    /// halfway between two of the coin's smallest units rounds up to the larger one).
+    ///
+    /// # Returns
+    ///
+    /// The rounded amount of coins, and the exact (unrounded) amount.
+    ///
+    pub fn to_coins(&self, amount: f64, decimals: u32) -> (f64, f64) {
+        let exact = amount / self.price;
+        let scale = 10f64.powi(decimals as i32);
+        ((exact * scale + 0.5).floor() / scale, exact)
+    }
+
+    /// Formats the fiat equivalent of an amount of coins, e.g. "12.50 USD"
+    pub fn format(&self, coins: f64) -> String {
+        format!("{:.2} {}", self.convert(coins), self.currency)
+    }
+
+    /// Describes the rate, where it came from and when it was given, e.g.
+    /// "1 PIV = 0.25 USD (from --fiat-rate, as of 2026-10-16 12:00:00 UTC)"
+    pub fn describe(&self, ticker: &str) -> String {
+        format!(
+            "1 {} = {} {} (from {}, as of {})",
+            ticker,
+            self.price,
+            self.currency,
+            self.source,
+            format_utc(self.as_of)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn to_coins_rounds_exact_halves_up() {
+        let rate = FiatRate::new("usd", 4.0);
+        // 0.125 and 0.625 are exact in binary, so only the rounding rule decides them
+        assert_eq!(rate.to_coins(0.5, 2), (0.13, 0.125));
+        assert_eq!(rate.to_coins(2.5, 2), (0.63, 0.625));
+    }
+
+    #[test]
+    fn to_coins_rounds_to_the_nearest_unit_otherwise() {
+        let rate = FiatRate::new("usd", 4.0);
+        assert_eq!(rate.to_coins(0.496, 2).0, 0.12);
+        assert_eq!(rate.to_coins(0.504, 2).0, 0.13);
+        let thirds = FiatRate::new("eur", 3.0);
+        assert_eq!(thirds.to_coins(1.0, 8).0, 0.33333333);
+        assert_eq!(thirds.to_coins(2.0, 8).0, 0.66666667);
+    }
+
+    #[test]
+    fn to_coins_keeps_whole_amounts_exact() {
+        let rate = FiatRate::new("usd", 0.25);
+        assert_eq!(rate.to_coins(10.0, 8), (40.0, 40.0));
+        assert_eq!(rate.to_coins(0.0, 8), (0.0, 0.0));
+    }
+
+    #[test]
+    fn new_rates_are_uppercased_and_labelled_manual() {
+        let rate = FiatRate::new("eur", 1.0);
+        assert_eq!(rate.currency, "EUR");
+        assert_eq!(rate.source, MANUAL_RATE_SOURCE);
+        assert_eq!(rate.format(12.5), "12.50 EUR");
+    }
+}
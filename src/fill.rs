@@ -0,0 +1,528 @@
+use std::{
+    cell::Cell,
+    collections::HashMap,
+    fmt::Display,
+    io::{self, IsTerminal},
+};
+
+use crate::{
+    clustering::{is_insufficient_funds, ChangeLocks},
+    coins::CoinParams,
+    rpc::ReconnectingRpc,
+    runlog::RunLog,
+    ask_string, style,
+    OptimisedPromoKeypair,
+};
+
+/// The default maximum number of outputs in a single `sendmany` TX, keeping it within relay limits
+pub const DEFAULT_MAX_OUTPUTS: usize = 100;
+
+/// What happens once a fill has failed
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+pub enum FailurePolicy {
+    /// Keep retrying (up to any maximum number of attempts)
+    #[default]
+    Retry,
+    /// Retry once automatically, then ask the operator what to do
+    Prompt,
+}
+
+/// What to do about a fill that keeps failing, as chosen by the operator
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+pub enum FailureAction {
+    /// Try the fill again
+    Retry,
+    /// Give up on the fill, leaving the code unfunded (and listed as failed)
+    #[default]
+    Skip,
+    /// Give up on the fill, and stop the whole run
+    Abort,
+}
+
+/// Options controlling how promos are funded
+#[derive(Default)]
+pub struct FillOptions {
+    /// Only spend coins with at least this many confirmations; fills use `sendmany` when set, since
+    /// `sendtoaddress` has no `minconf` parameter
+    pub min_conf: Option<u32>,
+    /// Send exactly the stated value (without the promo fee on top), taking the network fee out of
+    /// it via `subtractfeefromamount`; the promo then records what its address actually received
+    pub subtract_fee: bool,
+    /// The audit log every funding RPC call and its response is recorded to, if enabled
+    pub log: Option<RunLog>,
+    /// Give up on a fill after this many failed attempts, rather than retrying forever
+    pub max_attempts: Option<u32>,
+    /// The probability of each attempt failing on purpose, for testing error handling (only honoured with
+    /// the `simulate-failures` feature)
+    pub simulate_failures: Option<f64>,
+    /// Lock each fill's change, so consecutive fills are funded from different UTXOs (see `ChangeLocks`)
+    pub avoid_clustering: bool,
+    /// What happens once a fill has failed
+    pub on_failure: FailurePolicy,
+    /// What the prompt policy does when there's no terminal to ask at
+    pub fallback: FailureAction,
+    /// Set once the operator chooses to abort the run after a failed fill
+    pub aborted: Cell<bool>,
+}
+
+impl FillOptions {
+    /// Decides whether a fill that has failed this many times should be given up on, asking the
+    /// operator under the prompt policy.
+    ///
+    /// # Returns
+    ///
+    /// The action to take; `Abort` also marks the options as aborted, for the caller to stop the run.
+    ///
+    fn after_failure(&self, attempts: u32) -> FailureAction {
+        let action = match self.on_failure {
+            FailurePolicy::Retry if self.max_attempts.is_some_and(|max| attempts >= max) => FailureAction::Skip,
+            FailurePolicy::Retry => FailureAction::Retry,
+            // The first failure is retried automatically, as most are transient
+            FailurePolicy::Prompt if attempts < 2 => FailureAction::Retry,
+            FailurePolicy::Prompt if !io::stdin().is_terminal() => self.fallback,
+            FailurePolicy::Prompt => ask_failure_action(),
+        };
+        if action == FailureAction::Abort {
+            self.aborted.set(true);
+        }
+        action
+    }
+}
+
+/// Asks the operator what to do about a fill that keeps failing
+fn ask_failure_action() -> FailureAction {
+    loop {
+        match ask_string("The fill keeps failing: [r]etry, [s]kip this code, or [a]bort the run?", "r")
+            .trim()
+            .to_lowercase()
+            .as_str()
+        {
+            "r" | "retry" => return FailureAction::Retry,
+            "s" | "skip" => return FailureAction::Skip,
+            "a" | "abort" => return FailureAction::Abort,
+            other => eprintln!("{}", style::warning(format!("\"{}\" isn't one of r, s or a - try again!", other))),
+        }
+    }
+}
+
+/// How many of the wallet's most recent TXs are searched for a fill that errored but was sent anyway
+const SENT_TX_LOOKBACK: u32 = 100;
+
+/// The state fills share across a run: what the daemon supports, and which change is locked.
+#[derive(Default)]
+pub struct FillLedger {
+    /// Set once the daemon rejects `sendtoaddress`'s optional parameters, so later fills only pass
+    /// the address and amount
+    minimal_sendtoaddress: bool,
+    /// The change outputs locked to avoid clustering, when enabled
+    pub change_locks: ChangeLocks,
+}
+
+/// The result of a successful fill
+pub struct FillOutcome {
+    /// The TX ID which funded the address
+    pub tx_id: String,
+    /// The value the address holds for redemption (excluding any promo fee)
+    pub value: f64,
+}
+
+/// Fills a single promo with `sendtoaddress`, retrying until it succeeds (or the attempt cap is reached).
+///
+/// Before retrying a failed attempt, the wallet is checked for a send to the address, so an attempt that
+/// errored after being broadcast is never sent twice (see `find_sent_tx`).
+///
+/// # Arguments
+///
+/// * `rpc` - The RPC client of the funding wallet.
+/// * `promo` - The promo to fill; its value and TX ID are set once funded.
+/// * `value` - The value the promo should hold, the coin's promo fee is added on top (unless subtracting fees).
+/// * `coin_params` - A reference to the CoinParams for the selected coin.
+/// * `options` - How the fill should be made.
+/// * `ledger` - The run's shared fill state.
+///
+/// # Returns
+///
+/// The last error, if the fill was given up on.
+///
+pub fn fill_promo(
+    rpc: &mut ReconnectingRpc,
+    promo: &mut OptimisedPromoKeypair,
+    value: f64,
+    coin_params: &CoinParams,
+    options: &FillOptions,
+    ledger: &mut FillLedger,
+) -> Result<(), String> {
+    let outcome = fill_address(rpc, &promo.public, value, coin_params, options, ledger)?;
+    promo.value = outcome.value;
+    promo.tx_id = Some(outcome.tx_id);
+    Ok(())
+}
+
+/// Fills any address with `sendtoaddress`, retrying until it succeeds (or the attempt cap is reached).
+///
+/// This is the core of `fill_promo`, usable for addresses whose codes aren't known (such as those
+/// generated on another machine).
+///
+/// # Arguments
+///
+/// * `rpc` - The RPC client of the funding wallet.
+/// * `address` - The address to fill.
+/// * `value` - The value the address should hold, the coin's promo fee is added on top (unless subtracting fees).
+/// * `coin_params` - A reference to the CoinParams for the selected coin.
+/// * `options` - How the fill should be made.
+/// * `ledger` - The run's shared fill state.
+///
+/// # Returns
+///
+/// The TX ID and the value the address holds, or the last error if the fill was given up on.
+///
+pub fn fill_address(
+    rpc: &mut ReconnectingRpc,
+    address: &str,
+    value: f64,
+    coin_params: &CoinParams,
+    options: &FillOptions,
+    ledger: &mut FillLedger,
+) -> Result<FillOutcome, String> {
+    // Fee-subtracted fills send exactly the stated value, otherwise the redeemer's fee is added on top
+    let amount = if options.subtract_fee {
+        value
+    } else {
+        value + coin_params.promo_fee
+    };
+    humanln!(" - Filling with {} {}...", amount, coin_params.ticker);
+
+    // Attempt filling the address
+    let comment = format!("{} Promos pre-fill", coin_params.name);
+    let mut attempts = 0;
+    // Set once an attempt has errored, as the daemon may have broadcast it before the error reached us
+    let mut maybe_sent = false;
+    loop {
+        if maybe_sent {
+            match find_sent_tx(rpc, &[address]) {
+                Ok(Some(tx_id)) => {
+                    humanln!("{}", style::success(format!(" - The failed attempt was sent after all, TX: {}", tx_id)));
+                    return Ok(fill_sent(rpc, tx_id, value, options, ledger));
+                }
+                Ok(None) => (),
+                // Sending again without knowing could fund the address twice, so keep checking instead
+                Err(e) => {
+                    attempts += 1;
+                    if options.after_failure(attempts) != FailureAction::Retry {
+                        eprintln!(
+                            "{}",
+                            style::error(format!(
+                                " - Couldn't check whether the failed attempt was sent (\"{}\"). Giving up after {} attempts!",
+                                e, attempts
+                            ))
+                        );
+                        return Err(e);
+                    }
+                    eprintln!(
+                        "{}",
+                        style::warning(format!(
+                            " - Couldn't check whether the failed attempt was sent (\"{}\"). Retrying in 10 seconds...",
+                            e
+                        ))
+                    );
+                    std::thread::sleep(std::time::Duration::from_secs(10));
+                    continue;
+                }
+            }
+        }
+        let (method, result) = match (options.min_conf, &coin_params.send_rpc) {
+            // Only `sendmany` can restrict which coins are spent by confirmations
+            (Some(min_conf), _) => {
+                let amounts = HashMap::from([(address.to_owned(), amount)]);
+                let result = attempt(options, || rpc.call(|c| c.sendmany("", amounts, Some(min_conf), Some(&comment))));
+                ("sendmany", result)
+            }
+            // Forks with their own send method get called by name, in the shape they declare
+            (None, Some(send)) => (
+                send.method.as_str(),
+                attempt(options, || {
+                    rpc.call_raw(&send.method, send.params(address, amount)).and_then(|result| {
+                        result
+                            .as_str()
+                            .map(str::to_owned)
+                            .ok_or_else(|| format!("{} returned no TX ID: {}", send.method, result))
+                    })
+                }),
+            ),
+            (None, None) if ledger.minimal_sendtoaddress => (
+                "sendtoaddress",
+                attempt(options, || rpc.call(|c| c.sendtoaddress(address, amount, None, None, None))),
+            ),
+            (None, None) => (
+                "sendtoaddress",
+                attempt(options, || {
+                    rpc.call(|c| {
+                        c.sendtoaddress(
+                            address,
+                            amount,
+                            Some(&comment),
+                            Some(""),
+                            Some(options.subtract_fee),
+                        )
+                    })
+                }),
+            ),
+        };
+        if let Some(ref log) = options.log {
+            log.record(method, &[(address, amount)], &result);
+        }
+        match result {
+            Ok(tx_id) => {
+                humanln!("{}", style::success(format!(" - TX: {}", tx_id)));
+                return Ok(fill_sent(rpc, tx_id, value, options, ledger));
+            }
+            // Older and alt daemons may not implement the full signature: fall back to just the address and
+            // amount, as long as nothing depends on the dropped parameters (`subtractfeefromamount` does)
+            Err(e)
+                if options.min_conf.is_none()
+                    && coin_params.send_rpc.is_none()
+                    && !options.subtract_fee
+                    && !ledger.minimal_sendtoaddress
+                    && is_signature_error(&e) =>
+            {
+                eprintln!(
+                    "{}",
+                    style::warning(format!(
+                        " - The daemon rejected sendtoaddress's optional parameters (\"{}\"), retrying with just the address and amount...",
+                        e
+                    ))
+                );
+                ledger.minimal_sendtoaddress = true;
+            }
+            // Every spare UTXO is already used: clustering can no longer be avoided, so spend the change after all
+            Err(e) if is_insufficient_funds(&e) && !ledger.change_locks.is_empty() => {
+                eprintln!(
+                    "{}",
+                    style::warning(" - No unlocked coins are left, unlocking this run's change to keep funding...")
+                );
+                ledger.change_locks.unlock_all(rpc);
+            }
+            Err(e) => {
+                attempts += 1;
+                maybe_sent = true;
+                if options.after_failure(attempts) != FailureAction::Retry {
+                    eprintln!(
+                        "{}",
+                        style::error(format!(" - TX failed with error: \"{}\". Giving up after {} attempts!", e, attempts))
+                    );
+                    return Err(e);
+                }
+                eprintln!(
+                    "{}",
+                    style::warning(format!(" - TX failed with error: \"{}\". Retrying in 10 seconds...", e))
+                );
+                std::thread::sleep(std::time::Duration::from_secs(10));
+            }
+        }
+    }
+}
+
+/// Finishes a fill once its TX is known: works out the value the address holds, and locks its change if
+/// avoiding clustering.
+fn fill_sent(rpc: &mut ReconnectingRpc, tx_id: String, value: f64, options: &FillOptions, ledger: &mut FillLedger) -> FillOutcome {
+    let value = if options.subtract_fee {
+        received_after_fee(rpc, &tx_id, value)
+    } else {
+        value
+    };
+    if options.avoid_clustering {
+        ledger.change_locks.lock_change(rpc, &tx_id);
+    }
+    FillOutcome { tx_id, value }
+}
+
+/// Searches the wallet's recent TXs for a send to any of the given addresses.
+///
+/// A funding call can error after the daemon has already broadcast its TX (a dropped connection or a
+/// timeout, for example), so this is checked before every retry: resending blindly could fund the
+/// same code twice.
+///
+/// # Returns
+///
+/// The TX ID of the most recent matching send, if any, or the error if the wallet couldn't be searched.
+///
+fn find_sent_tx(rpc: &mut ReconnectingRpc, addresses: &[&str]) -> Result<Option<String>, String> {
+    let txs = rpc.call_raw("listtransactions", serde_json::json!(["*", SENT_TX_LOOKBACK]))?;
+    let txs = txs.as_array().ok_or_else(|| format!("listtransactions returned a non-list: {}", txs))?;
+    Ok(txs
+        .iter()
+        .rev()
+        .find(|tx| tx["category"] == "send" && tx["address"].as_str().is_some_and(|address| addresses.contains(&address)))
+        .and_then(|tx| tx["txid"].as_str().map(str::to_owned)))
+}
+
+/// Makes a single funding RPC call, with its TX ID and error as strings.
+///
+/// With the `simulate-failures` feature, this is also where fake failures are injected, in place of
+/// the real call.
+///
+fn attempt<T: Display, E: Display>(options: &FillOptions, send: impl FnOnce() -> Result<T, E>) -> Result<String, String> {
+    if simulate_failure(options) {
+        return Err(String::from("Simulated failure (--simulate-failures)"));
+    }
+    send().map(|tx_id| tx_id.to_string()).map_err(|e| e.to_string())
+}
+
+/// Whether to fail this attempt on purpose, at the rate given by `--simulate-failures`
+#[cfg(feature = "simulate-failures")]
+fn simulate_failure(options: &FillOptions) -> bool {
+    use rand::Rng;
+    options.simulate_failures.is_some_and(|rate| rand::thread_rng().gen_bool(rate))
+}
+
+/// Failures can never be simulated outside of testing builds
+#[cfg(not(feature = "simulate-failures"))]
+fn simulate_failure(_options: &FillOptions) -> bool {
+    false
+}
+
+/// Whether an RPC error means the call's parameters didn't match the daemon's signature for it.
+///
+/// Daemons respond to the wrong number of parameters with the method's help text, and to the wrong
+/// types with a JSON type error.
+///
+fn is_signature_error(error: &str) -> bool {
+    let error = error.to_lowercase();
+    ["sendtoaddress \"", "expected type", "json value is not", "too many parameters", "wrong number of"]
+        .iter()
+        .any(|pattern| error.contains(pattern))
+}
+
+/// Looks up how much a fee-subtracted fill actually delivered: its amount, minus the TX's network fee.
+///
+/// If the wallet can't be queried, the sent amount is returned (with a warning), as the best estimate.
+///
+fn received_after_fee(rpc: &mut ReconnectingRpc, tx_id: &str, amount: f64) -> f64 {
+    match rpc.call(|c| c.gettransaction(tx_id)) {
+        Ok(tx) => {
+            let received = amount - tx.fee.unwrap_or(0.0).abs();
+            humanln!(" - Received after network fee: {}", received);
+            received
+        }
+        Err(e) => {
+            eprintln!(
+                "{}",
+                style::warning(format!(
+                    " - Couldn't look up the network fee of {} (\"{}\"), recording the sent amount",
+                    tx_id, e
+                ))
+            );
+            amount
+        }
+    }
+}
+
+/// Fills a group of same-value promos with a single `sendmany` TX, retrying until it succeeds (or the
+/// attempt cap is reached).
+///
+/// Callers are expected to keep groups at or below their maximum outputs per TX. As with `fill_address`,
+/// the wallet is checked for the TX before retrying a failed attempt; a `sendmany` TX pays every output
+/// or none, so finding any one of the group's addresses means the whole group was funded.
+///
+/// # Arguments
+///
+/// * `rpc` - The RPC client of the funding wallet.
+/// * `promos` - The promos to fill; their values and TX IDs are set once funded.
+/// * `values` - The value each promo should hold (in the same order), the coin's promo fee is added on top.
+/// * `coin_params` - A reference to the CoinParams for the selected coin.
+/// * `options` - How the fill should be made.
+/// * `ledger` - The run's shared fill state.
+///
+/// # Returns
+///
+/// The last error, if the fill was given up on (leaving every promo in the TX unfunded).
+///
+pub fn fill_many(
+    rpc: &mut ReconnectingRpc,
+    promos: &mut [OptimisedPromoKeypair],
+    values: &[f64],
+    coin_params: &CoinParams,
+    options: &FillOptions,
+    ledger: &mut FillLedger,
+) -> Result<(), String> {
+    let amounts: HashMap<String, f64> = promos
+        .iter()
+        .zip(values)
+        .map(|(promo, &value)| (promo.public.clone(), value + coin_params.promo_fee))
+        .collect();
+    if amounts.is_empty() {
+        return Ok(());
+    }
+    let addresses: Vec<&str> = amounts.keys().map(String::as_str).collect();
+
+    humanln!(
+        " - Filling {} codes with {} {} in total, in one TX...",
+        amounts.len(),
+        amounts.values().map(|amount| amount - coin_params.promo_fee).sum::<f64>(),
+        coin_params.ticker
+    );
+    let comment = format!("{} Promos pre-fill x{}", coin_params.name, amounts.len());
+    let mut attempts = 0;
+    // Set once an attempt has errored, as the daemon may have broadcast it before the error reached us
+    let mut maybe_sent = false;
+    loop {
+        let sent = match maybe_sent {
+            true => find_sent_tx(rpc, &addresses),
+            false => Ok(None),
+        };
+        let result = match sent {
+            Ok(Some(tx_id)) => {
+                humanln!(" - The failed attempt was sent after all");
+                Ok(tx_id)
+            }
+            Ok(None) => {
+                let min_conf = options.min_conf.unwrap_or(1);
+                let result = attempt(options, || rpc.call(|c| c.sendmany("", amounts.clone(), Some(min_conf), Some(&comment))));
+                if let Some(ref log) = options.log {
+                    let outputs: Vec<(&str, f64)> = amounts.iter().map(|(address, amount)| (address.as_str(), *amount)).collect();
+                    log.record("sendmany", &outputs, &result);
+                }
+                result
+            }
+            // Sending again without knowing could fund the group twice, so keep checking instead
+            Err(e) => Err(format!("couldn't check whether the failed attempt was sent: {}", e)),
+        };
+        match result {
+            Ok(tx_id) => {
+                humanln!("{}", style::success(format!(" - TX: {}", tx_id)));
+                for (promo, &value) in promos.iter_mut().zip(values) {
+                    promo.value = value;
+                    promo.tx_id = Some(tx_id.clone());
+                }
+                if options.avoid_clustering {
+                    ledger.change_locks.lock_change(rpc, &tx_id);
+                }
+                return Ok(());
+            }
+            // Every spare UTXO is already used: clustering can no longer be avoided, so spend the change after all
+            Err(e) if is_insufficient_funds(&e) && !ledger.change_locks.is_empty() => {
+                eprintln!(
+                    "{}",
+                    style::warning(" - No unlocked coins are left, unlocking this run's change to keep funding...")
+                );
+                ledger.change_locks.unlock_all(rpc);
+            }
+            Err(e) => {
+                attempts += 1;
+                maybe_sent = true;
+                if options.after_failure(attempts) != FailureAction::Retry {
+                    eprintln!(
+                        "{}",
+                        style::error(format!(" - TX failed with error: \"{}\". Giving up after {} attempts!", e, attempts))
+                    );
+                    return Err(e);
+                }
+                eprintln!(
+                    "{}",
+                    style::warning(format!(" - TX failed with error: \"{}\". Retrying in 10 seconds...", e))
+                );
+                std::thread::sleep(std::time::Duration::from_secs(10));
+            }
+        }
+    }
+}
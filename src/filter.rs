@@ -0,0 +1,81 @@
+use std::collections::HashMap;
+
+use crate::{
+    coins::{lookup_coin, CoinParams},
+    output::{is_gzip, read_csv, read_text, write_text},
+    plan::to_sats,
+};
+
+/// An inclusive range of code values, either end of which may be open
+#[derive(Clone, Copy, Default)]
+pub struct ValueRange {
+    /// The lowest value kept, if any
+    pub min: Option<f64>,
+    /// The highest value kept, if any
+    pub max: Option<f64>,
+}
+
+impl ValueRange {
+    /// Whether either end of the range is set, so it filters anything at all
+    pub fn is_set(&self) -> bool {
+        self.min.is_some() || self.max.is_some()
+    }
+
+    /// Whether a value of a coin is within the range, compared in the coin's smallest units so float
+    /// noise never excludes a code
+    pub fn contains(&self, value: f64, coin_params: &CoinParams) -> bool {
+        let sats = to_sats(value, coin_params);
+        self.min.is_none_or(|min| sats >= to_sats(min, coin_params))
+            && self.max.is_none_or(|max| sats <= to_sats(max, coin_params))
+    }
+
+    /// Describes the range, e.g. "worth 5 to 10 PIV" or "worth at least 5 PIV"
+    pub fn describe(&self, ticker: &str) -> String {
+        match (self.min, self.max) {
+            (Some(min), Some(max)) => format!("worth {} to {} {}", min, max, ticker),
+            (Some(min), None) => format!("worth at least {} {}", min, ticker),
+            (None, Some(max)) => format!("worth at most {} {}", max, ticker),
+            (None, None) => String::from("of any value"),
+        }
+    }
+}
+
+/// Writes the rows of a batch CSV whose value is within a range to a new CSV, for handing a subset of
+/// codes to a partner. Rows are kept as-is (every column, in order), under the same header.
+///
+/// # Arguments
+///
+/// * `input` - The CSV to filter (gzipped if it ends in `.gz`).
+/// * `out` - The CSV to write (gzipped if it ends in `.gz`).
+/// * `range` - The values to keep.
+///
+/// # Returns
+///
+/// How many rows were kept, out of how many, or the reason the CSV couldn't be filtered.
+///
+pub fn filter_csv(input: &str, out: &str, range: ValueRange) -> Result<(usize, usize), String> {
+    if input == out {
+        return Err(format!("\"{}\" can't be both the input and the output", out));
+    }
+    let contents = read_text(input).map_err(|e| format!("can't read \"{}\": {}", input, e))?;
+    let records = read_csv(input).map_err(|e| format!("can't parse \"{}\": {}", input, e))?;
+
+    let mut filtered = contents.lines().next().unwrap_or_default().to_owned() + "\n";
+    let lines = contents.lines().skip(1).filter(|line| !line.trim().is_empty());
+    let mut kept = 0;
+    let mut coins: HashMap<&str, CoinParams> = HashMap::new();
+    for (record, line) in records.iter().zip(lines) {
+        if !coins.contains_key(record.coin.as_str()) {
+            let coin_params = lookup_coin(&record.coin)
+                .ok_or_else(|| format!("\"{}\" has codes for an unsupported coin \"{}\"", input, record.coin))?;
+            coins.insert(&record.coin, coin_params);
+        }
+        if range.contains(record.value, &coins[record.coin.as_str()]) {
+            filtered.push_str(line);
+            filtered.push('\n');
+            kept += 1;
+        }
+    }
+    write_text(out, &filtered, is_gzip(out)).map_err(|e| format!("can't write \"{}\": {}", out, e))?;
+    Ok((kept, records.len()))
+}
@@ -0,0 +1,278 @@
+use std::{
+    fs::{self, File},
+    io::{self, Write},
+    path::Path,
+};
+
+use crate::{
+    coins::CoinParams,
+    fill::{fill_address, FillLedger, FillOptions},
+    output::{is_gzip, read_text, restrict_permissions, write_text, ValueFormat},
+    reserve::ReserveLock,
+    rpc::ReconnectingRpc,
+    style, OptimisedPromoKeypair,
+};
+
+/// A CSV loaded for funding: its header, rows, and the positions of the columns we need
+struct FundingCsv {
+    header: Vec<String>,
+    rows: Vec<Vec<String>>,
+    address_col: usize,
+    value_col: usize,
+    tx_id_col: usize,
+    coin_col: Option<usize>,
+}
+
+/// Reads a CSV of addresses to fund, which needs `address` and `value` columns.
+///
+/// A `tx_id` column is added if missing; rows which already have one are treated as funded.
+///
+fn read_funding_csv(path: &str) -> io::Result<FundingCsv> {
+    let contents = read_text(path)?;
+    let mut lines = contents.lines().filter(|l| !l.trim().is_empty());
+    let mut header: Vec<String> = lines
+        .next()
+        .unwrap_or_default()
+        .split(',')
+        .map(|c| c.trim().to_owned())
+        .collect();
+
+    // Older (or `--csv-trailing-comma`) Batcher CSVs end their header with a comma, which isn't a real column
+    if header.last().is_some_and(|c| c.is_empty()) {
+        header.pop();
+    }
+
+    let find = |name: &str| header.iter().position(|c| c == name);
+    let missing = |name: &str| io::Error::new(io::ErrorKind::InvalidData, format!("no \"{}\" column", name));
+    let address_col = find("address").ok_or_else(|| missing("address"))?;
+    let value_col = find("value").ok_or_else(|| missing("value"))?;
+    let coin_col = find("coin");
+    let tx_id_col = match find("tx_id") {
+        Some(col) => col,
+        None => {
+            header.push(String::from("tx_id"));
+            header.len() - 1
+        }
+    };
+
+    let rows = lines
+        .map(|line| {
+            let mut row: Vec<String> = line.split(',').map(|c| c.trim().to_owned()).collect();
+            row.resize(header.len(), String::new());
+            row
+        })
+        .collect();
+
+    Ok(FundingCsv {
+        header,
+        rows,
+        address_col,
+        value_col,
+        tx_id_col,
+        coin_col,
+    })
+}
+
+/// Saves a funding CSV, via a temporary file so a crash mid-write never corrupts it.
+///
+/// The temporary file is restricted to the current user (`0600`) before anything is written to it,
+/// as reserve files hold codes, and it replaces the original (permissions included) once written.
+///
+fn write_funding_csv(path: &str, csv: &FundingCsv) -> io::Result<()> {
+    let mut contents = csv.header.join(",") + "\n";
+    for row in &csv.rows {
+        contents.push_str(&row.join(","));
+        contents.push('\n');
+    }
+    let tmp_path = format!("{}.tmp", path);
+    File::create(&tmp_path)?;
+    restrict_permissions(Path::new(&tmp_path), 0o600)?;
+    write_text(&tmp_path, &contents, is_gzip(path))?;
+    fs::rename(tmp_path, path)
+}
+
+/// The funding request written alongside a generate-only batch, as `<name>_funding_request.csv`
+pub fn funding_request_path(name: &str) -> String {
+    format!("{}_funding_request.csv", Path::new(name).with_extension("").to_string_lossy())
+}
+
+/// Starts a funding request: a CSV (`coin,address,value`) of every code that needs funding, for the
+/// `fund` command to consume on a separate, online machine. Rows are added as each code is recorded
+/// (see `funding_request_row`), so an interrupted run's request already lists every code it made.
+///
+/// It holds no codes or keys, so it's the only file that needs to leave an airgapped machine.
+///
+/// # Arguments
+///
+/// * `path` - The funding request to write.
+/// * `append` - Add to an existing request (from an interrupted run), rather than starting a new one.
+///
+pub fn create_funding_request(path: &str, append: bool) -> io::Result<()> {
+    if append && Path::new(path).exists() {
+        return Ok(());
+    }
+    let mut file = File::create(path)?;
+    writeln!(file, "coin,address,value")
+}
+
+/// Returns a promo's row of a funding request, or `None` if it has no value to fund
+pub fn funding_request_row(promo: &OptimisedPromoKeypair, coin_ticker: &str, value_format: &ValueFormat) -> Option<String> {
+    (promo.value > 0.0).then(|| format!("{},{},{}", coin_ticker.to_lowercase(), promo.public, value_format.format(promo.value)))
+}
+
+/// Counts the addresses listed in a funding request
+pub fn count_funding_request(path: &str) -> io::Result<usize> {
+    Ok(read_text(path)?.lines().skip(1).filter(|line| !line.trim().is_empty()).count())
+}
+
+/// Funds every address in a CSV (for example, one generated on an airgapped machine), writing each
+/// TX ID back into the CSV as soon as it's sent.
+///
+/// Rows with a TX ID are skipped, so an interrupted run can simply be repeated.
+///
+/// # Arguments
+///
+/// * `path` - The CSV to fund, with `address` and `value` columns.
+/// * `rpc` - The RPC client of the funding wallet.
+/// * `coin_params` - A reference to the CoinParams for the selected coin.
+/// * `options` - How the fills should be made.
+///
+pub fn fund_csv(path: &str, rpc: &mut ReconnectingRpc, coin_params: &CoinParams, options: &FillOptions) -> io::Result<()> {
+    let mut csv = read_funding_csv(path)?;
+
+    // Never send one coin to addresses that were generated for another
+    if let Some(coin_col) = csv.coin_col {
+        if let Some(row) = csv.rows.iter().find(|row| !row[coin_col].eq_ignore_ascii_case(&coin_params.ticker)) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("it lists \"{}\" addresses, but {} was selected", row[coin_col], coin_params.ticker),
+            ));
+        }
+    }
+    let mut ledger = FillLedger::default();
+    let total = csv.rows.len();
+    let mut failed = 0;
+
+    for i in 0..total {
+        let address = csv.rows[i][csv.address_col].clone();
+        if !csv.rows[i][csv.tx_id_col].is_empty() {
+            humanln!("Address {} of {}: {} - already funded, skipping", i + 1, total, address);
+            continue;
+        }
+        let value: f64 = match csv.rows[i][csv.value_col].parse() {
+            Ok(value) if value > 0.0 => value,
+            _ => {
+                eprintln!(
+                    "{}",
+                    style::warning(format!("Address {} of {}: {} - no valid value, skipping", i + 1, total, address))
+                );
+                continue;
+            }
+        };
+
+        humanln!("Address {} of {}: {}", i + 1, total, address);
+        match fill_address(rpc, &address, value, coin_params, options, &mut ledger) {
+            Ok(outcome) => {
+                csv.rows[i][csv.value_col] = outcome.value.to_string();
+                csv.rows[i][csv.tx_id_col] = outcome.tx_id;
+                write_funding_csv(path, &csv)?;
+            }
+            // Its TX ID stays blank, so re-running picks it up again
+            Err(_) => {
+                failed += 1;
+                if options.aborted.get() {
+                    eprintln!("{}", style::error("Aborting, as requested - the remaining addresses were left unfunded"));
+                    break;
+                }
+            }
+        }
+    }
+
+    ledger.change_locks.unlock_all(rpc);
+
+    if failed > 0 {
+        eprintln!(
+            "{}",
+            style::warning(format!("{} addresses couldn't be funded, re-run to retry them", failed))
+        );
+    } else {
+        humanln!("Funded \"{}\"!", path);
+    }
+    Ok(())
+}
+
+/// Draws codes from a reserve file (see `reserve`), funding each with the given value and writing its
+/// value and TX ID back, so issuing a code takes a single fill rather than a whole derivation.
+///
+/// Codes are drawn in the order they were reserved, skipping any already drawn. Nothing is sent
+/// unless the pool holds enough undrawn codes for the whole request.
+///
+/// # Arguments
+///
+/// * `path` - The reserve file to draw from.
+/// * `count` - How many codes to draw.
+/// * `value` - The value to fund each drawn code with.
+/// * `rpc` - The RPC client of the funding wallet.
+/// * `coin_params` - A reference to the CoinParams for the selected coin.
+/// * `options` - How the fills should be made.
+///
+/// # Returns
+///
+/// The drawn codes which were funded, for issuing.
+///
+pub fn draw_reserve(
+    path: &str,
+    count: usize,
+    value: f64,
+    rpc: &mut ReconnectingRpc,
+    coin_params: &CoinParams,
+    options: &FillOptions,
+) -> io::Result<Vec<String>> {
+    let _lock = ReserveLock::acquire(path)?;
+    let mut csv = read_funding_csv(path)?;
+    let code_col = csv
+        .header
+        .iter()
+        .position(|c| c == "code")
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "no \"code\" column, is it a reserve file?"))?;
+    if let Some(coin_col) = csv.coin_col {
+        if let Some(row) = csv.rows.iter().find(|row| !row[coin_col].eq_ignore_ascii_case(&coin_params.ticker)) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("it reserves \"{}\" codes, but {} was selected", row[coin_col], coin_params.ticker),
+            ));
+        }
+    }
+
+    let undrawn: Vec<usize> = (0..csv.rows.len()).filter(|&i| csv.rows[i][csv.tx_id_col].is_empty()).take(count).collect();
+    if undrawn.len() < count {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("only {} undrawn codes are left, reserve more first", undrawn.len()),
+        ));
+    }
+
+    let mut ledger = FillLedger::default();
+    let mut drawn = Vec::new();
+    for (n, i) in undrawn.into_iter().enumerate() {
+        let (code, address) = (csv.rows[i][code_col].clone(), csv.rows[i][csv.address_col].clone());
+        humanln!("Drawing code {} of {}: {}", n + 1, count, address);
+        match fill_address(rpc, &address, value, coin_params, options, &mut ledger) {
+            Ok(outcome) => {
+                csv.rows[i][csv.value_col] = outcome.value.to_string();
+                csv.rows[i][csv.tx_id_col] = outcome.tx_id;
+                write_funding_csv(path, &csv)?;
+                drawn.push(code);
+            }
+            // It stays undrawn, so the next draw picks it up again
+            Err(_) if options.aborted.get() => {
+                eprintln!("{}", style::error("Aborting, as requested - the remaining codes were left undrawn"));
+                break;
+            }
+            Err(_) => (),
+        }
+    }
+
+    ledger.change_locks.unlock_all(rpc);
+    Ok(drawn)
+}
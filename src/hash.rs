@@ -0,0 +1,56 @@
+use bitcoin_hashes::hex::ToHex;
+use sha2::{Digest, Sha256};
+
+/// Hashes data with SHA256, using the CPU's SHA extensions where available.
+///
+/// Key derivation spends nearly all of its time here, so this uses `sha2`, which detects SHA-NI
+/// (x86) and the ARMv8 crypto extensions at runtime, falling back to a portable implementation.
+/// The output is plain SHA256, identical to `bitcoin_hashes`.
+///
+pub fn sha256(data: &[u8]) -> [u8; 32] {
+    Sha256::digest(data).into()
+}
+
+/// Hashes a WIF for verification-only exports, as the hex SHA256 of its string
+pub fn wif_hash(wif: &str) -> String {
+    sha256(wif.as_bytes()).to_hex()
+}
+
+/// Applies `rounds` further rounds of SHA256 to a 32-byte state, as in key derivation
+pub fn sha256_rounds(mut state: [u8; 32], rounds: u64) -> [u8; 32] {
+    for _ in 0..rounds {
+        state = sha256(&state);
+    }
+    state
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin_hashes::{hex::ToHex, sha256, Hash};
+
+    #[test]
+    fn sha256_matches_bitcoin_hashes() {
+        for data in [&b""[..], b"test", b"promo-abc", &[0xab; 200]] {
+            assert_eq!(sha256(data), sha256::Hash::hash(data).into_inner());
+        }
+    }
+
+    #[test]
+    fn sha256_rounds_matches_bitcoin_hashes() {
+        let start = sha256(b"promo-abc");
+        let mut expected = start;
+        for rounds in 0..5 {
+            assert_eq!(sha256_rounds(start, rounds), expected);
+            expected = sha256::Hash::hash(&expected).into_inner();
+        }
+    }
+
+    #[test]
+    fn sha256_rounds_derives_a_pinned_key() {
+        assert_eq!(
+            sha256_rounds(sha256(b"promo-abc"), 999).to_hex(),
+            "34503bdd4d6c299722ab7d22f94d92a2835a711224fd13b8b41717e9ad1bdcb5"
+        );
+    }
+}
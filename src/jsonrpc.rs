@@ -0,0 +1,96 @@
+//! A minimal JSON-RPC client over plain HTTP, for calls `BitcoinRpcClient` can't make (such as a fork's
+//! own send method). It only needs `std` and `serde_json`, so the regtest tests include it as-is.
+
+use std::{
+    io::{Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    time::Duration,
+};
+
+use serde_json::{json, Value};
+
+/// Where JSON-RPC calls are sent, and how long each may take
+pub struct Endpoint {
+    /// The host the daemon's RPC listens on
+    pub host: String,
+    pub port: u16,
+    pub user: String,
+    pub pass: String,
+    /// The wallet to target on multiwallet daemons, or `None` for the default wallet
+    pub wallet: Option<String>,
+    /// How long connecting, sending the request and reading the response may each take
+    pub timeout: Duration,
+}
+
+impl Endpoint {
+    /// Makes a single JSON-RPC call, returning its result or the daemon's error message.
+    ///
+    /// A daemon that stops answering fails the call once `timeout` passes, rather than hanging it.
+    ///
+    pub fn call(&self, method: &str, params: &Value) -> Result<Value, String> {
+        let body = json!({ "jsonrpc": "1.0", "id": "batcher", "method": method, "params": params }).to_string();
+        let path = match self.wallet {
+            Some(ref wallet) => format!("/wallet/{}", percent_encode(wallet)),
+            None => String::from("/"),
+        };
+        let request = format!(
+            "POST {} HTTP/1.0\r\nHost: {}\r\nAuthorization: Basic {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            path,
+            self.host,
+            base64_encode(format!("{}:{}", self.user, self.pass).as_bytes()),
+            body.len(),
+            body
+        );
+
+        let address = (self.host.as_str(), self.port)
+            .to_socket_addrs()
+            .map_err(|e| e.to_string())?
+            .next()
+            .ok_or_else(|| format!("\"{}\" didn't resolve to an address", self.host))?;
+        let mut stream = TcpStream::connect_timeout(&address, self.timeout).map_err(|e| e.to_string())?;
+        stream.set_read_timeout(Some(self.timeout)).map_err(|e| e.to_string())?;
+        stream.set_write_timeout(Some(self.timeout)).map_err(|e| e.to_string())?;
+        stream.write_all(request.as_bytes()).map_err(|e| e.to_string())?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response).map_err(|e| e.to_string())?;
+
+        // Daemons answer RPC errors with a JSON body too, so only a missing one (e.g. bad credentials) is an HTTP error
+        let (head, body) = response.split_once("\r\n\r\n").unwrap_or((response.as_str(), ""));
+        let Ok(reply) = serde_json::from_str::<Value>(body) else {
+            return Err(head.lines().next().unwrap_or("no response").to_owned());
+        };
+        match reply["error"] {
+            Value::Null => Ok(reply["result"].clone()),
+            ref error => Err(error["message"].as_str().map_or_else(|| error.to_string(), str::to_owned)),
+        }
+    }
+}
+
+/// Base64-encodes bytes, for HTTP basic auth
+fn base64_encode(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::new();
+    for chunk in bytes.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, byte)| n | ((*byte as u32) << (16 - 8 * i)));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[((n >> (18 - 6 * i)) & 63) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// Percent-encodes everything but unreserved URL characters, as wallet names may contain spaces etc
+pub fn percent_encode(input: &str) -> String {
+    let mut encoded = String::new();
+    for byte in input.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
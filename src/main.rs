@@ -1,18 +1,122 @@
 use std::{
     env::home_dir,
-    fs::{self, OpenOptions},
-    io::{self, prelude::*},
+    fs,
+    io::{self, prelude::*, IsTerminal},
+    cell::Cell,
+    sync::atomic::{AtomicBool, Ordering},
 };
 
+/// Prints human-readable output, like `print!`, via `style::print_human` (so it can move to stderr
+/// when a machine-readable stream takes stdout). Defined ahead of every `mod`, so each can use it.
+macro_rules! human {
+    ($($arg:tt)*) => {
+        $crate::style::print_human(format_args!($($arg)*))
+    };
+}
+
+/// Prints a line of human-readable output, like `println!`, via `style::print_human`
+macro_rules! humanln {
+    () => {
+        $crate::style::print_human(format_args!("\n"))
+    };
+    ($($arg:tt)*) => {
+        $crate::style::print_human(format_args!("{}\n", format_args!($($arg)*)))
+    };
+}
+
+mod archive;
+
+mod audit;
+use audit::audit_csv;
+
+mod bench;
+use bench::{benchmark_hash_rate, estimate_runtime, format_duration, print_preflight, print_tune};
+
+mod cli;
+use cli::{parse_args, CliArgs, Command};
+
+mod config;
+
+mod clustering;
+
+mod code;
+use code::{CodeFormat, MAP_NUMERIC};
+
 mod coins;
-use coins::{CoinParams, get_supported_coins};
+use coins::{check_coin_table, get_supported_coins, lookup_coin, CoinParams};
+
+mod confirm;
+use confirm::{wait_for_confirmations, DEFAULT_POLL_INTERVAL};
+
+mod derive;
+use derive::{DerivationScheme, Deriver, V2_EXTRA_BITS};
+
+mod export;
+use export::{export_payment_uris, export_redeemer_codes, export_wallet_keys, KeyExportFormat};
+
+mod fund;
+use fund::{count_funding_request, create_funding_request, draw_reserve, fund_csv, funding_request_path};
+
+mod hash;
+
+mod jsonrpc;
+
+mod merkle;
+use merkle::MerkleTree;
+
+mod fiat;
+use fiat::FiatRate;
+
+mod filter;
+use filter::filter_csv;
+
+mod fill;
+use fill::{fill_many, fill_promo, FillLedger, FillOptions};
+
+mod output;
+use output::{read_csv, validate_filename, read_json_save, read_saved_codes, CsvLayout, ExtraSave, FailedCsv, OutputFormat, OutputSink, ValueFormat, WifVault, WriteBuffer};
+
+mod pipeline;
+use pipeline::OrderedPromos;
+
+mod merge;
+use merge::merge_csvs;
 
-use pivx_rpc_rs::{self, BitcoinRpcClient};
+mod plan;
+use plan::{print_plan_diff, round_to_coin, share_of_budget, BatchPlan, PromoBatch};
 
-use base58::ToBase58;
-use bitcoin_hashes::{sha256, sha256d, Hash};
+mod progress;
+use progress::ProgressReporter;
+
+mod qr;
+use qr::{save_qr, save_secret_qr, QrPayload};
+
+mod reserve;
+use reserve::generate_reserve;
+
+mod rpc;
+use rpc::{check_coin_params, check_daemon_compat, daemon_chain, ReconnectingRpc, DEFAULT_RPC_RETRIES, DEFAULT_RPC_RETRY_DELAY, DEFAULT_RPC_TIMEOUT};
+
+mod runlog;
+use runlog::RunLog;
+
+mod state;
+use state::RunState;
+
+mod style;
+
+mod template;
+use template::{load_template, save_template};
+
+mod utc;
+
+mod vanity;
+use vanity::{expected_attempts, find_vanity_promo, validate_vanity, warn_vanity_cost};
+
+use base58::{FromBase58, ToBase58};
+use bitcoin_hashes::{hex::ToHex, sha256, sha256d, Hash};
 use ripemd::{Digest, Ripemd160};
-use secp256k1::{rand, rand::Rng, PublicKey, Secp256k1, SecretKey};
+use secp256k1::{ecdsa::Signature, rand, rand::Rng, Message, PublicKey, Secp256k1, SecretKey};
 
 /// A struct representing an optimized promotional keypair.
 ///
@@ -24,17 +128,20 @@ pub struct OptimisedPromoKeypair {
     public: String,
     code: String,
     value: f64,
-}
-
-/// A struct representing a promo batch request.
-///
-/// This struct contains the Value and the Quantity of the batch it represents.
-///
-pub struct PromoBatch {
-    /// The value of the batch
-    value: f64,
-    /// The quantity of the batch
-    qty: u64,
+    /// A signature of the code by its own key, if proof-of-generation was requested
+    signature: Option<Signature>,
+    /// The TX ID which funded this promo, if it was filled
+    tx_id: Option<String>,
+    /// The scheme this promo's key was derived with
+    derivation: DerivationScheme,
+    /// The note of the promo's batch, if the plan has notes (empty for batches without one)
+    note: Option<String>,
+    /// The SHA256 of the promo's WIF, if verification-only WIF hashes were requested
+    wif_hash: Option<String>,
+    /// When the promo was completed (generated, and funded if it needed to be), as an ISO 8601 UTC timestamp
+    timestamp: Option<String>,
+    /// The iteration target the promo was derived with, if targets are being recorded
+    target: Option<u64>,
 }
 
 /// Iterations required for a PIVX Promo to be derived.
@@ -44,105 +151,807 @@ pub struct PromoBatch {
 ///
 pub const PROMO_TARGETS: [u64; 1] = [12_500_000];
 
+/// The collision probability (across a whole run) above which the operator is warned
+pub const COLLISION_WARN_THRESHOLD: f64 = 0.01;
+
 /// The default coin to use if none is selected
 pub const DEFAULT_COIN_TICKER: &str = "PIV";
 
 fn main() {
+    // Parse any command-line options
+    let cli = parse_args();
+    style::init_human_stderr(cli.progress_json.as_deref() == Some("-"));
+    style::init_colors(cli.no_color);
+    style::init_redact(cli.redact);
+    DECIMAL_COMMA.store(cli.decimal_comma, Ordering::Relaxed);
+
+    // A mistake in the coin table would produce wrong WIFs and addresses, so refuse to start with one
+    let coin_problems = check_coin_table(&get_supported_coins());
+    if !coin_problems.is_empty() {
+        for problem in &coin_problems {
+            eprintln!("{}", style::error(format!("Coin table: {}", problem)));
+        }
+        std::process::exit(1);
+    }
+    let fiat = cli.fiat_rate.map(|price| FiatRate::new(&cli.fiat_currency, price));
+
+    // Handle any standalone commands
+    if let Command::Tune { target_ms } = cli.command {
+        print_tune(target_ms);
+        return;
+    }
+    if let Command::Filter { ref csv, ref out } = cli.command {
+        match filter_csv(csv, out, cli.value_range) {
+            Ok((kept, total)) => humanln!(
+                "Exported the {} of {} codes in \"{}\" {} to \"{}\"",
+                kept, total, csv, cli.value_range.describe("coins"), out
+            ),
+            Err(e) => {
+                eprintln!("{}", style::error(format!("Failed to filter: {}", e)));
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    if let Command::Prove { ref code, ref csv } = cli.command {
+        let records = match read_csv(csv) {
+            Ok(records) => records,
+            Err(e) => {
+                eprintln!("{}", style::error(format!("Failed to read \"{}\": {}", csv, e)));
+                std::process::exit(1);
+            }
+        };
+        let tree = MerkleTree::new(records.iter().map(|record| record.code.as_str()));
+        let Some(proof) = tree.prove(code) else {
+            eprintln!("{}", style::error(format!("'{}' isn't one of the codes in \"{}\"", code, csv)));
+            std::process::exit(1);
+        };
+        let salt = tree.salt(code);
+        let proof = serde_json::json!({
+            "salt": salt.to_hex(),
+            "leaf": merkle::leaf_hash(&salt, code).to_hex(),
+            "root": tree.root().unwrap().to_hex(),
+            "proof": proof,
+        });
+        humanln!("{}", serde_json::to_string_pretty(&proof).unwrap());
+        return;
+    }
+
+    if let Command::Merge { ref out, ref inputs } = cli.command {
+        match merge_csvs(out, inputs) {
+            Ok(summary) => {
+                humanln!("Merged {} CSVs into \"{}\":", inputs.len(), out);
+                print_batches(&summary.plan, &find_coin(&summary.coin), fiat.as_ref());
+                if summary.duplicates > 0 {
+                    humanln!("{}", style::warning(format!("{} duplicate codes were dropped", summary.duplicates)));
+                }
+            }
+            Err(e) => {
+                eprintln!("{}", style::error(format!("Failed to merge: {}", e)));
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    if let Command::CheckConfig = cli.command {
+        let coins = match cli.coin {
+            Some(ref ticker) => vec![find_coin(ticker)],
+            None => get_supported_coins(),
+        };
+        for coin_params in coins {
+            print_coin_conf(&coin_params, &cli);
+        }
+        return;
+    }
+    if let Command::VerifyWif { ref wif, ref csv } = cli.command {
+        let records = match read_csv(csv) {
+            Ok(records) => records,
+            Err(e) => {
+                eprintln!("{}", style::error(format!("Failed to read \"{}\": {}", csv, e)));
+                std::process::exit(1);
+            }
+        };
+        let hash = hash::wif_hash(wif.trim());
+        let Some(record) = records.iter().find(|record| record.wif_hash.as_deref() == Some(hash.as_str())) else {
+            eprintln!("{}", style::error(format!("No code in \"{}\" has that WIF's hash ({})", csv, hash)));
+            std::process::exit(1);
+        };
+        humanln!("{}", style::success(format!("The WIF matches the hash of code '{}' ({} {})", record.code, record.value, record.coin.to_uppercase())));
+        match wif_to_address(wif.trim(), &find_coin(&record.coin)) {
+            Ok(address) => humanln!("Its address: {}", address),
+            Err(e) => eprintln!("{}", style::warning(format!("... but it can't be decoded as a {} WIF: {}", record.coin, e))),
+        }
+        return;
+    }
+
+    // Only wipe the screen for a human at a real terminal; redirected output should stay a clean log
+    let should_clear = !cli.no_clear && style::human_is_terminal();
+
+    // Pick up an interrupted run, if asked to: its state decides everything the remaining codes depend on
+    let resumed = cli.resume.then(|| {
+        match RunState::load().and_then(|state| state.check_flags(&cli).map(|_| state)) {
+            Ok(state) => state,
+            Err(e) => {
+                eprintln!("{}", style::error(format!("Can't continue the interrupted run: {}", e)));
+                std::process::exit(1);
+            }
+        }
+    });
+
+    // A continued run reports in the rate it was started with, unless a new one is given
+    let fiat = fiat.or_else(|| resumed.as_ref().and_then(|state| state.fiat_rate.clone()));
+
     // Select which coin to create promo codes for
-    let coin_params = select_coin();
-    println!("Selected coin: {} ({})", coin_params.name, coin_params.ticker);
+    let mut coin_params = match (&resumed, &cli.coin) {
+        (Some(state), _) => find_coin(&state.coin),
+        (None, Some(ticker)) => find_coin(ticker),
+        (None, None) => select_coin(),
+    };
+    if let Some(fee) = cli.fee {
+        coin_params.promo_fee = fee;
+    }
+    // Test networks use their own version bytes, so their addresses won't share mainnet's prefix either
+    if let Some((pub_byte, priv_byte)) = cli.version_bytes {
+        coin_params.pub_key_byte = pub_byte;
+        coin_params.priv_key_byte = priv_byte;
+        coin_params.expected_address_prefix = None;
+    }
+    humanln!("Selected coin: {} ({})", coin_params.name, coin_params.ticker);
+    // Payment URIs need the coin's URI scheme
+    if (cli.uri_export.is_some() || cli.qr_payload == QrPayload::Uri) && coin_params.uri_scheme.is_none() {
+        eprintln!("{}", style::usage_error(format!("{} has no known payment URI scheme, so URIs can't be made", coin_params.ticker)));
+        std::process::exit(1);
+    }
+    // A coin's own send method takes just the address and amount, so it can't subtract the fee
+    if let (Some(send), true) = (&coin_params.send_rpc, cli.subtract_fee) {
+        eprintln!(
+            "{}",
+            style::usage_error(format!("\"--subtract-fee\" isn't supported by {}'s send method ({})", coin_params.ticker, send.method))
+        );
+        std::process::exit(1);
+    }
+
+    // Reserving only derives codes, so it needs neither the daemon nor any planning
+    if let Command::Reserve { ref path, count } = cli.command {
+        let prefix = cli.prefix.clone().unwrap_or_default();
+        if let Err(e) = validate_prefix(&prefix, cli.check_char) {
+            eprintln!("{}", style::usage_error(e));
+            std::process::exit(1);
+        }
+        if cli.derivation == DerivationScheme::V2 && prefix.is_empty() {
+            eprintln!("{}", style::usage_error("Reserving v2 codes needs a prefix (pass --prefix)"));
+            std::process::exit(1);
+        }
+        let charset = if cli.numeric { MAP_NUMERIC } else { MAP_ALPHANUMERIC };
+        let code_format = CodeFormat {
+            length: random_code_length(&prefix, cli.code_length, charset, cli.derivation),
+            prefix,
+            check_char: cli.check_char,
+            charset,
+        };
+        let mut deriver = Deriver::new(cli.derivation, &code_format.prefix);
+        if let Some(ref namespace) = cli.namespace {
+            deriver = deriver.with_namespace(namespace);
+        }
+        let threads = cli.threads.unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get()));
+        humanln!("Reserving {} {} codes into \"{}\"...", count, coin_params.ticker, path);
+        if let Err(e) = generate_reserve(path, count, threads, &code_format, &deriver, &coin_params) {
+            eprintln!("{}", style::error(format!("Failed to reserve codes into \"{}\": {}", path, e)));
+            std::process::exit(1);
+        }
+        humanln!("{}", style::success(format!("Reserved {} codes, ready to be funded with \"draw\"!", count)));
+        return;
+    }
 
     // Parse the coin's config
-    let coin_config = parse_coin_conf(&coin_params);
-
-    // Setup the RPC
-    let rpc = BitcoinRpcClient::new(
-        String::from("http://localhost:") + &coin_config.rpc_port.to_string(),
-        Some(coin_config.rpc_user.to_owned()),
-        Some(coin_config.rpc_pass.to_owned()),
-        4,
-        10,
-        1000,
-    );
+    let mut coin_config = rpc_config_for(&cli, &coin_params);
+
+    // Setup the RPC, which reconnects by itself if the daemon restarts mid-run
+    // ... and if the daemon can't be reached at all, let the operator fix the connection (or coin) up-front,
+    // rather than failing on every fill
+    let mut rpc = loop {
+        let mut rpc = ReconnectingRpc::new(coin_config.clone());
+        if cli.no_fill || !io::stdin().is_terminal() {
+            break rpc;
+        }
+        let error = match rpc.call(|c| c.getnetworkinfo()) {
+            Ok(_) => break rpc,
+            Err(e) => e.to_string(),
+        };
+        eprintln!(
+            "{}",
+            style::warning(format!(
+                "Couldn't reach the {} daemon at {}:{} - error: \"{}\"",
+                coin_params.name, coin_config.rpc_host, coin_config.rpc_port, error
+            ))
+        );
+        match ask_rpc_recovery(resumed.is_none()) {
+            RpcRecovery::Manual => ask_rpc_connection(&mut coin_config),
+            RpcRecovery::Coin => {
+                coin_params = select_coin();
+                if let Some(fee) = cli.fee {
+                    coin_params.promo_fee = fee;
+                }
+                humanln!("Selected coin: {} ({})", coin_params.name, coin_params.ticker);
+                coin_config = rpc_config_for(&cli, &coin_params);
+            }
+            RpcRecovery::Continue => break rpc,
+        }
+    };
+
+    // If a specific wallet was requested, make sure it's actually loaded before planning anything
+    // ... unless nothing will be funded, in which case the daemon is never contacted at all
+    if let Some(wallet) = cli.wallet.as_ref().filter(|_| !cli.no_fill) {
+        if let Err(e) = rpc.call(|c| c.getwalletinfo()) {
+            eprintln!(
+                "{}",
+                style::error(format!(
+                    "Wallet \"{}\" could not be reached, is it loaded? (try `loadwallet`) - error: \"{}\"",
+                    wallet, e
+                ))
+            );
+            std::process::exit(1);
+        }
+        humanln!("Using wallet: {}", wallet);
+    }
+
+    // Make sure the hardcoded version byte matches what the daemon actually uses, if requested
+    if cli.check_coin_params && !check_coin_params(&mut rpc, &coin_params) && !ask_bool("Continue anyway?", false) {
+        humanln!("Quitting...");
+        return;
+    }
+
+    // Audits only re-derive an existing CSV's codes (and, optionally, check their balances)
+    if let Command::Audit { ref csv } = cli.command {
+        let prefix = cli.prefix.clone().unwrap_or_default();
+        if cli.derivation == DerivationScheme::V2 && prefix.is_empty() {
+            eprintln!("{}", style::usage_error("Auditing v2 codes needs their prefix (pass --prefix)"));
+            std::process::exit(1);
+        }
+        let mut deriver = Deriver::new(cli.derivation, &prefix);
+        if let Some(ref namespace) = cli.namespace {
+            deriver = deriver.with_namespace(namespace);
+        }
+        let balance_rpc = if cli.check_balance { Some(&mut rpc) } else { None };
+        match audit_csv(csv, &coin_params, &deriver, cli.check_char, balance_rpc) {
+            Ok((0, true)) => humanln!("{}", style::success(format!("Every code in \"{}\" checks out!", csv))),
+            Ok((0, false)) => humanln!(
+                "{}",
+                style::warning(format!(
+                    "Every code in \"{}\" is well-formed, but wasn't checked against its address or funds (use \"--check-balance\")",
+                    csv
+                ))
+            ),
+            Ok((problems, _)) => {
+                eprintln!("{}", style::error(format!("{} rows of \"{}\" have problems!", problems, csv)));
+                std::process::exit(1);
+            }
+            Err(e) => {
+                eprintln!("{}", style::error(format!("Failed to audit \"{}\": {}", csv, e)));
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    // Fund-only mode needs no planning: the CSV says what to fund
+    if let Command::Fund { ref csv } = cli.command {
+        if cli.confirm_wallet && !confirm_source_wallet(&mut rpc, &coin_params) {
+            humanln!("Confirmation failed, nothing was funded - Quitting...");
+            return;
+        }
+        let fill_options = FillOptions {
+            min_conf: cli.min_conf,
+            subtract_fee: cli.subtract_fee,
+            log: cli.log_file.as_deref().map(RunLog::open),
+            max_attempts: if cli.strict { Some(1) } else { cli.max_fill_attempts },
+            simulate_failures: cli.simulate_failures,
+            avoid_clustering: cli.avoid_clustering,
+            on_failure: cli.on_failure,
+            fallback: cli.on_failure_fallback,
+            aborted: Cell::new(false),
+        };
+        if let Err(e) = fund_csv(csv, &mut rpc, &coin_params, &fill_options) {
+            eprintln!("{}", style::error(format!("Failed to fund \"{}\": {}", csv, e)));
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    // Drawing funds codes that were derived ahead of time, so issuing them is as quick as a fill
+    if let Command::Draw { ref path, count, value } = cli.command {
+        if cli.confirm_wallet && !confirm_source_wallet(&mut rpc, &coin_params) {
+            humanln!("Confirmation failed, nothing was funded - Quitting...");
+            return;
+        }
+        let fill_options = FillOptions {
+            min_conf: cli.min_conf,
+            subtract_fee: cli.subtract_fee,
+            log: cli.log_file.as_deref().map(RunLog::open),
+            max_attempts: if cli.strict { Some(1) } else { cli.max_fill_attempts },
+            simulate_failures: cli.simulate_failures,
+            avoid_clustering: cli.avoid_clustering,
+            on_failure: cli.on_failure,
+            fallback: cli.on_failure_fallback,
+            aborted: Cell::new(false),
+        };
+        match draw_reserve(path, count, value, &mut rpc, &coin_params, &fill_options) {
+            Ok(drawn) => {
+                for code in &drawn {
+                    humanln!("{}", style::success(format!("Issued: {} ({} {})", style::code(code), value, coin_params.ticker)));
+                }
+                if drawn.len() < count {
+                    eprintln!(
+                        "{}",
+                        style::warning(format!("{} codes couldn't be funded, and were left in the reserve", count - drawn.len()))
+                    );
+                    std::process::exit(1);
+                }
+            }
+            Err(e) => {
+                eprintln!("{}", style::error(format!("Failed to draw from \"{}\": {}", path, e)));
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
 
     let should_save: bool;
     let mut promo_prefix = String::new();
     let mut filename = String::from("codes");
-    let mut batches: Vec<PromoBatch> = Vec::new();
+    let mut plan = BatchPlan::default();
 
     // If Promo Interactive mode is on: let's ask and figure out ALL the settings beforehand for a fine-tuned experience
-    should_save = ask_bool("Would you like to save your batch as a CSV file?", true);
-    if should_save {
-        filename = ask_string("What would you like to name it?", &filename)
+    // ... though there's nothing to save when only planning
+    let only_planning = cli.plan_only || matches!(cli.command, Command::PlanDiff { .. });
+
+    // Starting over would orphan an interrupted run, so make sure that's intended
+    if resumed.is_none() && !only_planning && RunState::exists() {
+        humanln!(
+            "{}",
+            style::warning(format!(
+                "An interrupted run was found (\"{}\"), which can be picked up with \"--continue\"! Starting a new run will discard it.",
+                state::STATE_FILE
+            ))
+        );
+        if !ask_bool("Start a new run anyway?", false) {
+            humanln!("Quitting...");
+            return;
+        }
     }
-    println!("Perfect, now, let's start planning your batch!");
-    println!("----------------------------------------------");
-    loop {
+
+    should_save = match resumed {
+        Some(ref state) => state.save_file.is_some(),
+        None => {
+            !only_planning
+                && ask_bool(
+                    &format!("Would you like to save your batch as a {} file?", cli.output_format.name()),
+                    true,
+                )
+        }
+    };
+    // Redacted codes could never be recovered from the console, so they have to be written somewhere
+    if cli.redact && !should_save && !only_planning {
+        eprintln!("{}", style::usage_error("--redact masks codes on screen, so they must be saved: choose to save the batch"));
+        std::process::exit(1);
+    }
+    if should_save && resumed.is_none() {
+        // Only a plain name is accepted, so the save file always lands in the output directory
+        loop {
+            filename = ask_string("What would you like to name it?", &filename);
+            match validate_filename(&filename) {
+                Ok(_) => break,
+                Err(e) => {
+                    eprintln!("{}", style::warning(format!("{} - try again!", e)));
+                    filename = String::from("codes");
+                }
+            }
+        }
+    }
+    // Start from a saved template, if requested, which can then be added to as usual
+    let mut adding_batches = true;
+    if let Some(ref state) = resumed {
+        plan = state.remaining();
+        humanln!("Continuing the interrupted run, with {} codes left:", plan.total_codes());
+        print_batches(&plan, &coin_params, fiat.as_ref());
+        adding_batches = false;
+    } else if let Some(ref name) = cli.template {
+        plan = match load_template(name) {
+            Ok(plan) => plan,
+            Err(e) => {
+                eprintln!("{}", style::error(format!("Failed to load template \"{}\": {}", name, e)));
+                std::process::exit(1);
+            }
+        };
+        humanln!("Loaded template \"{}\":", name);
+        print_batches(&plan, &coin_params, fiat.as_ref());
+        if let Some(budget) = cli.budget {
+            print_budget(&plan, budget, &coin_params);
+        }
+        adding_batches = ask_bool("Would you like to add another batch?", false);
+    } else {
+        humanln!("Perfect, now, let's start planning your batch!");
+        humanln!("----------------------------------------------");
+    }
+    // Values entered in fiat are rounded to the coin's precision, so keep track of how far that moves the total
+    let fiat_values = fiat.as_ref().filter(|_| cli.fiat_values);
+    let mut fiat_rounding = 0.0;
+    while adding_batches {
         let qty = ask_float(
-            format!("Batch {}: how many codes do you want?", batches.len() + 1).as_str(),
+            format!("Batch {}: how many codes do you want?", plan.len() + 1).as_str(),
             5.0,
         ) as u64;
-        let value = ask_float(
-            format!(
-                "Batch {}: how much {} should each of your {} codes be worth?",
-                batches.len() + 1,
-                coin_params.ticker,
-                qty
-            )
-            .as_str(),
-            1.0,
+        let question = format!(
+            "Batch {}: how much {} should each of your {} codes be worth?",
+            plan.len() + 1,
+            fiat_values.map_or(&coin_params.ticker, |fiat| &fiat.currency),
+            qty
         );
-        batches.push(PromoBatch { value, qty });
-
-        // Clear the screen and log the batches
-        clear_terminal_screen();
-        println!("----------------------------------------------");
-        let mut count = 1;
-        let mut total_value = 0.0;
-        let mut total_codes: u64 = 0;
-        for batch in batches.as_slice() {
-            println!(
-                " - Batch {}: {} codes of {} {}",
-                count, batch.qty, batch.value, coin_params.ticker
+        // With a budget, a batch may instead take a percentage of whatever's left of it
+        let remaining_budget = cli.budget.map(|budget| round_to_coin(budget - plan.total_value(), &coin_params).0);
+        let (value, percent) = match remaining_budget {
+            Some(remaining) => ask_value_or_percent(
+                &format!("{} Or, a percentage of the remaining {} {} budget (e.g. \"20%\")", question, remaining, coin_params.ticker),
+                1.0,
+            ),
+            None => (ask_float(&question, 1.0), false),
+        };
+
+        // Fiat values are converted at the given rate, rounding half-up to the coin's precision
+        let (value, exact_value) = match (fiat_values, remaining_budget) {
+            (_, Some(remaining)) if percent => {
+                let share = share_of_budget(remaining, value, qty, &coin_params);
+                humanln!(
+                    "{}% of the remaining {} {} is {} {} for each of the {} codes",
+                    value, remaining, coin_params.ticker, share, coin_params.ticker, qty
+                );
+                (share, share)
+            }
+            (Some(fiat), _) => {
+                let (coins, exact) = fiat.to_coins(value, coin_params.decimals);
+                humanln!(
+                    "{} {} is {} {} (exactly {} {}, rounded half-up to {} decimals)",
+                    value, fiat.currency, coins, coin_params.ticker, exact, coin_params.ticker, coin_params.decimals
+                );
+                (coins, exact)
+            }
+            (None, _) => (value, value),
+        };
+
+        // Values must fit the coin's precision, so the value sent is exactly the value recorded
+        let (value, rounded) = round_to_coin(value, &coin_params);
+        if rounded {
+            humanln!(
+                "{}",
+                style::warning(format!(
+                    "Warning: {} only supports {} decimals, so the value was rounded to {} {}",
+                    coin_params.ticker, coin_params.decimals, value, coin_params.ticker
+                ))
             );
-            count += 1;
-            total_value += batch.value * batch.qty as f64;
-            total_codes += batch.qty;
         }
-        println!(
-            "... for a total of {} codes worth {} {}",
-            total_codes, total_value, coin_params.ticker
-        );
-        println!("----------------------------------------------");
+
+        // Notes are optional even when asked for: an empty answer means none
+        let note = if cli.batch_notes {
+            let note = ask_string(format!("Batch {}: a note for these codes (optional)", plan.len() + 1).as_str(), "");
+            Some(note.trim().to_owned()).filter(|note| !note.is_empty())
+        } else {
+            None
+        };
+
+        // Random-valued batches treat the value above as their minimum
+        let (max_value, step) = if cli.random_values {
+            let max_value = ask_float(
+                format!(
+                    "Batch {}: up to how much {} should each code be randomly worth? (leave at {} for a fixed value)",
+                    plan.len() + 1,
+                    coin_params.ticker,
+                    value
+                )
+                .as_str(),
+                value,
+            );
+            if max_value > value {
+                let step = ask_float(
+                    format!("Batch {}: in steps of how much {}?", plan.len() + 1, coin_params.ticker).as_str(),
+                    10f64.powi(-(coin_params.decimals as i32)),
+                );
+                (Some(round_to_coin(max_value, &coin_params).0), Some(step))
+            } else {
+                (None, None)
+            }
+        } else {
+            (None, None)
+        };
+
+        // Batches can't overspend the budget, however their value was entered
+        if let Some(remaining) = remaining_budget {
+            let batch_total = max_value.unwrap_or(value) * qty as f64;
+            if batch_total > remaining + f64::EPSILON * remaining.max(1.0) {
+                eprintln!(
+                    "{}",
+                    style::error(format!(
+                        "Invalid batch: its {} {} is over the remaining budget of {} {} - try again!",
+                        batch_total, coin_params.ticker, remaining, coin_params.ticker
+                    ))
+                );
+                continue;
+            }
+        }
+
+        if let Err(e) = plan.push(PromoBatch { value, qty, note, max_value, step }) {
+            eprintln!("{}", style::error(format!("Invalid batch: {} - try again!", e)));
+            continue;
+        }
+        fiat_rounding += (value - exact_value) * qty as f64;
+
+        // Clear the screen (if allowed) and log the batches
+        if should_clear {
+            clear_terminal_screen();
+        }
+        print_batches(&plan, &coin_params, fiat.as_ref());
+        if let Some(budget) = cli.budget {
+            print_budget(&plan, budget, &coin_params);
+        }
 
         // Ask if they wanna add more batches, or they're ready to start generating
-        let continue_batching = ask_bool("Would you like to add another batch?", false);
+        adding_batches = ask_bool("Would you like to add another batch?", false);
+    }
 
-        // If it's a no... break the batch creation loop and move on
-        if !continue_batching {
-            break;
+    // A plan without a single code would "succeed" without doing anything, which is never what was meant
+    if plan.total_codes() == 0 {
+        let reason = match resumed {
+            Some(_) => format!("The interrupted run has no codes left to generate (remove \"{}\" to start a new run)", state::STATE_FILE),
+            None => String::from("No codes are planned, so there's nothing to generate - plan at least one batch with one or more codes"),
+        };
+        eprintln!("{}", style::error(reason));
+        std::process::exit(1);
+    }
+
+    // Report how much rounding the fiat-entered values moved the total, so it's never a surprise
+    if let Some(fiat) = fiat_values {
+        let (fiat_rounding, _) = round_to_coin(fiat_rounding, &coin_params);
+        humanln!(
+            "Rounding fiat values to {} decimals changed the total by {}{} {} (~{})",
+            coin_params.decimals,
+            if fiat_rounding >= 0.0 { "+" } else { "" },
+            fiat_rounding,
+            coin_params.ticker,
+            fiat.format(fiat_rounding)
+        );
+    }
+
+    // Codes worth dust can never be economically redeemed, so they need an explicit go-ahead
+    let dust_batches: Vec<usize> = (0..plan.len()).filter(|&i| plan.batches[i].is_dust(&coin_params)).collect();
+    if !dust_batches.is_empty() {
+        for i in &dust_batches {
+            eprintln!(
+                "{}",
+                style::warning(format!(
+                    "Warning: batch {}'s codes can be worth {} {} or less, {}'s dust threshold: they'd cost more to redeem than they're worth!",
+                    i + 1, coin_params.dust_threshold, coin_params.ticker, coin_params.name
+                ))
+            );
+        }
+        if !cli.allow_dust && !ask_bool("Mint them anyway?", false) {
+            eprintln!("{}", style::error("Raise the value of these batches above dust, or pass --allow-dust - Quitting..."));
+            std::process::exit(1);
         }
     }
 
-    // Check if they want a prefix used
-    promo_prefix = ask_string(
-        format!(
-            "What prefix would you like to use, if any? For example: promo-{}, or, if omitted: {}",
-            get_alpha_numeric_rand(5),
-            get_alpha_numeric_rand(6)
-        )
-        .as_str(),
+    // Keep the finished plan for next time, if requested
+    if let Some(ref name) = cli.save_template {
+        match save_template(name, &plan) {
+            Ok(path) => humanln!("Saved the plan as template \"{}\" (\"{}\")", name, path.display()),
+            Err(e) => eprintln!("{}", style::error(format!("Failed to save template \"{}\": {}", name, e))),
+        }
+    }
+
+    // When diffing, compare the plan against the existing CSV and stop before generating anything
+    if let Command::PlanDiff { ref csv } = cli.command {
+        match read_csv(csv) {
+            Ok(existing) => print_plan_diff(&plan, &existing, &coin_params),
+            Err(e) => eprintln!("{}", style::error(format!("Failed to read \"{}\": {}", csv, e))),
+        }
+        humanln!("Plan-diff mode: nothing was generated or funded - Quitting...");
+        return;
+    }
+
+    // When only planning, show the full summary and stop before generating anything
+    if cli.plan_only {
+        print_plan_summary(&plan, &coin_params, fiat.as_ref());
+        if let Some(budget) = cli.budget {
+            print_budget(&plan, budget, &coin_params);
+        }
+        humanln!("Plan-only mode: nothing was generated or funded - Quitting...");
+        return;
+    }
+
+    // Check if they want a prefix used, unless it was already passed as a flag (or is the interrupted run's)
+    match resumed.as_ref().map(|state| state.prefix.clone()).or(cli.prefix) {
+        Some(prefix) => {
+            if let Err(e) = validate_prefix(&prefix, cli.check_char) {
+                eprintln!("{}", style::usage_error(format!("Invalid --prefix: {}", e)));
+                std::process::exit(1);
+            }
+            promo_prefix = prefix;
+        }
+        None => loop {
+            promo_prefix = ask_string(
+                format!(
+                    "What prefix would you like to use, if any? For example: promo-{}, or, if omitted: {}",
+                    get_alpha_numeric_rand(5),
+                    get_alpha_numeric_rand(6)
+                )
+                .as_str(),
+                &promo_prefix,
+            );
+            match validate_prefix(&promo_prefix, cli.check_char) {
+                Ok(_) => break,
+                Err(e) => {
+                    eprintln!("{}", style::warning(format!("{} - try again!", e)));
+                    promo_prefix.clear();
+                }
+            }
+        },
+    }
+    let numeric = resumed.as_ref().map_or(cli.numeric, |state| state.numeric);
+    let charset = if numeric { MAP_NUMERIC } else { MAP_ALPHANUMERIC };
+    let code_length = random_code_length(
         &promo_prefix,
+        resumed.as_ref().map(|state| state.code_length).or(cli.code_length),
+        charset,
+        cli.derivation,
     );
+    let code_format = CodeFormat {
+        prefix: promo_prefix,
+        length: code_length,
+        check_char: cli.check_char,
+        charset,
+    };
+    if numeric {
+        humanln!(
+            "Generating numeric-only codes, with {} digits each: a much smaller space per character, so watch the collision chance below",
+            code_length
+        );
+    }
+
+    // The prefix-cached derivation only makes sense with a prefix to cache
+    if cli.derivation == DerivationScheme::V2 {
+        if code_format.prefix.is_empty() {
+            eprintln!("{}", style::error("The v2 derivation scheme requires a prefix!"));
+            std::process::exit(1);
+        }
+        humanln!(
+            "{}",
+            style::warning(
+                "Warning: using the v2 (prefix-cached) derivation scheme! These codes can ONLY be redeemed by v2-aware redeemers."
+            )
+        );
+        // v2's cheaper iterations are only as safe as the extra characters that pay for them
+        let safe_length = random_code_length(&code_format.prefix, None, charset, DerivationScheme::V2);
+        if code_format.length < safe_length {
+            humanln!(
+                "{}",
+                style::warning(format!(
+                    "Warning: v2 codes with {} random characters are cheaper to brute-force than v1 codes, use at least {}!",
+                    code_format.length, safe_length
+                ))
+            );
+        }
+        humanln!("Precomputing the prefix state...");
+    }
+    // Vanity searches are costly, so make sure they're possible, and that the operator knows the cost
+    let worker_threads = cli
+        .threads
+        .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get()));
+    if let Some(ref vanity) = cli.vanity {
+        if let Err(e) = validate_vanity(vanity, &coin_params) {
+            eprintln!("{}", style::error(format!("Invalid vanity prefix: {}", e)));
+            std::process::exit(1);
+        }
+        warn_vanity_cost(vanity, worker_threads);
+    }
+
+    let mut deriver = Deriver::new(cli.derivation, &code_format.prefix);
+    if let Some(ref path) = cli.checkpoint {
+        deriver = deriver.with_checkpoint(path, &code_format);
+    }
+    if let Some(ref namespace) = cli.namespace {
+        humanln!(
+            "{}",
+            style::warning(format!(
+                "Warning: deriving in namespace \"{}\"! These codes can ONLY be redeemed by redeemers that know it.",
+                namespace
+            ))
+        );
+        deriver = deriver.with_namespace(namespace);
+    }
+
+    if cli.double_check {
+        humanln!("Double-checking the prefix state...");
+        if !deriver.check_prefix_state() {
+            eprintln!(
+                "{}",
+                style::error("The prefix state derived differently the second time! This machine may have a memory or hardware fault.")
+            );
+            std::process::exit(1);
+        }
+        deriver = deriver.with_double_check();
+    }
 
-    // Create CSV file and write header if saving is enabled
-    let csv_filename = if should_save {
-        let mut filename_with_ext = filename.clone() + ".csv";
+    // Checkpoints track a single in-progress derivation, so checkpointed runs derive one code at a time
+    let derive_threads = if cli.checkpoint.is_some() { 1 } else { worker_threads };
+
+    // Let the operator know how long the run will take (double-checking derives each code, or vanity match, twice)
+    let total_codes = plan.total_codes();
+    let check_iterations = if cli.double_check { deriver.iterations_per_code() as f64 } else { 0.0 };
+    match cli.vanity {
+        Some(ref vanity) => print_preflight(
+            total_codes,
+            expected_attempts(vanity) * deriver.iterations_per_code() as f64 + check_iterations,
+            worker_threads,
+        ),
+        None => print_preflight(
+            total_codes,
+            deriver.iterations_per_code() as f64 + check_iterations,
+            derive_threads,
+        ),
+    }
+
+    // Let the operator know how likely it is for any two codes to collide
+    let collision_chance = collision_probability(total_codes, charset.len(), code_length);
+    humanln!(
+        "Chance of any two of your {} codes colliding (with {} random characters): {:.6}%",
+        total_codes,
+        code_length,
+        collision_chance * 100.0
+    );
+    if collision_chance > COLLISION_WARN_THRESHOLD {
+        let mut suggested_length = code_length;
+        while collision_probability(total_codes, charset.len(), suggested_length) > COLLISION_WARN_THRESHOLD {
+            suggested_length += 1;
+        }
+        humanln!(
+            "{}",
+            style::warning(format!(
+                "Warning: that's above {}%! Consider a longer code length, such as \"--code-length {}\".",
+                COLLISION_WARN_THRESHOLD * 100.0,
+                suggested_length
+            ))
+        );
+    }
+
+    // Create the save file (and write its header, if the format has one) if saving is enabled
+    let csv_layout = CsvLayout {
+        signature: cli.sign_codes,
+        note: plan.has_notes(),
+        wif_hash: cli.wif_hash,
+        timestamp: cli.timestamps,
+        derivation: cli.derivation != DerivationScheme::V1,
+        target: cli.record_target,
+        namespace: cli.namespace.is_some(),
+        funding_status: cli.no_fill,
+        trailing_comma: cli.csv_trailing_comma,
+    };
+    let extension = match cli.gzip {
+        true => format!("{}.gz", cli.output_format.extension()),
+        false => cli.output_format.extension().to_owned(),
+    };
+    let save_filename = if let Some(ref state) = resumed {
+        // The interrupted run's save file already has its header (and codes), so it's appended to as-is
+        state.save_file.clone()
+    } else if should_save {
+        // Place the file in the output directory, if one was configured
+        if let Some(ref output_dir) = cli.output_dir {
+            fs::create_dir_all(output_dir).unwrap();
+            filename = std::path::Path::new(output_dir).join(&filename).to_string_lossy().into_owned();
+        }
+        let mut filename_with_ext = format!("{}.{}", filename, extension);
         
         // Check if file already exists
         if std::path::Path::new(&filename_with_ext).exists() {
-            println!("Warning: File '{}' already exists!", filename_with_ext);
-            println!("If you choose 'No', a new file with a timestamp will be created instead.");
+            humanln!("{}", style::warning(format!("Warning: File '{}' already exists!", filename_with_ext)));
+            humanln!("If you choose 'No', a new file with a timestamp will be created instead.");
             let overwrite = ask_bool("Do you want to overwrite it?", false);
             
             if !overwrite {
@@ -151,102 +960,647 @@ fn main() {
                     .duration_since(std::time::UNIX_EPOCH)
                     .unwrap()
                     .as_secs();
-                filename_with_ext = format!("{}_{}.csv", filename, timestamp);
-                println!("Creating new file: {}", filename_with_ext);
+                filename_with_ext = format!("{}_{}.{}", filename, timestamp, extension);
+                humanln!("Creating new file: {}", filename_with_ext);
             }
         }
         
-        let mut file = fs::File::create(&filename_with_ext).unwrap();
-        writeln!(file, "coin,value,code,").unwrap();
+        // Compressed saves are created along with their encoder, below
+        if !cli.gzip {
+            cli.output_format.create(&filename_with_ext, csv_layout).unwrap();
+        }
         Some(filename_with_ext)
     } else {
         None
     };
 
+    let gzip_encoder = save_filename
+        .as_deref()
+        .filter(|_| cli.gzip)
+        .map(|path| cli.output_format.create_gzip(path, csv_layout).unwrap());
+    // A continued JSON save is rewritten in full with each code, so it needs the codes it already has
+    let saved = match save_filename {
+        Some(ref path) if resumed.is_some() && cli.output_format == OutputFormat::Json => read_json_save(path).unwrap_or_else(|e| {
+            eprintln!("{}", style::error(format!("Can't read the interrupted run's save file \"{}\": {}", path, e)));
+            std::process::exit(1);
+        }),
+        _ => Vec::new(),
+    };
+    let mut outputs = OutputSink {
+        save_file: save_filename.clone(),
+        format: cli.output_format,
+        saved,
+        gzip: gzip_encoder,
+        coin_ticker: coin_params.ticker.clone(),
+        value_format: ValueFormat {
+            decimals: coin_params.decimals,
+            trim_zeros: cli.trim_values,
+        },
+        namespace: cli.namespace.clone(),
+        extra: Vec::new(),
+        wif_vault: None,
+        funding_request: None,
+        timestamps: cli.timestamps,
+        funding_status: cli.no_fill,
+        buffer: cli.buffer_writes.map(WriteBuffer::new),
+    };
+    if let Some(flush_every) = cli.buffer_writes {
+        let when = match flush_every {
+            0 => String::from("once the run finishes"),
+            _ => format!("every {} codes", flush_every),
+        };
+        humanln!(
+            "{}",
+            style::warning(format!(
+                "Warning: codes are only written out {}! A crash loses every code since, including the keys of funded ones.",
+                when
+            ))
+        );
+    }
+
+    // Create any extra save files, which are written from the same promos as the main one
+    for (path, format) in [(&cli.csv_out, OutputFormat::Csv), (&cli.json_out, OutputFormat::Json)] {
+        let Some(path) = path else { continue };
+        match ExtraSave::create(path, format, csv_layout) {
+            Ok(extra) => outputs.extra.push(extra),
+            Err(e) => {
+                eprintln!("{}", style::error(format!("Can't create \"{}\": {}", path, e)));
+                std::process::exit(1);
+            }
+        }
+    }
+    if let Some(ref path) = cli.wif_vault {
+        match WifVault::create(path, coin_params.priv_key_byte, coin_params.compressed) {
+            Ok(vault) => outputs.wif_vault = Some(vault),
+            Err(e) => {
+                eprintln!("{}", style::error(format!("Can't create the WIF vault \"{}\": {}", path, e)));
+                std::process::exit(1);
+            }
+        }
+    }
+    // Generate-only runs list each address to fund as it's recorded, so an interrupted run's request is never short
+    if cli.no_fill {
+        let path = funding_request_path(save_filename.as_deref().unwrap_or(&filename));
+        match create_funding_request(&path, resumed.is_some()) {
+            Ok(_) => outputs.funding_request = Some(path),
+            Err(e) => {
+                eprintln!("{}", style::error(format!("Can't create the funding request \"{}\": {}", path, e)));
+                std::process::exit(1);
+            }
+        }
+    }
+
+    // High-value runs need the operator to re-type the total, so nothing large is launched by habit
+    if let Some(threshold) = cli.confirm_threshold {
+        if plan.total_with_fees(&coin_params) >= threshold && !confirm_total_value(&plan, &coin_params) {
+            humanln!("Confirmation failed, nothing was generated or funded - Quitting...");
+            return;
+        }
+    }
+
+    // Real mainnet distributions need the ticker typed out, in case a test run forgot its test network
+    if let Some(guard) = cli.mainnet_guard {
+        if plan.needs_funding()
+            && !cli.no_fill
+            && plan.total_with_fees(&coin_params) >= guard
+            && !confirm_mainnet(&mut rpc, &coin_params)
+        {
+            humanln!("Confirmation failed, nothing was generated or funded - Quitting...");
+            return;
+        }
+    }
+
+    // With several wallets loaded, make sure the coins come out of the intended one
+    if cli.confirm_wallet && plan.needs_funding() && !cli.no_fill && !confirm_source_wallet(&mut rpc, &coin_params) {
+        humanln!("Confirmation failed, nothing was generated or funded - Quitting...");
+        return;
+    }
+
+    // Before funding anything, make sure the daemon can actually handle it
+    if plan.needs_funding()
+        && !cli.no_fill
+        && !check_daemon_compat(&mut rpc, &coin_params)
+        && !ask_bool("Continue anyway?", false)
+    {
+        humanln!("Quitting...");
+        return;
+    }
+
+    // Snapshot the wallet before spending anything, so there's something to fall back on if the run goes wrong
+    if let (Some(path), true) = (&cli.backup_wallet, plan.needs_funding() && !cli.no_fill) {
+        humanln!("Backing up the wallet to \"{}\"...", path);
+        match rpc.call_raw("backupwallet", serde_json::json!([path])) {
+            Ok(_) => humanln!("{}", style::success("Wallet backed up!")),
+            Err(e) => {
+                eprintln!("{}", style::error(format!("Failed to back up the wallet to \"{}\": {} - nothing was funded", path, e)));
+                std::process::exit(1);
+            }
+        }
+    }
+
     // Start generating!
-    println!("Time to begin! Please do NOT cancel or interfere with the generation process!");
-    println!("Generating...");
+    humanln!("Time to begin! Please do NOT cancel or interfere with the generation process!");
+    humanln!("Generating...");
     let mut codes: Vec<OptimisedPromoKeypair> = Vec::new();
+    let mut qr_files: Vec<String> = Vec::new();
+    let mut ledger = FillLedger::default();
+    let mut failed = match resumed {
+        Some(_) => FailedCsv::continuing(save_filename.as_deref().unwrap_or(&filename)),
+        None => FailedCsv::alongside(save_filename.as_deref().unwrap_or(&filename)),
+    };
 
-    // We'll loop each batch and decrement it's quantity as each code is generated
-    let mut batch_count = 1;
-    for mut batch in batches {
-        let mut code_count = 1;
-        // Loop each code within the batch
-        while batch.qty >= 1 {
-            let mut promo = create_promo_key(&promo_prefix, &coin_params);
-            let wif = secret_to_wif(promo.private, coin_params.priv_key_byte);
-            println!(
-                "Code {code_count} of batch {batch_count}: Promo: '{}' - Address: {} - WIF: {wif}",
-                promo.code, promo.public
+    // The Merkle root commits to the whole batch, including codes saved before an interruption
+    let prior_codes = match save_filename {
+        Some(ref path) if resumed.is_some() => read_saved_codes(path, cli.output_format).unwrap_or_else(|e| {
+            eprintln!(
+                "{}",
+                style::warning(format!("Can't read back \"{}\" ({}), so the Merkle root will only cover the remaining codes", path, e))
             );
+            Vec::new()
+        }),
+        _ => Vec::new(),
+    };
+
+    // Keep the run's state after every finished code, so `--continue` can pick it up if this process dies
+    // ... except for compressed saves, which can't be appended to once their stream is cut off
+    let keep_state = !cli.gzip;
+    let mut run_state = resumed.map(|state| RunState { fiat_rate: fiat.clone(), ..state }).unwrap_or_else(|| RunState {
+        coin: coin_params.ticker.clone(),
+        prefix: code_format.prefix.clone(),
+        code_length,
+        check_char: cli.check_char,
+        numeric,
+        derivation: cli.derivation.version(),
+        namespace: cli.namespace.clone(),
+        save_file: save_filename.clone(),
+        output_format: cli.output_format.extension().to_owned(),
+        sign_codes: cli.sign_codes,
+        wif_hash: cli.wif_hash,
+        timestamps: cli.timestamps,
+        record_target: cli.record_target,
+        csv_trailing_comma: cli.csv_trailing_comma,
+        no_fill: cli.no_fill,
+        fiat_rate: fiat.clone(),
+        batches: plan.batches.clone(),
+        progress: vec![0; plan.len()],
+    });
+    let fill_options = FillOptions {
+        min_conf: cli.min_conf,
+        subtract_fee: cli.subtract_fee,
+        log: cli.log_file.as_deref().map(RunLog::open),
+        max_attempts: if cli.strict { Some(1) } else { cli.max_fill_attempts },
+        simulate_failures: cli.simulate_failures,
+        avoid_clustering: cli.avoid_clustering,
+        on_failure: cli.on_failure,
+        fallback: cli.on_failure_fallback,
+        aborted: Cell::new(false),
+    };
+
+    // Open the progress stream, if requested
+    let mut progress = cli.progress_json.as_deref().map(|path| {
+        ProgressReporter::open(path, total_codes, plan.len()).unwrap_or_else(|e| {
+            eprintln!("{}", style::error(format!("Can't open the progress stream \"{}\": {}", path, e)));
+            std::process::exit(1);
+        })
+    });
+
+    // Derive codes on worker threads, while this thread signs, funds and saves them in order
+    std::thread::scope(|scope| {
+        // Vanity searches parallelise each code on their own, so they derive nothing here
+        let derive_total = if cli.vanity.is_some() { 0 } else { total_codes };
+        let mut derived = OrderedPromos::start(scope, derive_total, derive_threads, &code_format, &deriver, &coin_params);
+
+        // We'll loop each batch and decrement it's quantity as each code is generated
+        let mut batch_count = 1;
+        let batch_total = plan.batches.len();
+        for mut batch in plan.batches {
+            let failed_before = failed.count;
+            // Codes finished before an interruption still count, both for numbering and the saved progress
+            let done_before = run_state.progress[batch_count - 1];
+            let batch_start = codes.len();
+            let mut code_count = done_before + 1;
+            // When aggregating fills, codes wait here until there's enough for a `sendmany`
+            let mut pending: Vec<OptimisedPromoKeypair> = Vec::new();
+            let mut pending_values: Vec<f64> = Vec::new();
+            // Loop each code within the batch
+            while batch.qty >= 1 {
+                let mut promo = match cli.vanity {
+                    Some(ref vanity) => find_vanity_promo(&code_format, &deriver, &coin_params, vanity, worker_threads),
+                    None => derived.next_promo(),
+                };
+                if csv_layout.note {
+                    promo.note = Some(batch.note.clone().unwrap_or_default());
+                }
+                let wif = secret_to_wif(promo.private, coin_params.priv_key_byte, coin_params.compressed);
+                if wif_to_address(&wif, &coin_params).as_ref() != Ok(&promo.public) {
+                    eprintln!("{}", style::error(format!("The WIF of code '{}' doesn't decode back to its address!", style::code(&promo.code))));
+                    std::process::exit(1);
+                }
+                if cli.wif_hash {
+                    promo.wif_hash = Some(hash::wif_hash(&wif));
+                }
+                if cli.record_target {
+                    promo.target = Some(deriver.target());
+                }
+                humanln!(
+                    "Code {code_count} of batch {batch_count}: Promo: '{}' - Address: {} - WIF: {}",
+                    style::code(&promo.code),
+                    promo.public,
+                    style::wif(&wif)
+                );
+
+                // Never continue (let alone fund) if the address isn't what the coin's addresses look like
+                if let Err(e) = coin_params.check_address(&promo.public) {
+                    eprintln!("{}", style::error(e));
+                    std::process::exit(1);
+                }
+
+                // If requested, prove we controlled this key at mint time by signing the code with it
+                if cli.sign_codes {
+                    let signature = sign_message(&promo.private, &promo.code);
+                    humanln!(" - Signature: {}", signature);
+                    promo.signature = Some(signature);
+                }
+
+                // A random-valued batch picks each code's value as it's generated
+                let value = batch.pick_value(&coin_params);
+
+                // Save a printable QR code of the promo (or of its payment URI), if requested
+                if let Some(ref qr_dir) = cli.qr_dir {
+                    let payload = match cli.qr_payload {
+                        QrPayload::Code => promo.code.clone(),
+                        // The coin is checked for a URI scheme up-front
+                        QrPayload::Uri => coin_params.payment_uri(&promo.public, value).unwrap_or_default(),
+                    };
+                    match save_qr(qr_dir, &promo.code, &payload, cli.qr_format) {
+                        Ok(path) => {
+                            humanln!(" - QR: {}", path);
+                            qr_files.push(path);
+                        }
+                        Err(e) => eprintln!("{}", style::error(format!(" - Failed to save QR code: {}", e))),
+                    }
+                }
+
+                // Save a cold-storage backup QR of the promo's WIF, if requested (never bundled with the public QRs)
+                if let Some(ref wif_qr_dir) = cli.wif_qr_dir {
+                    match save_secret_qr(wif_qr_dir, &promo.public, &wif, cli.qr_format) {
+                        Ok(path) => humanln!(" - WIF QR: {}", path),
+                        Err(e) => eprintln!("{}", style::error(format!(" - Failed to save WIF QR code: {}", e))),
+                    }
+                }
 
-            // If these codes have value, fill 'em!
-            if batch.value > 0.0 {
-                println!(" - Filling with {} {}...", batch.value, coin_params.ticker);
-
-                // Attempt filling the code's address
-                loop {
-                    match rpc.sendtoaddress(
-                        &promo.public,
-                        batch.value + coin_params.promo_fee,
-                        Some(&format!("{} Promos pre-fill", coin_params.name)),
-                        Some(""),
-                        Some(false),
-                    ) {
-                        Ok(tx_id) => {
-                            println!(" - TX: {}", tx_id);
-                            promo.value = batch.value;
-                            break;
+                if value > 0.0 && cli.no_fill {
+                    // Record the intended value, leaving the code unfunded (with no TX ID) for a later funding run
+                    promo.value = value;
+                    outputs.record(&mut promo).unwrap();
+                    codes.push(promo);
+                } else if value > 0.0 && cli.sendmany {
+                    // Hold it back, and fill the whole group at once when it's full (or the batch ends)
+                    pending.push(promo);
+                    pending_values.push(value);
+                    if pending.len() >= cli.max_outputs || batch.qty == 1 {
+                        let result = fill_many(&mut rpc, &mut pending, &pending_values, &coin_params, &fill_options, &mut ledger);
+                        if cli.explorer_links {
+                            // The whole group shares a single TX
+                            print_explorer_link(&pending[0], &coin_params);
+                        }
+                        for (mut promo, value) in pending.drain(..).zip(pending_values.drain(..)) {
+                            if let Err(ref e) = result {
+                                failed.record(&promo, value, e).unwrap();
+                                if cli.strict || fill_options.aborted.get() {
+                                    eprintln!(
+                                        "{}",
+                                        style::error(format!(" - Unfunded: '{}' ({}) for {} {}", style::code(&promo.code), promo.public, value, coin_params.ticker))
+                                    );
+                                }
+                            }
+                            outputs.record(&mut promo).unwrap();
+                            codes.push(promo);
                         }
-                        Err(e) => {
-                            eprintln!(
-                                " - TX failed with error: \"{}\". Retrying in 10 seconds...",
-                                e
-                            );
-                            std::thread::sleep(std::time::Duration::from_secs(10));
+                        if let (Err(e), true) = (result, cli.strict || fill_options.aborted.get()) {
+                            abort_strict(&e, &failed, &mut outputs, &mut ledger, &mut rpc);
+                        }
+                    }
+                } else {
+                    // If these codes have value, fill 'em!
+                    if value > 0.0 {
+                        if let Err(e) = fill_promo(&mut rpc, &mut promo, value, &coin_params, &fill_options, &mut ledger) {
+                            failed.record(&promo, value, &e).unwrap();
+                            if cli.strict || fill_options.aborted.get() {
+                                eprintln!(
+                                    "{}",
+                                    style::error(format!(" - Unfunded: '{}' ({}) for {} {}", style::code(&promo.code), promo.public, value, coin_params.ticker))
+                                );
+                                outputs.record(&mut promo).unwrap();
+                                abort_strict(&e, &failed, &mut outputs, &mut ledger, &mut rpc);
+                            }
+                        }
+                        if cli.explorer_links {
+                            print_explorer_link(&promo, &coin_params);
+                        }
+                    }
+
+                    // Save and push this promo
+                    outputs.record(&mut promo).unwrap();
+                    codes.push(promo);
+                }
+
+                // Decrement batch quantity
+                batch.qty -= 1;
+                code_count += 1;
+                if let Some(ref mut progress) = progress {
+                    progress.code_done(batch_count);
+                }
+
+                // Persist how far the run got, only counting codes that are fully saved (and funded):
+                // buffered codes only count once they're written out
+                if keep_state {
+                    run_state.progress[batch_count - 1] = done_before + (codes.len() - batch_start) as u64;
+                    if outputs.is_flushed() {
+                        if let Err(e) = run_state.save() {
+                            eprintln!("{}", style::warning(format!(" - Couldn't save the run's state: {}", e)));
                         }
                     }
                 }
             }
 
-            // Append to CSV file immediately if saving is enabled
-            if let Some(ref csv_file) = csv_filename {
-                let mut file = OpenOptions::new()
-                    .append(true)
-                    .open(csv_file)
-                    .unwrap();
-                writeln!(file, "{},{},{}", coin_params.ticker.to_lowercase(), promo.value, promo.code).unwrap();
+            // Give the operator a checkpoint between batches, unless this one was already finished before an interruption
+            if cli.pause_between_batches && batch_count < batch_total && codes.len() > batch_start {
+                let batch_codes = &codes[batch_start..];
+                let funded: Vec<&OptimisedPromoKeypair> = batch_codes.iter().filter(|promo| promo.tx_id.is_some()).collect();
+                let (funded_value, _) = round_to_coin(funded.iter().map(|promo| promo.value).sum(), &coin_params);
+                humanln!("----------------------------------------------");
+                humanln!(
+                    "Batch {} of {} done: {} codes, {} funded (worth {} {}), {} failed",
+                    batch_count, batch_total, batch_codes.len(), funded.len(), funded_value, coin_params.ticker,
+                    failed.count - failed_before
+                );
+                humanln!("----------------------------------------------");
+                if !ask_bool(format!("Continue with batch {}?", batch_count + 1).as_str(), true) {
+                    pause_run(batch_count, keep_state, &run_state, &mut outputs, &mut ledger, &mut rpc);
+                }
             }
+            batch_count += 1;
+        }
+    });
 
-            // Push this promo
-            codes.push(promo);
+    // Hand any change locked to avoid clustering back to the wallet
+    ledger.change_locks.unlock_all(&mut rpc);
 
-            // Decrement batch quantity
-            batch.qty -= 1;
-            code_count += 1;
+    // Complete the save file (only compressed saves need it)
+    outputs.finish().unwrap();
+    if let Some(ref mut progress) = progress {
+        progress.finish();
+    }
+
+    // The run is complete, so there's nothing left to continue
+    if keep_state {
+        if let Err(e) = RunState::remove() {
+            eprintln!("{}", style::warning(format!("Couldn't remove \"{}\": {}", state::STATE_FILE, e)));
         }
-        batch_count += 1;
     }
 
-    // CSV already saved during generation if enabled
-    if should_save {
-        if let Some(ref csv_file) = csv_filename {
-            println!("Saved batch as \"{}\"!", csv_file);
+    // Hold off on reporting the run as done until its funds are settled, if asked to
+    if let Some(confirmations) = cli.wait_confirmations {
+        let tx_ids: Vec<String> = codes.iter().filter_map(|promo| promo.tx_id.clone()).collect();
+        if !tx_ids.is_empty() {
+            wait_for_confirmations(&mut rpc, &tx_ids, confirmations, cli.poll_interval.unwrap_or(DEFAULT_POLL_INTERVAL));
+        }
+    }
+
+    // Commit to the whole batch with a Merkle root, which can be published without revealing any codes
+    let all_codes = prior_codes.iter().map(String::as_str).chain(codes.iter().map(|promo| promo.code.as_str()));
+    let merkle_root = MerkleTree::new(all_codes).root();
+    if let Some(root) = merkle_root {
+        humanln!("Merkle root of the batch's {} codes: {}", prior_codes.len() + codes.len(), root.to_hex());
+        if let Some(ref save_file) = save_filename {
+            let root_path = format!("{}_merkle_root.txt", std::path::Path::new(save_file).with_extension("").to_string_lossy());
+            match fs::write(&root_path, format!("{}\n", root.to_hex())) {
+                Ok(_) => humanln!("Saved the Merkle root as \"{}\", prove a code's inclusion with `batcher prove <code> {}`", root_path, save_file),
+                Err(e) => eprintln!("{}", style::error(format!("Failed to save the Merkle root to \"{}\": {}", root_path, e))),
+            }
+        }
+    }
+
+    // Export the codes within the value range as a separate CSV, leaving the save file complete
+    if cli.value_range.is_set() {
+        match save_filename {
+            Some(ref save_file) if cli.output_format == OutputFormat::Csv => {
+                let stem = save_file.strip_suffix(&format!(".{}", extension)).unwrap_or(save_file);
+                let filtered_path = format!("{}_filtered.{}", stem, extension);
+                match filter_csv(save_file, &filtered_path, cli.value_range) {
+                    Ok((kept, total)) => humanln!(
+                        "Exported the {} of {} codes {} to \"{}\"",
+                        kept, total, cli.value_range.describe(&coin_params.ticker), filtered_path
+                    ),
+                    Err(e) => eprintln!("{}", style::error(format!("Failed to export the filtered codes: {}", e))),
+                }
+            }
+            Some(_) => eprintln!("{}", style::warning("Value filters only apply to CSV saves, so nothing was filtered")),
+            None => eprintln!("{}", style::warning("Nothing was saved, so there are no codes to filter")),
+        }
+    }
+
+    // Summarise what this run generated, in coin and (if a rate was given) fiat
+    let (generated_value, _) = round_to_coin(codes.iter().map(|promo| promo.value).sum(), &coin_params);
+    humanln!(
+        "Generated {} codes worth {} {}{}",
+        codes.len(), generated_value, coin_params.ticker,
+        fiat.as_ref().map(|fiat| format!(" (~{})", fiat.format(generated_value))).unwrap_or_default()
+    );
+    if let Some(ref fiat) = fiat {
+        humanln!("Fiat rate: {}", fiat.describe(&coin_params.ticker));
+    }
+
+    // Failed codes exist (and are saved) unfunded, so make sure the operator knows where to follow up
+    if failed.count > 0 {
+        eprintln!(
+            "{}",
+            style::warning(format!(
+                "{} codes couldn't be funded! They're listed in \"{}\", which can be retried with the \"fund\" command",
+                failed.count, failed.path
+            ))
+        );
+    }
+
+    // Generate-only runs hand the addresses to fund over to an online machine, without any secrets
+    if let Some(ref request_path) = outputs.funding_request {
+        match count_funding_request(request_path) {
+            Ok(count) => humanln!(
+                "Saved a funding request for {} addresses as \"{}\", fund it from an online machine with `batcher fund {}`",
+                count, request_path, request_path
+            ),
+            Err(e) => eprintln!("{}", style::error(format!("Failed to read back the funding request \"{}\": {}", request_path, e))),
+        }
+    }
+
+    // Export the keys for importing into a backup wallet, if requested
+    if let Some(ref path) = cli.export_keys {
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        match export_wallet_keys(path, &codes, &coin_params, cli.export_keys_format, timestamp) {
+            Ok(_) => humanln!("Exported {} keys for wallet import to \"{}\"!", codes.len(), path),
+            Err(e) => eprintln!("{}", style::error(format!("Failed to export keys to \"{}\": {}", path, e))),
+        }
+    }
+
+    // Export the codes in the redeemer's own format, if requested
+    if let Some(ref path) = cli.redeemer_export {
+        match export_redeemer_codes(path, &codes, &coin_params, cli.no_fill) {
+            Ok(count) => humanln!("Exported {} codes for the redeemer to \"{}\"!", count, path),
+            Err(e) => eprintln!("{}", style::error(format!("Failed to export codes to \"{}\": {}", path, e))),
+        }
+        if cli.derivation != DerivationScheme::V1 || cli.namespace.is_some() {
+            humanln!(
+                "{}",
+                style::warning("Warning: these codes use v2 derivation or a namespace, so only a redeemer that supports them can redeem them!")
+            );
+        }
+    }
+
+    // Export the codes' payment URIs, if requested
+    if let Some(ref path) = cli.uri_export {
+        match export_payment_uris(path, &codes, &coin_params, cli.no_fill) {
+            Ok(count) => humanln!("Exported {} payment URIs to \"{}\"!", count, path),
+            Err(e) => eprintln!("{}", style::error(format!("Failed to export payment URIs to \"{}\": {}", path, e))),
+        }
+    }
+
+    // Import the promo addresses as watch-only, so the wallet can monitor redemptions
+    if cli.watch_only {
+        import_watch_only(&mut rpc, &codes, &coin_params, cli.rescan);
+    }
+
+    // Batch already saved during generation if enabled: otherwise, these codes only exist in this terminal
+    match save_filename {
+        Some(ref save_file) => humanln!("Saved batch as \"{}\"!", save_file),
+        None => confirm_codes_recorded(&codes, &coin_params.ticker),
+    }
+
+    for extra in &outputs.extra {
+        humanln!("Also saved batch as \"{}\"!", extra.path);
+    }
+    if let Some(ref vault) = outputs.wif_vault {
+        humanln!(
+            "{}",
+            style::warning(format!("Saved every key to \"{}\", keep it offline (or delete it once it's no longer needed)!", vault.path))
+        );
+    }
+
+    // Bundle the outputs into an encrypted archive, if requested
+    if cli.zip {
+        match save_filename {
+            Some(ref save_file) => {
+                let archive_path = save_file.trim_end_matches(&format!(".{}", extension)).to_string() + ".zip";
+                let password = archive::ask_password();
+                let mut files = vec![save_file.to_owned()];
+                files.extend(qr_files.iter().cloned());
+                match archive::zip_encrypted(&archive_path, &files, &password) {
+                    Ok(_) => humanln!(
+                        "Saved encrypted archive as \"{}\"! Remember to remove the unencrypted \"{}\" once it's no longer needed.",
+                        archive_path, save_file
+                    ),
+                    Err(e) => eprintln!(
+                        "{}",
+                        style::error(format!("Failed to create archive \"{}\": {}", archive_path, e))
+                    ),
+                }
+            }
+            None => eprintln!("{}", style::error("Nothing was saved, so there's nothing to ZIP!")),
         }
     }
 
-    println!("Finished! - Quitting...");
+    // Orchestrating programs get the run's outcome as a single JSON line
+    if style::json_errors() {
+        let summary = serde_json::json!({
+            "type": "summary",
+            "coin": coin_params.ticker,
+            "codes": codes.len(),
+            "value": generated_value,
+            "failed": failed.count,
+            "failed_file": if failed.count > 0 { Some(&failed.path) } else { None },
+            "save_file": save_filename,
+            "merkle_root": merkle_root.map(|root| root.to_hex()),
+        });
+        eprintln!("{}", summary);
+    }
+
+    humanln!("Finished! - Quitting...");
+}
+
+/// Whether interactive numbers are entered with a decimal comma, decided once at startup
+static DECIMAL_COMMA: AtomicBool = AtomicBool::new(false);
+
+/// Parses an interactively entered number, with either a decimal point or (in decimal comma mode) a decimal comma.
+///
+/// Input that could mean two different numbers (such as "1,000" without decimal comma mode, or
+/// "1.000,5" with it) is rejected rather than guessed at.
+///
+/// # Returns
+///
+/// The number, or why the input was rejected.
+///
+pub fn parse_decimal(input: &str, decimal_comma: bool) -> Result<f64, String> {
+    let normalised = if decimal_comma {
+        if input.contains('.') {
+            return Err(String::from("use a comma for decimals (\".\" is ambiguous in decimal comma mode)"));
+        }
+        if input.matches(',').count() > 1 {
+            return Err(String::from("a number can only have one decimal comma"));
+        }
+        input.replace(',', ".")
+    } else {
+        if input.contains(',') {
+            return Err(String::from(
+                "use a point for decimals (run with --decimal-comma to enter commas instead)",
+            ));
+        }
+        input.to_owned()
+    };
+    normalised.parse().map_err(|_| format!("\"{}\" is not a number", input))
+}
+
+/// Asks for a value, which may instead be given as a percentage (e.g. "20%").
+///
+/// # Returns
+///
+/// The number entered, and whether it was a percentage (always within 0 to 100).
+///
+pub fn ask_value_or_percent(question: &str, default: f64) -> (f64, bool) {
+    let decimal_comma = DECIMAL_COMMA.load(Ordering::Relaxed);
+    loop {
+        let answer = ask_string(question, &default.to_string());
+        let (number, percent) = match answer.strip_suffix('%') {
+            Some(number) => (number.trim(), true),
+            None => (answer.as_str(), false),
+        };
+        match parse_decimal(number, decimal_comma) {
+            Ok(number) if percent && !(number > 0.0 && number <= 100.0) => {
+                eprintln!("{}", style::warning("A percentage must be above 0% and at most 100% - try again!"))
+            }
+            Ok(number) if number >= 0.0 => return (number, percent),
+            Ok(_) => eprintln!("{}", style::warning("Weird answer... try again!")),
+            Err(e) => eprintln!("{}", style::warning(format!("{} - try again!", e))),
+        }
+    }
 }
 
 pub fn ask_float(question: &str, default: f64) -> f64 {
-    println!("{question} (default: \"{default}\")");
+    let decimal_comma = DECIMAL_COMMA.load(Ordering::Relaxed);
+    let default_text = if decimal_comma {
+        default.to_string().replace('.', ",")
+    } else {
+        default.to_string()
+    };
+    humanln!("{question} (default: \"{default_text}\")");
 
     // We run this in a loop; incase the user enters a weird non-number; we'll catch it, tell them to stop being stupid, and ask again
     let mut float_answer = default;
     loop {
-        print!("{default}: ");
-        io::stdout().flush().unwrap_or_default();
+        human!("{default_text}: ");
+        style::flush_human();
 
         // Wait for input
         let mut answer = String::new();
@@ -259,9 +1613,13 @@ pub fn ask_float(question: &str, default: f64) -> f64 {
             break;
         }
 
-        // Attempt to parse the float
-        float_answer = match answer.parse() {
+        // Attempt to parse the float, asking again if it's ambiguous
+        float_answer = match parse_decimal(&answer, decimal_comma) {
             Ok(number) => number,
+            Err(e) if answer.contains([',', '.']) => {
+                eprintln!("{}", style::warning(format!("Ambiguous answer: {} - try again!", e)));
+                continue;
+            }
             Err(_) => 0.0,
         };
 
@@ -269,21 +1627,21 @@ pub fn ask_float(question: &str, default: f64) -> f64 {
         if float_answer >= 0.0 {
             break;
         } else {
-            eprintln!("Weird answer... try again!");
+            eprintln!("{}", style::warning("Weird answer... try again!"));
         }
     }
 
     // Add some natural spacing
-    println!("");
+    humanln!("");
 
     // Return our glorious float
     float_answer
 }
 
 pub fn ask_string(question: &str, default: &str) -> String {
-    println!("{question} (default: \"{default}\")");
-    print!("{default}: ");
-    io::stdout().flush().unwrap_or_default();
+    humanln!("{question} (default: \"{default}\")");
+    human!("{default}: ");
+    style::flush_human();
 
     // Wait for input
     let mut answer = String::new();
@@ -292,7 +1650,7 @@ pub fn ask_string(question: &str, default: &str) -> String {
     answer = answer.trim().to_string();
 
     // Add some natural spacing
-    println!("");
+    humanln!("");
 
     // If it's empty: use the default
     if answer.is_empty() {
@@ -302,36 +1660,287 @@ pub fn ask_string(question: &str, default: &str) -> String {
     }
 }
 
-pub fn ask_bool(question: &str, default: bool) -> bool {
-    let default_answer_string = match default {
-        true => "Y/n",
-        false => "y/N",
-    };
-    println!("{question}");
-    print!("{default_answer_string}: ");
-    io::stdout().flush().unwrap_or_default();
-
-    // Wait for input
-    let mut answer = String::new();
-    let stdin = std::io::stdin();
-    stdin.read_line(&mut answer).unwrap_or_default();
-    // Trim and lowercase it for simplicity
-    answer = answer.trim().to_string().to_ascii_lowercase();
+pub fn ask_bool(question: &str, default: bool) -> bool {
+    let default_answer_string = match default {
+        true => "Y/n",
+        false => "y/N",
+    };
+    humanln!("{question}");
+    human!("{default_answer_string}: ");
+    style::flush_human();
+
+    // Wait for input
+    let mut answer = String::new();
+    let stdin = std::io::stdin();
+    stdin.read_line(&mut answer).unwrap_or_default();
+    // Trim and lowercase it for simplicity
+    answer = answer.trim().to_string().to_ascii_lowercase();
+
+    // Add some natural spacing
+    humanln!("");
+
+    // Check if Yes/No - a non-matching answer will use default
+    match answer.as_str() {
+        "y" => true,
+        "n" => false,
+        _ => default,
+    }
+}
+
+/// Stops a run at a failed fill (in strict mode, or when the operator chose to abort), exiting non-zero.
+///
+/// Every code generated so far (including the failed one) has already been saved, so this only
+/// completes the save file, releases any locked change and explains where things stand.
+///
+fn abort_strict(
+    error: &str,
+    failed: &FailedCsv,
+    outputs: &mut OutputSink,
+    ledger: &mut FillLedger,
+    rpc: &mut ReconnectingRpc,
+) -> ! {
+    ledger.change_locks.unlock_all(rpc);
+    if let Err(e) = outputs.finish() {
+        eprintln!("{}", style::error(format!("Failed to complete the save file: {}", e)));
+    }
+    eprintln!(
+        "{}",
+        style::error(format!(
+            "Aborting the run on a failed fill (\"{}\"). The unfunded code(s) are listed in \"{}\"",
+            error, failed.path
+        ))
+    );
+    std::process::exit(1);
+}
+
+/// Stops a run at the operator's request between batches, leaving it to be picked up with `--continue`.
+///
+/// Every code so far is already saved (and funded), so this writes out the save file and the run's
+/// state, and releases any locked change.
+///
+fn pause_run(
+    batch: usize,
+    keep_state: bool,
+    run_state: &RunState,
+    outputs: &mut OutputSink,
+    ledger: &mut FillLedger,
+    rpc: &mut ReconnectingRpc,
+) -> ! {
+    ledger.change_locks.unlock_all(rpc);
+    if let Err(e) = outputs.finish() {
+        eprintln!("{}", style::error(format!("Failed to complete the save file: {}", e)));
+        std::process::exit(1);
+    }
+    if !keep_state {
+        humanln!("{}", style::warning(format!("Stopped after batch {}. Compressed runs can't be continued, so the rest of the plan was dropped.", batch)));
+        std::process::exit(0);
+    }
+    if let Err(e) = run_state.save() {
+        eprintln!("{}", style::error(format!("Couldn't save the run's state: {}", e)));
+        std::process::exit(1);
+    }
+    humanln!("Stopped after batch {}. Pick the rest of the plan up with \"--continue\" - Quitting...", batch);
+    std::process::exit(0);
+}
+
+/// Prints the block explorer link of a promo's funding TX, if it was funded and the coin has a known explorer
+fn print_explorer_link(promo: &OptimisedPromoKeypair, coin_params: &CoinParams) {
+    if let Some(link) = promo.tx_id.as_deref().and_then(|tx_id| coin_params.explorer_link(tx_id)) {
+        humanln!(" - Explorer: {}", link);
+    }
+}
+
+/// Prints every batch in a plan, with the running totals (and their fiat equivalent, if a rate was given)
+fn print_batches(plan: &BatchPlan, coin_params: &CoinParams, fiat: Option<&FiatRate>) {
+    humanln!("----------------------------------------------");
+    for (i, batch) in plan.batches.iter().enumerate() {
+        let value = match batch.max_value {
+            Some(max_value) => format!("{} to {}", batch.value, max_value),
+            None => batch.value.to_string(),
+        };
+        humanln!(
+            " - Batch {}: {} codes of {} {}{}",
+            i + 1, batch.qty, value, coin_params.ticker,
+            batch.note.as_ref().map(|note| format!(" ({})", note)).unwrap_or_default()
+        );
+    }
+    humanln!(
+        "... for a total of {} codes worth {} {}{}",
+        plan.total_codes(), plan.total_value(), coin_params.ticker,
+        fiat.map(|fiat| format!(" (~{})", fiat.format(plan.total_value()))).unwrap_or_default()
+    );
+    if let Some(fiat) = fiat {
+        humanln!("Fiat rate: {}", fiat.describe(&coin_params.ticker));
+    }
+    humanln!("----------------------------------------------");
+}
+
+/// Prints how a plan draws down its budget, batch by batch
+fn print_budget(plan: &BatchPlan, budget: f64, coin_params: &CoinParams) {
+    humanln!("Budget: {} {}", budget, coin_params.ticker);
+    let mut remaining = budget;
+    for (i, batch) in plan.batches.iter().enumerate() {
+        let spent = batch.max_value() * batch.qty as f64;
+        remaining = round_to_coin(remaining - spent, coin_params).0;
+        humanln!(
+            " - Batch {}: {} {} ({:.1}%), leaving {} {}",
+            i + 1, spent, coin_params.ticker, spent / budget * 100.0, remaining, coin_params.ticker
+        );
+    }
+    if remaining < 0.0 {
+        humanln!("{}", style::warning(format!("The batches are over budget by {} {}!", -remaining, coin_params.ticker)));
+    }
+    humanln!("----------------------------------------------");
+}
+
+/// Prints a full summary of a planned run: its totals, the balance it requires, and its estimated runtime.
+///
+/// # Arguments
+///
+/// * `plan` - The planned batches.
+/// * `coin_params` - A reference to the CoinParams for the selected coin.
+/// * `fiat` - The fiat rate to show the totals' equivalents in, if one was given.
+///
+pub fn print_plan_summary(plan: &BatchPlan, coin_params: &CoinParams, fiat: Option<&FiatRate>) {
+    let total_codes = plan.total_codes();
+    let funded_codes = plan.funded_codes();
+    let total_fees = plan.total_fees(coin_params);
+
+    humanln!("Benchmarking derivation speed...");
+    let hash_rate = benchmark_hash_rate();
+    let runtime = estimate_runtime(total_codes, *PROMO_TARGETS.last().unwrap(), hash_rate);
+
+    humanln!("----------------------------------------------");
+    humanln!("Total codes:      {}", total_codes);
+    let in_fiat = |coins: f64| fiat.map(|fiat| format!(" (~{})", fiat.format(coins))).unwrap_or_default();
+    humanln!("Total value:      {} {}{}", plan.total_value(), coin_params.ticker, in_fiat(plan.total_value()));
+    humanln!(
+        "Redeemer fees:    {} {}{} ({} funded codes at {} {})",
+        total_fees, coin_params.ticker, in_fiat(total_fees), funded_codes, coin_params.promo_fee, coin_params.ticker
+    );
+    humanln!(
+        "Required balance: {} {}{} (plus the wallet's own TX fees)",
+        plan.total_with_fees(coin_params),
+        coin_params.ticker,
+        in_fiat(plan.total_with_fees(coin_params))
+    );
+    if let Some(fiat) = fiat {
+        humanln!("Fiat rate:        {}", fiat.describe(&coin_params.ticker));
+    }
+    humanln!(
+        "Estimated time:   {} (at {:.0} hashes/s)",
+        format_duration(runtime),
+        hash_rate
+    );
+    humanln!("----------------------------------------------");
+}
+
+/// Asks the operator to re-type a plan's total cost, as a second confirmation before a high-value run.
+///
+/// # Returns
+///
+/// `true` if the re-typed total matches.
+///
+pub fn confirm_total_value(plan: &BatchPlan, coin_params: &CoinParams) -> bool {
+    let total = plan.total_with_fees(coin_params);
+    humanln!(
+        "{}",
+        style::warning(format!(
+            "This is a high-value run: {} {} (including redeemer fees) will be sent!",
+            total, coin_params.ticker
+        ))
+    );
+    let answer = ask_string(
+        format!("To confirm, please type the total {} amount exactly", coin_params.ticker).as_str(),
+        "",
+    );
+    match answer.parse::<f64>() {
+        Ok(typed) => (typed - total).abs() < 1e-8,
+        Err(_) => false,
+    }
+}
+
+/// Asks the operator to type the coin's ticker before spending real coins on mainnet.
+///
+/// A daemon that can't be queried is treated as mainnet, since this is a safety check. Test
+/// networks (and regtest) skip the confirmation.
+///
+/// # Returns
+///
+/// `true` if the run isn't on mainnet, or the ticker was typed correctly.
+///
+pub fn confirm_mainnet(rpc: &mut ReconnectingRpc, coin_params: &CoinParams) -> bool {
+    let chain = daemon_chain(rpc);
+    if chain.as_deref().is_some_and(|chain| chain != "main") {
+        humanln!("Daemon is on the \"{}\" network, no mainnet confirmation needed", chain.unwrap());
+        return true;
+    }
+    humanln!(
+        "{}",
+        style::warning(format!(
+            "This run will spend REAL {} on mainnet{}!",
+            coin_params.ticker,
+            if chain.is_none() { " (the daemon's network couldn't be checked)" } else { "" }
+        ))
+    );
+    let answer = ask_string(format!("To confirm, please type \"{}\"", coin_params.ticker).as_str(), "");
+    answer.trim().eq_ignore_ascii_case(&coin_params.ticker)
+}
 
-    // Add some natural spacing
-    println!("");
+/// Shows which wallet a run will be funded from, and its balance, then asks the operator to confirm it.
+///
+/// Daemons with several wallets loaded fund from whichever the RPC points at, so this makes sure it's
+/// the intended one before any coins leave it. A wallet that can't be queried is never confirmed.
+///
+/// # Returns
+///
+/// `true` if the operator confirmed the wallet.
+///
+pub fn confirm_source_wallet(rpc: &mut ReconnectingRpc, coin_params: &CoinParams) -> bool {
+    let info = match rpc.call_raw("getwalletinfo", serde_json::json!([])) {
+        Ok(info) => info,
+        Err(e) => {
+            eprintln!("{}", style::error(format!("Couldn't look up the funding wallet: {}", e)));
+            return false;
+        }
+    };
+    // Single-wallet daemons (and older ones) leave the name out, or empty for the default wallet
+    let name = match info["walletname"].as_str() {
+        Some(name) if !name.is_empty() => format!("\"{}\"", name),
+        _ => String::from("the default wallet"),
+    };
+    humanln!(
+        "{}",
+        style::warning(format!(
+            "Funds will be sent from {}, which holds {} {}",
+            name,
+            info["balance"].as_f64().unwrap_or_default(),
+            coin_params.ticker
+        ))
+    );
+    ask_bool("Is this the wallet you meant to fund from?", false)
+}
 
-    // Check if Yes/No - a non-matching answer will use default
-    match answer.as_str() {
-        "y" => true,
-        "n" => false,
-        _ => default,
+/// Recaps every code of an unsaved run, and waits until the operator confirms they've recorded them.
+///
+/// Without a saved file, any funded codes are lost once the terminal is closed, so this gate makes
+/// sure that's never by accident.
+///
+pub fn confirm_codes_recorded(promos: &[OptimisedPromoKeypair], coin_ticker: &str) {
+    humanln!("{}", style::warning("Nothing was saved! Here are all of your codes, for copying:"));
+    humanln!("----------------------------------------------");
+    for promo in promos {
+        humanln!(" - {} ({} {})", promo.code, promo.value, coin_ticker);
+    }
+    humanln!("----------------------------------------------");
+    while !ask_bool("Have you recorded all of these codes? They will NOT be shown again!", false) {
+        humanln!("Please record them before continuing.");
     }
 }
 
 /// Clear (wipe) the terminal screen
 pub fn clear_terminal_screen() {
-    print!("{esc}c", esc = 27 as char);
+    human!("{esc}c", esc = 27 as char);
 }
 
 /// Converts a secret key into Wallet Import Format (WIF).
@@ -340,19 +1949,22 @@ pub fn clear_terminal_screen() {
 ///
 /// * `privkey` - The secret key to be converted.
 /// * `version_byte` - The version byte for the WIF format (coin-specific).
+/// * `compressed` - Whether the key's address uses the compressed public key (coin-specific).
 ///
 /// # Returns
 ///
 /// The secret key in WIF format as a string.
 ///
-pub fn secret_to_wif(privkey: SecretKey, version_byte: u8) -> String {
+pub fn secret_to_wif(privkey: SecretKey, version_byte: u8, compressed: bool) -> String {
     // Convert into byte format
     let privkey_bytes = privkey.secret_bytes();
 
-    // Format the byte payload into WIF format
+    // Format the byte payload into WIF format, flagged as compressed if the coin's wallets expect it
     let mut wif_bytes = vec![version_byte];
     wif_bytes.extend_from_slice(&privkey_bytes);
-    wif_bytes.extend_from_slice(&[1]);
+    if compressed {
+        wif_bytes.push(WIF_COMPRESSION_FLAG);
+    }
 
     // Concat the WIF bytes with it's SHA256d checksum.
     let sha256d_wif = sha256d::Hash::hash(&wif_bytes).into_inner();
@@ -362,22 +1974,149 @@ pub fn secret_to_wif(privkey: SecretKey, version_byte: u8) -> String {
     wif_bytes.to_base58()
 }
 
+/// The byte ending the payload of a WIF whose address uses the compressed public key
+pub const WIF_COMPRESSION_FLAG: u8 = 1;
+
+/// The reasons a WIF can fail to decode
+#[derive(Debug, PartialEq, Eq)]
+pub enum WifError {
+    /// The string isn't valid Base58
+    Base58,
+    /// The payload is neither a compressed nor an uncompressed WIF's length
+    Length(usize),
+    /// The SHA256d checksum doesn't match
+    Checksum,
+    /// The version byte isn't the expected coin's
+    Version { expected: u8, found: u8 },
+    /// The key bytes aren't a valid secp256k1 secret key
+    Key,
+    /// The compression flag byte isn't `WIF_COMPRESSION_FLAG`
+    Flag(u8),
+    /// The WIF's compression doesn't match the coin's, so it would import as a different address
+    Compression { expected: bool },
+}
+
+impl std::fmt::Display for WifError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            WifError::Base58 => write!(f, "not valid Base58"),
+            WifError::Length(len) => write!(f, "unexpected payload length of {} bytes", len),
+            WifError::Checksum => write!(f, "checksum mismatch"),
+            WifError::Version { expected, found } => {
+                write!(f, "version byte {} doesn't match the coin's ({})", found, expected)
+            }
+            WifError::Key => write!(f, "not a valid secret key"),
+            WifError::Flag(flag) => write!(f, "unexpected compression flag byte {}", flag),
+            WifError::Compression { expected: true } => write!(f, "an uncompressed key, but the coin uses compressed keys"),
+            WifError::Compression { expected: false } => write!(f, "a compressed key, but the coin uses uncompressed keys"),
+        }
+    }
+}
+
+/// Converts a Wallet Import Format (WIF) string back into a secret key, the inverse of `secret_to_wif`.
+///
+/// # Arguments
+///
+/// * `wif` - The WIF string to decode (compressed or uncompressed).
+/// * `expected_version` - The version byte the WIF must have (coin-specific).
+///
+/// # Returns
+///
+/// The secret key and whether the WIF is flagged as compressed, or the reason the WIF is invalid.
+///
+pub fn wif_to_secret(wif: &str, expected_version: u8) -> Result<(SecretKey, bool), WifError> {
+    let bytes = wif.from_base58().map_err(|_| WifError::Base58)?;
+
+    // Version byte + 32 key bytes (+ a compression flag) + 4 checksum bytes
+    let payload_len = match bytes.len() {
+        37 | 38 => bytes.len() - 4,
+        len => return Err(WifError::Length(len)),
+    };
+    let (payload, checksum) = bytes.split_at(payload_len);
+    if sha256d::Hash::hash(payload).into_inner()[..4] != *checksum {
+        return Err(WifError::Checksum);
+    }
+    if payload[0] != expected_version {
+        return Err(WifError::Version {
+            expected: expected_version,
+            found: payload[0],
+        });
+    }
+
+    // Strip the version byte, and the compression flag (if any)
+    let compressed = match payload.get(33) {
+        None => false,
+        Some(&WIF_COMPRESSION_FLAG) => true,
+        Some(&flag) => return Err(WifError::Flag(flag)),
+    };
+    let privkey = SecretKey::from_slice(&payload[1..33]).map_err(|_| WifError::Key)?;
+    Ok((privkey, compressed))
+}
+
+/// Converts a WIF straight into its coin address, e.g. to check a WIF matches an expected funded address.
+///
+/// The WIF's compression must match the coin's, since a compressed key's address differs from an
+/// uncompressed one's: a mismatched WIF would import into a wallet as some other, empty address.
+///
+/// # Arguments
+///
+/// * `wif` - The WIF string to decode.
+/// * `coin_params` - A reference to the CoinParams for the WIF's coin.
+///
+/// # Returns
+///
+/// The coin address, or the reason the WIF is invalid.
+///
+pub fn wif_to_address(wif: &str, coin_params: &CoinParams) -> Result<String, WifError> {
+    let (privkey, compressed) = wif_to_secret(wif, coin_params.priv_key_byte)?;
+    if compressed != coin_params.compressed {
+        return Err(WifError::Compression { expected: coin_params.compressed });
+    }
+    let pubkey = PublicKey::from_secret_key(&Secp256k1::signing_only(), &privkey);
+    Ok(pubkey_to_address(pubkey, coin_params.pub_key_byte, compressed))
+}
+
+/// Signs a message with a secret key, proving control of the key without revealing it.
+///
+/// The message is hashed with SHA256 before signing, so it may be of any length.
+///
+/// # Arguments
+///
+/// * `privkey` - The secret key to sign with.
+/// * `message` - The message to be signed (typically the promo code itself).
+///
+/// # Returns
+///
+/// The ECDSA signature, which displays as DER-encoded hex.
+///
+pub fn sign_message(privkey: &SecretKey, message: &str) -> Signature {
+    let secp = Secp256k1::signing_only();
+    let digest = sha256::Hash::hash(message.as_bytes()).into_inner();
+    let msg = Message::from_slice(&digest).unwrap();
+    secp.sign_ecdsa(&msg, privkey)
+}
+
 /// Converts a public key into a coin address.
 ///
 /// # Arguments
 ///
 /// * `pubkey` - The public key to be converted.
 /// * `version_byte` - The version byte for the address format (coin-specific).
+/// * `compressed` - Whether the address is of the compressed public key (coin-specific).
 ///
 /// # Returns
 ///
 /// The coin address as a string.
 ///
-pub fn pubkey_to_address(pubkey: PublicKey, version_byte: u8) -> String {
+pub fn pubkey_to_address(pubkey: PublicKey, version_byte: u8, compressed: bool) -> String {
     // Convert into byte format
-    let pubkey_bytes = pubkey.serialize();
+    let pubkey_bytes = if compressed {
+        pubkey.serialize().to_vec()
+    } else {
+        pubkey.serialize_uncompressed().to_vec()
+    };
 
-    // First sha256 round of the compressed pubkey
+    // First sha256 round of the pubkey
     let pre_ripemd = sha256::Hash::hash(&pubkey_bytes).into_inner();
 
     // Then a ripemd160 round
@@ -399,7 +2138,7 @@ pub fn pubkey_to_address(pubkey: PublicKey, version_byte: u8) -> String {
 
 /// A string representing the base58 charset for generating alphanumeric random values.
 ///
-const MAP_ALPHANUMERIC: &str = "abcdefghijkmnopqrstuvwxyzABCDEFGHJKLMNPQRSTUVWXYZ123456789";
+pub const MAP_ALPHANUMERIC: &str = "abcdefghijkmnopqrstuvwxyzABCDEFGHJKLMNPQRSTUVWXYZ123456789";
 
 /// Returns a vector of random bytes of the specified size.
 ///
@@ -429,56 +2168,176 @@ pub fn get_safe_rand(n_size: usize) -> Vec<u8> {
 /// A randomly generated alphanumeric string.
 ///
 pub fn get_alpha_numeric_rand(n_size: usize) -> String {
+    get_rand_chars(MAP_ALPHANUMERIC, n_size)
+}
+
+/// Returns a randomly generated string of the specified size, drawn from the given charset.
+///
+/// # Arguments
+///
+/// * `charset` - The characters to draw from (at most 256 of them).
+/// * `n_size` - The desired length of the generated string.
+///
+/// # Returns
+///
+/// A randomly generated string.
+///
+pub fn get_rand_chars(charset: &str, n_size: usize) -> String {
     let mut result = String::new();
     let rand_values = get_safe_rand(n_size);
     for byte in rand_values {
-        let index = (byte % MAP_ALPHANUMERIC.len() as u8) as usize;
-        result.push(MAP_ALPHANUMERIC.chars().nth(index).unwrap());
+        let index = (byte as usize) % charset.len();
+        result.push(charset.chars().nth(index).unwrap());
     }
     result
 }
 
+/// Derives a secret key from raw key bytes, checking that they form a usable secp256k1 scalar.
+///
+/// A valid scalar is non-zero and below the curve order; the chance of a hash missing this range
+/// is roughly 1 in 2^128, but it's handled explicitly rather than panicking.
+///
+/// # Arguments
+///
+/// * `bytes` - The 32 bytes to interpret as a private key.
+///
+/// # Returns
+///
+/// The `SecretKey`, or `None` if the bytes are not a valid scalar.
+///
+pub fn derive_valid_key(bytes: &[u8; 32]) -> Option<SecretKey> {
+    SecretKey::from_slice(bytes).ok()
+}
+
+/// Checks that a promo prefix only uses characters which are safe in codes, URLs and CSVs.
+///
+/// With a check character, the prefix must also be drawn from `MAP_ALPHANUMERIC` alone: the check
+/// only covers those characters, so a mistyped '0', 'O', 'I', 'l', '-' or '_' would slip through.
+///
+/// # Arguments
+///
+/// * `prefix` - The prefix to validate, an empty prefix is valid (and means "no prefix").
+/// * `check_char` - Whether codes end in a check character.
+///
+/// # Returns
+///
+/// `Ok` if the prefix is usable, otherwise an `Err` describing the problem.
+///
+pub fn validate_prefix(prefix: &str, check_char: bool) -> Result<(), String> {
+    if let Some(c) = prefix.chars().find(|c| !c.is_ascii_alphanumeric() && *c != '-' && *c != '_') {
+        return Err(format!(
+            "Prefix \"{}\" contains '{}', only letters, numbers, '-' and '_' are allowed",
+            prefix, c
+        ));
+    }
+    match prefix.chars().find(|c| check_char && !MAP_ALPHANUMERIC.contains(*c)) {
+        Some(c) => Err(format!(
+            "Prefix \"{}\" contains '{}', which the check character can't cover (it skips '0', 'O', 'I', 'l', '-' and '_')",
+            prefix, c
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Returns how many random characters a code should have.
+///
+/// Omitted prefixes add an extra character for higher entropy - with prefix, we deduct a character.
+/// Smaller charsets (such as numeric-only) get as many characters as it takes to keep at least the
+/// same entropy as the alphanumeric default, and v2 codes get enough extra characters for
+/// `V2_EXTRA_BITS` more bits. An explicitly configured length always takes priority.
+///
+pub fn random_code_length(prefix: &str, configured: Option<usize>, charset: &str, scheme: DerivationScheme) -> usize {
+    let alphanumeric_length = match configured {
+        Some(len) => return len,
+        None if prefix.is_empty() => 6,
+        None => 5,
+    };
+    let ratio = (MAP_ALPHANUMERIC.len() as f64).ln() / (charset.len() as f64).ln();
+    let extra_chars = match scheme {
+        DerivationScheme::V1 => 0.0,
+        DerivationScheme::V2 => V2_EXTRA_BITS as f64 / (charset.len() as f64).log2(),
+    };
+    (alphanumeric_length as f64 * ratio + extra_chars).ceil() as usize
+}
+
+/// Approximates the probability of at least one collision among a set of random codes.
+///
+/// This uses the birthday bound: `1 - e^(-n(n-1) / 2N)`, where `N` is the size of the code space.
+///
+/// # Arguments
+///
+/// * `total_codes` - The number of codes being generated.
+/// * `charset_len` - The number of characters each random position may take.
+/// * `code_length` - The number of random characters per code.
+///
+/// # Returns
+///
+/// The probability, between 0.0 and 1.0.
+///
+pub fn collision_probability(total_codes: u64, charset_len: usize, code_length: usize) -> f64 {
+    let n = total_codes as f64;
+    let space = (charset_len as f64).powi(code_length as i32);
+    -f64::exp_m1(-(n * (n - 1.0)) / (2.0 * space))
+}
+
+/// Derives the raw private key bytes of a promo code, via chained SHA256 hashing.
+///
+/// Production keys always use the latest `PROMO_TARGETS` value, but any iteration count may be
+/// passed, for example a small one for quickly testing the derivation.
+///
+/// # Arguments
+///
+/// * `code` - The full promo code.
+/// * `iterations` - The total number of SHA256 rounds (including the first hash of the code).
+///
+/// # Returns
+///
+/// The 32 derived key bytes.
+///
+pub fn derive_key_bytes(code: &str, iterations: u64) -> [u8; 32] {
+    // Convert the Promo Code to it's first SHA256 hash
+    let promo_key = hash::sha256(code.as_bytes());
+
+    // Recursively hash until we hit the target (minus one, as promo_key hashes it once)
+    hash::sha256_rounds(promo_key, iterations.saturating_sub(1))
+}
+
 /// Creates a crypto Promos keypair based on a given prefix and coin parameters.
 ///
 /// # Arguments
 ///
-/// * `prefix` - A reference to a String representing the prefix of the promotional code.
+/// * `format` - A reference to the CodeFormat describing how to assemble the promotional code.
+/// * `deriver` - A reference to the Deriver for the selected derivation scheme.
 /// * `coin_params` - A reference to the CoinParams for the selected coin.
 ///
 /// # Returns
 ///
 /// An `OptimisedPromoKeypair` struct containing the generated private and public keys, along with the promo code.
 ///
-pub fn create_promo_key(prefix: &String, coin_params: &CoinParams) -> OptimisedPromoKeypair {
+pub fn create_promo_key(format: &CodeFormat, deriver: &Deriver, coin_params: &CoinParams) -> OptimisedPromoKeypair {
     // Precompute a Secp256k1 context
     let secp = Secp256k1::new();
 
-    // Select the latest Target
-    let target = PROMO_TARGETS.last().unwrap();
-
-    // Generate entropy and append it to the promo code
-    // Omitted prefixes add an extra character for higher entropy - with prefix, we deduct a character.
-    let promo_code = if prefix.is_empty() {
-        get_alpha_numeric_rand(6)
-    } else {
-        prefix.to_owned() + "-" + &get_alpha_numeric_rand(5)
-    };
+    // In the (astronomically rare) case that a code hashes to an invalid scalar, we simply roll a new code
+    let (promo_code, private) = loop {
+        // Resume a code left unfinished by a crash, otherwise generate entropy and assemble a new promo code
+        let promo_code = deriver.resumable_code().unwrap_or_else(|| format.generate());
 
-    // Convert the Promo Code to it's first SHA256 hash
-    let mut promo_key = sha256::Hash::hash(promo_code.as_bytes()).into_inner();
+        // Derive the key bytes from the code, under the selected scheme
+        let promo_key = deriver.derive(&promo_code);
 
-    // Recursively hash until we hit the target (minus one, as promo_key hashes it once)
-    let mut iterations: u64 = 1;
-    while &iterations < target {
-        promo_key = sha256::Hash::hash(&promo_key).into_inner();
-        iterations += 1;
-    }
+        // Generate the final private key, if the scalar is usable
+        match derive_valid_key(&promo_key) {
+            Some(private) => break (promo_code, private),
+            None => eprintln!("{}", style::warning(format!("Code '{}' derived an invalid key, regenerating...", style::code(&promo_code)))),
+        }
+    };
 
-    // Generate the final keys
-    let private = SecretKey::from_slice(&promo_key).unwrap();
+    // Generate the public key
     let public = pubkey_to_address(
         PublicKey::from_secret_key(&secp, &private),
-        coin_params.pub_key_byte
+        coin_params.pub_key_byte,
+        coin_params.compressed
     );
 
     OptimisedPromoKeypair {
@@ -486,47 +2345,269 @@ pub fn create_promo_key(prefix: &String, coin_params: &CoinParams) -> OptimisedP
         public,
         code: promo_code,
         value: 0.0,
+        signature: None,
+        tx_id: None,
+        derivation: deriver.scheme(),
+        note: None,
+        wif_hash: None,
+        timestamp: None,
+        target: None,
+    }
+}
+
+/// Derives a promo's key a second time, and confirms it gives the same address, if the deriver
+/// double-checks. A mismatch means the machine can't be trusted to derive keys, so the run stops
+/// before the key could be funded.
+///
+/// # Arguments
+///
+/// * `promo` - The freshly derived promo.
+/// * `deriver` - The Deriver the promo was derived with.
+/// * `coin_params` - A reference to the CoinParams for the selected coin.
+///
+pub fn double_check_promo(promo: &OptimisedPromoKeypair, deriver: &Deriver, coin_params: &CoinParams) {
+    if !deriver.double_checks() {
+        return;
+    }
+    let matches = derive_valid_key(&deriver.derive_again(&promo.code)).is_some_and(|private| {
+        private == promo.private
+            && pubkey_to_address(
+                PublicKey::from_secret_key(&Secp256k1::new(), &private),
+                coin_params.pub_key_byte,
+                coin_params.compressed,
+            ) == promo.public
+    });
+    if !matches {
+        eprintln!(
+            "{}",
+            style::error(format!(
+                "Code '{}' derived a different key the second time! This machine may have a memory or hardware fault, stopping.",
+                style::code(&promo.code)
+            ))
+        );
+        std::process::exit(1);
+    }
+}
+
+/// Imports each promo's address into the wallet as watch-only.
+///
+/// Rescanning is expensive, so every import skips it. When `rescan` is set, the last address that
+/// imported successfully is then imported again with a rescan: this way the chain is rescanned exactly
+/// once, no matter the batch size, and even if the final import failed.
+///
+/// # Arguments
+///
+/// * `rpc` - The RPC client of the wallet to import into.
+/// * `promos` - The generated promos whose addresses should be watched.
+/// * `coin_params` - A reference to the CoinParams for the selected coin, used for the label.
+/// * `rescan` - Whether to rescan the chain once all addresses are imported.
+///
+pub fn import_watch_only(
+    rpc: &mut ReconnectingRpc,
+    promos: &[OptimisedPromoKeypair],
+    coin_params: &CoinParams,
+    rescan: bool,
+) {
+    let label = format!("{} Promos", coin_params.name);
+    humanln!("Importing {} addresses as watch-only...", promos.len());
+    if rescan {
+        humanln!(" - The wallet will rescan once after all imports, this may take a while!");
+    }
+
+    let mut failures = 0;
+    let mut last_imported = None;
+    for promo in promos {
+        match rpc.call(|c| c.importaddress(&promo.public, Some(&label), Some(false), Some(false))) {
+            Ok(_) => last_imported = Some(promo),
+            Err(e) => {
+                eprintln!("{}", style::error(format!(" - Failed to import {}: \"{}\"", promo.public, e)));
+                failures += 1;
+            }
+        }
+    }
+
+    // Re-importing an already-watched address is harmless, and triggers the one rescan
+    if let (true, Some(promo)) = (rescan, last_imported) {
+        humanln!(" - Rescanning the chain...");
+        if let Err(e) = rpc.call(|c| c.importaddress(&promo.public, Some(&label), Some(true), Some(false))) {
+            eprintln!("{}", style::error(format!(" - Failed to rescan the chain: \"{}\"", e)));
+        }
     }
+
+    humanln!(
+        "Imported {} of {} addresses as watch-only.",
+        promos.len() - failures,
+        promos.len()
+    );
 }
 
+#[derive(Clone)]
 pub struct RpcConfig {
+    /// The host the daemon's RPC listens on
+    pub rpc_host: String,
     pub rpc_user: String,
     pub rpc_pass: String,
     pub rpc_port: u16,
+    /// The wallet to target on multiwallet daemons, or `None` for the default wallet
+    pub wallet: Option<String>,
+    /// How many times the RPC client retries a failed request
+    pub retries: u32,
+    /// The delay between the RPC client's retries, in milliseconds
+    pub retry_delay: u64,
+    /// The RPC client's request timeout, in milliseconds
+    pub timeout: u64,
+}
+
+/// Builds the RPC config for a coin: its parsed config file, with any command-line overrides applied
+pub fn rpc_config_for(cli: &CliArgs, coin_params: &CoinParams) -> RpcConfig {
+    let mut coin_config = parse_coin_conf(coin_params);
+    coin_config.wallet = cli.wallet.clone();
+    coin_config.retries = cli.rpc_retries.unwrap_or(coin_config.retries);
+    coin_config.retry_delay = cli.rpc_retry_delay.unwrap_or(coin_config.retry_delay);
+    coin_config.timeout = cli.rpc_timeout.unwrap_or(coin_config.timeout);
+    coin_config
+}
+
+/// How to recover from a daemon that can't be reached
+enum RpcRecovery {
+    /// Enter the RPC host, port and credentials by hand
+    Manual,
+    /// Pick a different coin (whose config may be found)
+    Coin,
+    /// Carry on regardless
+    Continue,
+}
+
+/// Asks the operator how to recover from a daemon that can't be reached.
+///
+/// # Arguments
+///
+/// * `can_reselect` - Whether a different coin may be picked (not when continuing an interrupted run).
+///
+fn ask_rpc_recovery(can_reselect: bool) -> RpcRecovery {
+    humanln!("1. Enter the RPC connection manually");
+    if can_reselect {
+        humanln!("2. Pick a different coin");
+    }
+    humanln!("3. Continue anyway (fills will fail until the daemon is reachable)");
+    loop {
+        match ask_float("How would you like to continue?", 1.0) as u32 {
+            1 => return RpcRecovery::Manual,
+            2 if can_reselect => return RpcRecovery::Coin,
+            3 => return RpcRecovery::Continue,
+            _ => eprintln!("{}", style::warning("That's not one of the options - try again!")),
+        }
+    }
+}
+
+/// Asks for the RPC host, port and credentials, defaulting to the current ones
+fn ask_rpc_connection(config: &mut RpcConfig) {
+    config.rpc_host = ask_string("RPC host", &config.rpc_host);
+    config.rpc_port = loop {
+        match ask_string("RPC port", &config.rpc_port.to_string()).parse() {
+            Ok(port) => break port,
+            Err(_) => eprintln!("{}", style::warning("That's not a valid port - try again!")),
+        }
+    };
+    config.rpc_user = ask_string("RPC username", &config.rpc_user);
+    let password = rpassword::prompt_password("RPC password (leave empty to keep the current one): ").unwrap_or_default();
+    if !password.is_empty() {
+        config.rpc_pass = password;
+    }
+}
+
+/// Finds a supported coin by its ticker (case-insensitive), exiting if there's no such coin
+pub fn find_coin(ticker: &str) -> CoinParams {
+    match lookup_coin(ticker) {
+        Some(coin) => coin,
+        None => {
+            eprintln!("{}", style::usage_error(format!("Unsupported coin \"{}\"", ticker)));
+            std::process::exit(1);
+        }
+    }
 }
 
-/// Selects a coin from the list of supported coins
+/// Selects a coin from the list of supported coins, by its ticker (which never changes) or its menu number
 pub fn select_coin() -> CoinParams {
     let supported_coins = get_supported_coins();
     
-    println!("Which coin are you creating Promo Codes for?");
+    humanln!("Which coin are you creating Promo Codes for?");
     
     for (i, coin) in supported_coins.iter().enumerate() {
-        println!("{}. {} ({})", i + 1, coin.name, coin.ticker);
+        humanln!("{}. {} ({})", i + 1, coin.name, coin.ticker);
     }
     
     let default_idx = supported_coins
         .iter()
         .position(|c| c.ticker == DEFAULT_COIN_TICKER)
-        .unwrap_or(0) + 1;
+        .unwrap_or(0);
     
-    let selection = ask_float(
-        format!("Enter a number (1-{}) to select a coin", supported_coins.len()).as_str(),
-        default_idx as f64,
-    ) as usize;
+    let answer = ask_string(
+        format!("Enter a ticker, or a number (1-{}), to select a coin", supported_coins.len()).as_str(),
+        &supported_coins[default_idx].ticker,
+    );
+    let selection = supported_coins
+        .iter()
+        .position(|c| c.ticker.eq_ignore_ascii_case(&answer))
+        .or_else(|| answer.parse::<usize>().ok().filter(|n| (1..=supported_coins.len()).contains(n)).map(|n| n - 1));
     
     // Make sure the selection is valid
-    if selection < 1 || selection > supported_coins.len() {
-        println!("Invalid selection, using default: {} ({})", 
-                 supported_coins[default_idx - 1].name, 
-                 supported_coins[default_idx - 1].ticker);
-        return supported_coins[default_idx - 1].clone();
+    match selection {
+        Some(idx) => supported_coins[idx].clone(),
+        None => {
+            humanln!("Invalid selection, using default: {} ({})", 
+                     supported_coins[default_idx].name, 
+                     supported_coins[default_idx].ticker);
+            supported_coins[default_idx].clone()
+        }
     }
-    
-    supported_coins[selection - 1].clone()
 }
 
-pub fn parse_coin_conf(coin_params: &CoinParams) -> RpcConfig {
+/// Reports where a coin's config is looked for, and the RPC settings it resolves to (with any
+/// command-line overrides), without contacting the daemon. The password is never printed.
+fn print_coin_conf(coin_params: &CoinParams, cli: &CliArgs) {
+    let conf_dir = coin_conf_dir(coin_params);
+    let conf_file = conf_dir.join(&coin_params.conf_file_name);
+    let config = parse_coin_conf(coin_params);
+    let defaults = |value: &str, default: &str| if value == default { " (default)" } else { "" };
+
+    humanln!("----------------------------------------------");
+    humanln!("{} ({})", coin_params.name, coin_params.ticker);
+    match fs::metadata(&conf_file) {
+        Ok(_) => humanln!(" - Config file:  {}", conf_file.display()),
+        Err(e) => humanln!(
+            "{}",
+            style::warning(format!(" - Config file:  {} (not usable: {}, so defaults apply)", conf_file.display(), e))
+        ),
+    }
+    humanln!(" - RPC host:     {}", config.rpc_host);
+    humanln!(
+        " - RPC port:     {}{}",
+        config.rpc_port,
+        defaults(&config.rpc_port.to_string(), &coin_params.default_rpc_port.to_string())
+    );
+    humanln!(" - RPC user:     {}{}", config.rpc_user, defaults(&config.rpc_user, "user"));
+    humanln!(" - RPC password: [redacted]{}", defaults(&config.rpc_pass, "pass"));
+    if let Some(ref wallet) = cli.wallet {
+        humanln!(" - Wallet:       {} ({})", wallet, rpc::rpc_url(&RpcConfig { wallet: Some(wallet.clone()), ..config.clone() }));
+    }
+    // Daemons write a cookie when no rpcpassword is set, but Batcher only authenticates with the config's credentials
+    let cookie = conf_dir.join(".cookie");
+    if cookie.exists() {
+        humanln!(
+            "{}",
+            style::warning(format!(
+                " - Cookie file:  {} was found, but isn't used: set rpcuser and rpcpassword in the config instead",
+                cookie.display()
+            ))
+        );
+    } else {
+        humanln!(" - Cookie file:  none found");
+    }
+}
+
+/// Returns the directory a coin's config file (and its daemon's data) is found in, for this platform
+pub fn coin_conf_dir(coin_params: &CoinParams) -> std::path::PathBuf {
     let mut conf_dir = home_dir().unwrap_or_default();
     if cfg!(target_os = "windows") {
         conf_dir.push(format!("AppData\\Roaming\\{}", coin_params.name));
@@ -537,12 +2618,21 @@ pub fn parse_coin_conf(coin_params: &CoinParams) -> RpcConfig {
         let linux_dir_name = format!(".{}", coin_params.conf_dir_name.to_lowercase());
         conf_dir.push(linux_dir_name);
     }
-    let conf_file = conf_dir.join(&coin_params.conf_file_name);
+    conf_dir
+}
+
+pub fn parse_coin_conf(coin_params: &CoinParams) -> RpcConfig {
+    let conf_file = coin_conf_dir(coin_params).join(&coin_params.conf_file_name);
 
     let mut defaults = RpcConfig {
+        rpc_host: String::from("localhost"),
         rpc_user: String::from("user"),
         rpc_pass: String::from("pass"),
         rpc_port: coin_params.default_rpc_port,
+        wallet: None,
+        retries: DEFAULT_RPC_RETRIES,
+        retry_delay: DEFAULT_RPC_RETRY_DELAY,
+        timeout: DEFAULT_RPC_TIMEOUT,
     };
 
     let contents = match fs::read_to_string(conf_file) {
@@ -563,12 +2653,134 @@ pub fn parse_coin_conf(coin_params: &CoinParams) -> RpcConfig {
     defaults
 }
 
-pub fn compile_to_csv(promos: Vec<OptimisedPromoKeypair>, coin_ticker: &str) -> String {
-    let mut csv = String::from("coin,value,code,\n");
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin_hashes::hex::ToHex;
 
-    for promo in promos {
-        // Store the selected coin ticker in the CSV
-        csv.push_str(&format!("{},{},{}\n", coin_ticker.to_lowercase(), promo.value, promo.code));
+    /// The secp256k1 curve order, the first scalar too large to be a private key
+    const CURVE_ORDER: [u8; 32] = [
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xfe, 0xba, 0xae, 0xdc,
+        0xe6, 0xaf, 0x48, 0xa0, 0x3b, 0xbf, 0xd2, 0x5e, 0x8c, 0xd0, 0x36, 0x41, 0x41,
+    ];
+
+    #[test]
+    fn derive_valid_key_rejects_zero() {
+        assert!(derive_valid_key(&[0; 32]).is_none());
+    }
+
+    #[test]
+    fn derive_valid_key_rejects_scalars_from_the_curve_order() {
+        assert!(derive_valid_key(&CURVE_ORDER).is_none());
+        let mut above = CURVE_ORDER;
+        above[31] += 1;
+        assert!(derive_valid_key(&above).is_none());
+        assert!(derive_valid_key(&[0xff; 32]).is_none());
+    }
+
+    #[test]
+    fn derive_valid_key_accepts_valid_scalars() {
+        let mut one = [0; 32];
+        one[31] = 1;
+        assert_eq!(derive_valid_key(&one), Some(SecretKey::from_slice(&one).unwrap()));
+        let mut below = CURVE_ORDER;
+        below[31] -= 1;
+        assert_eq!(derive_valid_key(&below), Some(SecretKey::from_slice(&below).unwrap()));
+    }
+
+    #[test]
+    fn derive_key_bytes_of_one_iteration_is_the_code_hash() {
+        assert_eq!(derive_key_bytes("test", 1), sha256::Hash::hash(b"test").into_inner());
+    }
+
+    #[test]
+    fn derive_key_bytes_chains_each_iteration() {
+        let mut chained = sha256::Hash::hash(b"test");
+        for _ in 0..2 {
+            chained = sha256::Hash::hash(&chained[..]);
+        }
+        assert_eq!(derive_key_bytes("test", 3), chained.into_inner());
+        assert_eq!(
+            derive_key_bytes("test", 3).to_hex(),
+            "be2ef4971b24405df0541f95fe6158ac661e69971337ff7260b219d1056ebc3b"
+        );
+    }
+
+    #[test]
+    fn wif_round_trips_for_every_coin() {
+        let key = derive_valid_key(&derive_key_bytes("test", 3)).unwrap();
+        for coin in get_supported_coins() {
+            for compressed in [true, false] {
+                let wif = secret_to_wif(key, coin.priv_key_byte, compressed);
+                assert_eq!(wif_to_secret(&wif, coin.priv_key_byte), Ok((key, compressed)), "{}", coin.ticker);
+            }
+        }
+    }
+
+    #[test]
+    fn wif_matches_a_known_vector() {
+        let bytes = [
+            0x0c, 0x28, 0xfc, 0xa3, 0x86, 0xc7, 0xa2, 0x27, 0x60, 0x0b, 0x2f, 0xe5, 0x0b, 0x7c, 0xae, 0x11, 0xec, 0x86, 0xd3,
+            0xbf, 0x1f, 0xbe, 0x47, 0x1b, 0xe8, 0x98, 0x27, 0xe1, 0x9d, 0x72, 0xaa, 0x1d,
+        ];
+        let key = SecretKey::from_slice(&bytes).unwrap();
+        assert_eq!(secret_to_wif(key, 0x80, false), "5HueCGU8rMjxEXxiPuD5BDku4MkFqeZyd4dZ1jvhTVqvbTLvyTJ");
+        assert_eq!(secret_to_wif(key, 0x80, true), "KwdMAjGmerYanjeui5SHS7JkmpZvVipYvB2LJGU1ZxJwYvP98617");
+    }
+
+    #[test]
+    fn wif_to_secret_rejects_another_coins_version() {
+        let key = derive_valid_key(&derive_key_bytes("test", 3)).unwrap();
+        let wif = secret_to_wif(key, 212, true);
+        assert_eq!(wif_to_secret(&wif, 158), Err(WifError::Version { expected: 158, found: 212 }));
+    }
+
+    #[test]
+    fn collision_probability_is_zero_for_fewer_than_two_codes() {
+        assert_eq!(collision_probability(0, MAP_ALPHANUMERIC.len(), 6), 0.0);
+        assert_eq!(collision_probability(1, MAP_ALPHANUMERIC.len(), 6), 0.0);
+    }
+
+    #[test]
+    fn collision_probability_follows_the_birthday_bound() {
+        // Two codes over a space of 100: 1 - e^(-2 / 200)
+        let p = collision_probability(2, 10, 2);
+        assert!((p - (1.0 - (-0.01f64).exp())).abs() < 1e-12);
+        // 23 people, 365 birthdays: the classic ~50%
+        assert!((collision_probability(23, 365, 1) - 0.5).abs() < 0.05);
+        // Filling the whole space makes a collision a near certainty
+        assert!(collision_probability(1_000, 10, 2) > 0.999_999);
+    }
+
+    #[test]
+    fn collision_probability_keeps_tiny_odds_above_zero() {
+        let p = collision_probability(1_000, MAP_ALPHANUMERIC.len(), 12);
+        assert!(p > 0.0 && p < 1e-12);
+        assert!(collision_probability(1_000, MAP_ALPHANUMERIC.len(), 11) > p);
+        assert!(collision_probability(2_000, MAP_ALPHANUMERIC.len(), 12) > p);
+    }
+
+    #[test]
+    fn parse_decimal_reads_the_configured_separator() {
+        assert_eq!(parse_decimal("1.5", false), Ok(1.5));
+        assert_eq!(parse_decimal("1,5", true), Ok(1.5));
+        assert_eq!(parse_decimal("20", false), Ok(20.0));
+        assert_eq!(parse_decimal("20", true), Ok(20.0));
+    }
+
+    #[test]
+    fn parse_decimal_rejects_ambiguous_separators() {
+        // Could be one thousand, or one and a bit
+        assert!(parse_decimal("1,000", false).is_err());
+        assert!(parse_decimal("1.000", true).is_err());
+        assert!(parse_decimal("1.000,5", true).is_err());
+        assert!(parse_decimal("1,000,5", true).is_err());
+    }
+
+    #[test]
+    fn parse_decimal_rejects_non_numbers() {
+        assert!(parse_decimal("", false).is_err());
+        assert!(parse_decimal("abc", false).is_err());
+        assert!(parse_decimal(",", true).is_err());
     }
-    csv
 }
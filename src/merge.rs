@@ -0,0 +1,116 @@
+use std::collections::{BTreeMap, HashSet};
+
+use crate::{
+    coins::lookup_coin,
+    output::{is_gzip, read_csv, read_text, write_text},
+    plan::{to_sats, BatchPlan, PromoBatch},
+    style,
+};
+
+/// The result of a merge
+pub struct MergeSummary {
+    /// The coin ticker (lowercase) every merged row is for
+    pub coin: String,
+    /// The merged codes, grouped into a batch per value, for totals
+    pub plan: BatchPlan,
+    /// How many duplicate rows were dropped
+    pub duplicates: usize,
+}
+
+/// Merges several batch CSVs into one, de-duplicating by code.
+///
+/// Every input must have the same header (ignoring a legacy trailing comma) and be for the same
+/// coin. Rows are kept as-is, in input order; a code seen again is dropped, with a warning if its
+/// row differs from the first.
+///
+/// # Arguments
+///
+/// * `out` - The CSV to write (gzipped if it ends in `.gz`).
+/// * `inputs` - The CSVs to merge, in order.
+///
+/// # Returns
+///
+/// The merged totals, or the reason the inputs can't be merged.
+///
+pub fn merge_csvs(out: &str, inputs: &[String]) -> Result<MergeSummary, String> {
+    if inputs.iter().any(|input| input == out) {
+        return Err(format!("\"{}\" can't be both an input and the output", out));
+    }
+
+    let mut header: Option<String> = None;
+    let mut rows: Vec<String> = Vec::new();
+    let mut seen: BTreeMap<String, String> = BTreeMap::new();
+    let mut coins: HashSet<String> = HashSet::new();
+    let mut values: Vec<f64> = Vec::new();
+    let mut duplicates = 0;
+
+    for input in inputs {
+        let contents = read_text(input).map_err(|e| format!("can't read \"{}\": {}", input, e))?;
+        let input_header = contents.lines().next().unwrap_or_default().trim_end_matches(',').to_owned();
+        match header {
+            None => header = Some(input_header),
+            Some(ref expected) if *expected != input_header => {
+                return Err(format!(
+                    "\"{}\" has a different header (\"{}\", not \"{}\")",
+                    input, input_header, expected
+                ));
+            }
+            Some(_) => {}
+        }
+
+        let records = read_csv(input).map_err(|e| format!("can't parse \"{}\": {}", input, e))?;
+        let lines = contents.lines().skip(1).filter(|line| !line.trim().is_empty());
+        for (record, line) in records.into_iter().zip(lines) {
+            if let Some(first) = seen.get(&record.code) {
+                if first != line {
+                    eprintln!(
+                        "{}",
+                        style::warning(format!(
+                            "'{}' appears again in \"{}\" with a different row, keeping the first",
+                            record.code, input
+                        ))
+                    );
+                }
+                duplicates += 1;
+                continue;
+            }
+            coins.insert(record.coin.clone());
+            values.push(record.value);
+            seen.insert(record.code, line.to_owned());
+            rows.push(line.to_owned());
+        }
+    }
+
+    let coin = match coins.len() {
+        0 => return Err(String::from("there are no codes to merge")),
+        1 => coins.into_iter().next().unwrap(),
+        _ => return Err(String::from("the CSVs are for different coins, which can't be merged")),
+    };
+    let coin_params = lookup_coin(&coin).ok_or_else(|| format!("the CSVs are for an unsupported coin \"{}\"", coin))?;
+
+    let mut contents = header.unwrap_or_default() + "\n";
+    for row in &rows {
+        contents.push_str(row);
+        contents.push('\n');
+    }
+    write_text(out, &contents, is_gzip(out)).map_err(|e| format!("can't write \"{}\": {}", out, e))?;
+
+    // Group the codes by value, compared in the coin's smallest units
+    let mut batches: BTreeMap<u64, (f64, u64)> = BTreeMap::new();
+    for value in values {
+        batches.entry(to_sats(value, &coin_params)).or_insert((value, 0)).1 += 1;
+    }
+    let plan = BatchPlan {
+        batches: batches
+            .into_values()
+            .map(|(value, qty)| PromoBatch {
+                value,
+                qty,
+                note: None,
+                max_value: None,
+                step: None,
+            })
+            .collect(),
+    };
+    Ok(MergeSummary { coin, plan, duplicates })
+}
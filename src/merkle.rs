@@ -0,0 +1,268 @@
+use bitcoin_hashes::{
+    hex::{FromHex, ToHex},
+    hmac, sha256 as sha256_hash, Hash, HashEngine,
+};
+use serde::Serialize;
+
+use crate::hash::sha256;
+
+/// Derives a batch's secret Merkle key, as `SHA256(0x02 || code || '\n' || code || '\n' ...)` over the
+/// sorted, de-duplicated codes.
+///
+/// Only someone holding every code of the batch (i.e. its save file) can compute it, and it's never
+/// published, so the salts it keys stay secret.
+///
+fn batch_key(sorted_codes: &[&str]) -> [u8; 32] {
+    let mut data = vec![0x02];
+    for code in sorted_codes {
+        data.extend_from_slice(code.as_bytes());
+        data.push(b'\n');
+    }
+    sha256(&data)
+}
+
+/// Derives a code's leaf salt, as `HMAC-SHA256(batch key, code)`
+fn leaf_salt(key: &[u8; 32], code: &str) -> [u8; 32] {
+    let mut engine = hmac::HmacEngine::<sha256_hash::Hash>::new(key);
+    engine.input(code.as_bytes());
+    hmac::Hmac::<sha256_hash::Hash>::from_engine(engine).into_inner()
+}
+
+/// Hashes a promo code into a Merkle leaf, as `SHA256(0x00 || salt || code)`.
+///
+/// A code alone is cheap to hash, so an unsalted leaf could be brute-forced back into its code (and its
+/// funds swept) by anyone shown it as a sibling in a proof. Each code's salt is only ever given out in
+/// its own proof, so its holder can recompute its leaf, but the other leaves in the proof stay opaque.
+/// The leaf and node prefixes keep a leaf from ever being passed off as an inner node (or vice versa).
+///
+pub fn leaf_hash(salt: &[u8; 32], code: &str) -> [u8; 32] {
+    let mut data = Vec::with_capacity(code.len() + 33);
+    data.push(0x00);
+    data.extend_from_slice(salt);
+    data.extend_from_slice(code.as_bytes());
+    sha256(&data)
+}
+
+/// Hashes two child nodes into their parent, as `SHA256(0x01 || left || right)`
+fn node_hash(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let mut data = [0u8; 65];
+    data[0] = 0x01;
+    data[1..33].copy_from_slice(left);
+    data[33..].copy_from_slice(right);
+    sha256(&data)
+}
+
+/// Which side of the running hash a proof's sibling is on
+#[derive(Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Side {
+    Left,
+    Right,
+}
+
+/// A single step of an inclusion proof: the sibling to hash the running hash with
+#[derive(Serialize)]
+pub struct ProofStep {
+    /// The sibling node, as hex
+    pub hash: String,
+    /// Which side the sibling is on
+    pub side: Side,
+}
+
+/// A Merkle tree over a batch's codes, as a public commitment to the whole batch.
+///
+/// The leaves are the sorted, salted leaf hashes of every code, so the root doesn't depend on
+/// generation order. An odd node out at any level is carried up unchanged, rather than paired with
+/// itself.
+///
+pub struct MerkleTree {
+    /// The batch's secret key, which every leaf's salt is derived with
+    key: [u8; 32],
+    /// Every level of the tree, from the sorted leaves up to the root
+    levels: Vec<Vec<[u8; 32]>>,
+}
+
+impl MerkleTree {
+    /// Builds the tree over a set of codes
+    pub fn new<'a>(codes: impl IntoIterator<Item = &'a str>) -> MerkleTree {
+        let mut codes: Vec<&str> = codes.into_iter().collect();
+        codes.sort_unstable();
+        codes.dedup();
+        let key = batch_key(&codes);
+
+        let mut leaves: Vec<[u8; 32]> = codes.iter().map(|code| leaf_hash(&leaf_salt(&key, code), code)).collect();
+        leaves.sort_unstable();
+
+        let mut levels = vec![leaves];
+        while levels.last().unwrap().len() > 1 {
+            let next = levels
+                .last()
+                .unwrap()
+                .chunks(2)
+                .map(|pair| match pair {
+                    [left, right] => node_hash(left, right),
+                    [odd] => *odd,
+                    _ => unreachable!(),
+                })
+                .collect();
+            levels.push(next);
+        }
+        MerkleTree { key, levels }
+    }
+
+    /// The root of the tree, or `None` for an empty batch
+    pub fn root(&self) -> Option<[u8; 32]> {
+        self.levels.last().unwrap().first().copied()
+    }
+
+    /// The salt of a code's leaf, which its holder needs (along with the proof) to recompute the leaf
+    pub fn salt(&self, code: &str) -> [u8; 32] {
+        leaf_salt(&self.key, code)
+    }
+
+    /// Builds the inclusion proof for a code, or `None` if the code isn't in the tree.
+    ///
+    /// # Returns
+    ///
+    /// The siblings to hash with (in order, from the leaf up), which produce the root when folded
+    /// over the code's leaf hash.
+    ///
+    pub fn prove(&self, code: &str) -> Option<Vec<ProofStep>> {
+        let mut index = self.levels[0].binary_search(&leaf_hash(&self.salt(code), code)).ok()?;
+        let mut proof = Vec::new();
+        for level in &self.levels[..self.levels.len() - 1] {
+            let (sibling, side) = match index % 2 {
+                0 => (index + 1, Side::Right),
+                _ => (index - 1, Side::Left),
+            };
+            // A carried-up odd node has no sibling at this level
+            if let Some(hash) = level.get(sibling) {
+                proof.push(ProofStep {
+                    hash: hash.to_hex(),
+                    side,
+                });
+            }
+            index /= 2;
+        }
+        Some(proof)
+    }
+}
+
+/// Checks an inclusion proof: that folding its siblings over a code's leaf produces the root.
+///
+/// # Arguments
+///
+/// * `root` - The batch's published Merkle root.
+/// * `code` - The promo code being proven.
+/// * `salt` - The code's leaf salt, as given with its proof.
+/// * `proof` - The siblings, from the leaf up.
+///
+/// # Returns
+///
+/// Whether the code is part of the batch (a malformed sibling never is).
+///
+pub fn verify(root: &[u8; 32], code: &str, salt: &[u8; 32], proof: &[ProofStep]) -> bool {
+    let mut hash = leaf_hash(salt, code);
+    for step in proof {
+        let Ok(sibling) = <[u8; 32]>::from_hex(&step.hash) else {
+            return false;
+        };
+        hash = match step.side {
+            Side::Left => node_hash(&sibling, &hash),
+            Side::Right => node_hash(&hash, &sibling),
+        };
+    }
+    hash == *root
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn codes(count: usize) -> Vec<String> {
+        (0..count).map(|i| format!("promo-{}", i)).collect()
+    }
+
+    fn tree(codes: &[String]) -> MerkleTree {
+        MerkleTree::new(codes.iter().map(String::as_str))
+    }
+
+    #[test]
+    fn root_is_none_for_an_empty_batch() {
+        assert!(tree(&[]).root().is_none());
+    }
+
+    #[test]
+    fn root_of_one_code_is_its_leaf() {
+        let tree = tree(&codes(1));
+        assert_eq!(tree.root(), Some(leaf_hash(&tree.salt("promo-0"), "promo-0")));
+        assert!(tree.prove("promo-0").unwrap().is_empty());
+    }
+
+    #[test]
+    fn root_ignores_order_and_duplicates() {
+        let codes = codes(5);
+        let mut shuffled: Vec<String> = codes.iter().rev().cloned().collect();
+        shuffled.push(codes[2].clone());
+        assert_eq!(tree(&codes).root(), tree(&shuffled).root());
+    }
+
+    #[test]
+    fn root_depends_on_every_code() {
+        let mut other = codes(4);
+        other[3] = String::from("promo-x");
+        assert_ne!(tree(&codes(4)).root(), tree(&other).root());
+    }
+
+    #[test]
+    fn every_code_verifies_for_any_leaf_count() {
+        for count in 1..=9 {
+            let codes = codes(count);
+            let tree = tree(&codes);
+            let root = tree.root().unwrap();
+            for code in &codes {
+                let proof = tree.prove(code).unwrap();
+                assert!(verify(&root, code, &tree.salt(code), &proof), "{} of {}", code, count);
+            }
+        }
+    }
+
+    #[test]
+    fn prove_rejects_a_code_outside_the_batch() {
+        assert!(tree(&codes(3)).prove("promo-x").is_none());
+    }
+
+    #[test]
+    fn verify_rejects_tampered_proofs() {
+        let codes = codes(7);
+        let tree = tree(&codes);
+        let root = tree.root().unwrap();
+        let salt = tree.salt("promo-4");
+
+        let mut proof = tree.prove("promo-4").unwrap();
+        proof[0].hash = [0u8; 32].to_hex();
+        assert!(!verify(&root, "promo-4", &salt, &proof));
+
+        let mut proof = tree.prove("promo-4").unwrap();
+        proof[1].side = match proof[1].side {
+            Side::Left => Side::Right,
+            Side::Right => Side::Left,
+        };
+        assert!(!verify(&root, "promo-4", &salt, &proof));
+
+        let mut proof = tree.prove("promo-4").unwrap();
+        proof[0].hash.truncate(10);
+        assert!(!verify(&root, "promo-4", &salt, &proof));
+
+        let proof = tree.prove("promo-4").unwrap();
+        assert!(!verify(&root, "promo-5", &salt, &proof));
+        assert!(!verify(&root, "promo-4", &[0u8; 32], &proof));
+    }
+
+    #[test]
+    fn salts_differ_per_code_and_per_batch() {
+        let tree = tree(&codes(3));
+        assert_ne!(tree.salt("promo-0"), tree.salt("promo-1"));
+        assert_ne!(tree.salt("promo-0"), self::tree(&codes(4)).salt("promo-0"));
+    }
+}
@@ -0,0 +1,671 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Write},
+    path::Path,
+};
+
+use flate2::{read::MultiGzDecoder, write::GzEncoder, Compression};
+use serde_json::json;
+
+use crate::{
+    derive::DerivationScheme,
+    fund::funding_request_row,
+    secret_to_wif,
+    utc::{format_iso8601, now},
+    OptimisedPromoKeypair,
+};
+
+/// The format a batch is saved in
+#[derive(Default, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// One row per code, with a header (the default)
+    #[default]
+    Csv,
+    /// A single JSON array of code objects, rewritten as each code is added
+    Json,
+    /// One JSON object per line
+    Jsonl,
+}
+
+impl OutputFormat {
+    /// The file extension for this format (without the dot)
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Csv => "csv",
+            OutputFormat::Json => "json",
+            OutputFormat::Jsonl => "jsonl",
+        }
+    }
+
+    /// The human-readable name of this format
+    pub fn name(&self) -> &'static str {
+        match self {
+            OutputFormat::Csv => "CSV",
+            OutputFormat::Json => "JSON",
+            OutputFormat::Jsonl => "JSON Lines",
+        }
+    }
+
+    /// Creates (or truncates) a save file, writing anything the format needs before its first code
+    pub fn create(&self, path: &str, layout: CsvLayout) -> io::Result<()> {
+        let mut file = File::create(path)?;
+        self.write_preamble(&mut file, layout)
+    }
+
+    /// Creates (or truncates) a gzip-compressed save file, returning the encoder rows are streamed through.
+    ///
+    /// Only the append-only formats (CSV and JSON Lines) can be streamed.
+    ///
+    pub fn create_gzip(&self, path: &str, layout: CsvLayout) -> io::Result<GzEncoder<File>> {
+        let mut encoder = GzEncoder::new(File::create(path)?, Compression::default());
+        self.write_preamble(&mut encoder, layout)?;
+        encoder.flush()?;
+        Ok(encoder)
+    }
+
+    /// Writes anything the format needs before its first code
+    fn write_preamble(&self, writer: &mut impl Write, layout: CsvLayout) -> io::Result<()> {
+        match self {
+            OutputFormat::Csv => writeln!(writer, "{}", csv_header(layout)),
+            OutputFormat::Json => writeln!(writer, "[]"),
+            OutputFormat::Jsonl => Ok(()),
+        }
+    }
+}
+
+/// Everywhere finished promos are written to as they're generated.
+///
+/// Each promo is recorded immediately (rather than at the end of a run), so that a crash never
+/// loses codes which may have already been funded.
+///
+pub struct OutputSink {
+    /// The file the batch is saved to, if saving is enabled
+    pub save_file: Option<String>,
+    /// The format of the save file
+    pub format: OutputFormat,
+    /// Every promo saved so far, kept for JSON saves (which rewrite the whole array each time)
+    pub saved: Vec<serde_json::Value>,
+    /// The encoder rows are streamed through when the save file is gzip-compressed
+    pub gzip: Option<GzEncoder<File>>,
+    /// The ticker of the selected coin
+    pub coin_ticker: String,
+    /// How values are written in CSVs
+    pub value_format: ValueFormat,
+    /// The run's derivation namespace, recorded with each code in every output
+    pub namespace: Option<String>,
+    /// Any further save files, each written from the same promos as the main one
+    pub extra: Vec<ExtraSave>,
+    /// The WIF vault, if enabled
+    pub wif_vault: Option<WifVault>,
+    /// The funding request of a generate-only run, which each code needing funds is added to
+    pub funding_request: Option<String>,
+    /// Rows held back from the files, if writes are buffered
+    pub buffer: Option<WriteBuffer>,
+    /// Whether each promo is stamped with the time it's recorded
+    pub timestamps: bool,
+    /// Whether CSV rows end with the code's TX ID and whether it's funded, for generate-only runs
+    pub funding_status: bool,
+}
+
+/// Rows held in memory instead of being written as each code finishes, for very large runs where a
+/// write per code is too slow.
+///
+/// This trades crash-resilience for speed: a crash loses every row since the last flush, including
+/// the keys of any codes that were already funded.
+///
+pub struct WriteBuffer {
+    /// How many codes are recorded between flushes (0 only flushes once the run finishes)
+    pub flush_every: usize,
+    /// The lines waiting to be appended, as `(path, line)`
+    lines: Vec<(String, String)>,
+    /// How many codes have been recorded since the last flush
+    recorded: usize,
+}
+
+impl WriteBuffer {
+    /// Buffers writes, flushing every `flush_every` codes (or only at the end, if 0)
+    pub fn new(flush_every: usize) -> WriteBuffer {
+        WriteBuffer {
+            flush_every,
+            lines: Vec::new(),
+            recorded: 0,
+        }
+    }
+}
+
+impl OutputSink {
+    /// Writes a finished promo to every enabled output, first stamping it with the current time if enabled
+    pub fn record(&mut self, promo: &mut OptimisedPromoKeypair) -> io::Result<()> {
+        if self.timestamps {
+            promo.timestamp = Some(format_iso8601(now()));
+        }
+        let promo = &*promo;
+
+        // Save it immediately if saving is enabled
+        if let Some(save_file) = self.save_file.clone() {
+            match self.format {
+                OutputFormat::Csv | OutputFormat::Jsonl => {
+                    let line = match self.format {
+                        OutputFormat::Csv => self.to_csv(promo),
+                        _ => self.to_json(promo).to_string(),
+                    };
+                    match self.gzip {
+                        // Sync-flushed per row (unless buffering), so a crash still leaves every finished row decompressible
+                        Some(ref mut encoder) => {
+                            writeln!(encoder, "{}", line)?;
+                            if self.buffer.is_none() {
+                                encoder.flush()?;
+                            }
+                        }
+                        None => self.append(save_file.clone(), line)?,
+                    }
+                }
+                OutputFormat::Json => {
+                    self.saved.push(self.to_json(promo));
+                    if self.buffer.is_none() {
+                        rewrite_json(&save_file, &self.saved)?;
+                    }
+                }
+            }
+        }
+
+        // Write it to every extra save file, in whichever format each was requested in
+        let json = self.to_json(promo);
+        let mut lines = Vec::new();
+        for extra in &mut self.extra {
+            match extra.format {
+                OutputFormat::Csv => lines.push((extra.path.clone(), self.to_csv(promo))),
+                OutputFormat::Jsonl => lines.push((extra.path.clone(), json.to_string())),
+                OutputFormat::Json => {
+                    extra.saved.push(json.clone());
+                    if self.buffer.is_none() {
+                        rewrite_json(&extra.path, &extra.saved)?;
+                    }
+                }
+            }
+        }
+        if let Some(ref vault) = self.wif_vault {
+            let wif = secret_to_wif(promo.private, vault.version_byte, vault.compressed);
+            lines.push((vault.path.clone(), format!("{},{},{}", promo.code, promo.public, wif)));
+        }
+        if let Some(ref path) = self.funding_request {
+            if let Some(row) = funding_request_row(promo, &self.coin_ticker, &self.value_format) {
+                lines.push((path.clone(), row));
+            }
+        }
+        for (path, line) in lines {
+            self.append(path, line)?;
+        }
+
+        // Write out the buffered rows once enough have built up
+        if let Some(ref mut buffer) = self.buffer {
+            buffer.recorded += 1;
+            if buffer.flush_every > 0 && buffer.recorded >= buffer.flush_every {
+                self.flush()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Appends a line to a file, or holds it back if writes are buffered
+    fn append(&mut self, path: String, line: String) -> io::Result<()> {
+        match self.buffer {
+            Some(ref mut buffer) => {
+                buffer.lines.push((path, line));
+                Ok(())
+            }
+            None => append_line(&path, &line),
+        }
+    }
+
+    /// Writes out every buffered row (a no-op unless writes are buffered)
+    pub fn flush(&mut self) -> io::Result<()> {
+        let Some(ref mut buffer) = self.buffer else {
+            return Ok(());
+        };
+        if buffer.recorded == 0 {
+            return Ok(());
+        }
+
+        // Each file is opened once per flush, rather than once per row
+        let lines = std::mem::take(&mut buffer.lines);
+        buffer.recorded = 0;
+        let mut paths: Vec<&str> = Vec::new();
+        for (path, _) in &lines {
+            if !paths.contains(&path.as_str()) {
+                paths.push(path);
+            }
+        }
+        for path in paths {
+            let mut file = OpenOptions::new().append(true).open(path)?;
+            let mut chunk = String::new();
+            for (_, line) in lines.iter().filter(|(line_path, _)| line_path == path) {
+                chunk.push_str(line);
+                chunk.push('\n');
+            }
+            file.write_all(chunk.as_bytes())?;
+        }
+
+        // JSON saves are rewritten whole, once per flush
+        if let (Some(ref save_file), OutputFormat::Json) = (&self.save_file, self.format) {
+            rewrite_json(save_file, &self.saved)?;
+        }
+        for extra in self.extra.iter().filter(|extra| extra.format == OutputFormat::Json) {
+            rewrite_json(&extra.path, &extra.saved)?;
+        }
+        if let Some(ref mut encoder) = self.gzip {
+            encoder.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Whether every recorded code has been written out (always, unless writes are buffered)
+    pub fn is_flushed(&self) -> bool {
+        self.buffer.as_ref().is_none_or(|buffer| buffer.recorded == 0)
+    }
+
+    /// Returns a promo as a CSV row, along with the run's namespace and the code's funding status (if enabled)
+    fn to_csv(&self, promo: &OptimisedPromoKeypair) -> String {
+        let mut row = csv_row(promo, &self.coin_ticker, &self.value_format);
+        if let Some(ref namespace) = self.namespace {
+            row.push_str(&format!(",{}", namespace));
+        }
+        if self.funding_status {
+            row.push_str(&format!(",{},{}", promo.tx_id.as_deref().unwrap_or_default(), promo.tx_id.is_some()));
+        }
+        row
+    }
+
+    /// Returns a promo as a JSON object, along with the run's namespace (if any)
+    fn to_json(&self, promo: &OptimisedPromoKeypair) -> serde_json::Value {
+        let mut json = promo_to_json(promo, &self.coin_ticker);
+        if let Some(ref namespace) = self.namespace {
+            json["namespace"] = json!(namespace);
+        }
+        json
+    }
+
+    /// Completes the save file, writing out any buffered rows and the gzip trailer if it's compressed
+    pub fn finish(&mut self) -> io::Result<()> {
+        self.flush()?;
+        if let Some(encoder) = self.gzip.take() {
+            encoder.finish()?;
+        }
+        Ok(())
+    }
+}
+
+/// An extra save file, written alongside the main one (for example, a CSV for distribution and a
+/// JSON manifest, from a single run).
+///
+/// Extra saves are never compressed.
+///
+pub struct ExtraSave {
+    /// The path of the file
+    pub path: String,
+    /// The format it's saved in
+    pub format: OutputFormat,
+    /// Every promo saved so far, for JSON saves
+    saved: Vec<serde_json::Value>,
+}
+
+impl ExtraSave {
+    /// Creates (or truncates) an extra save file, writing anything the format needs before its first code
+    pub fn create(path: &str, format: OutputFormat, layout: CsvLayout) -> io::Result<ExtraSave> {
+        format.create(path, layout)?;
+        Ok(ExtraSave {
+            path: path.to_owned(),
+            format,
+            saved: Vec::new(),
+        })
+    }
+}
+
+/// A plain CSV of every promo's WIF (`code,address,wif`), for importing or sweeping the keys later.
+///
+/// It's restricted to the current user (`0600`) on Unix before any key is written to it.
+///
+pub struct WifVault {
+    /// The path of the file
+    pub path: String,
+    /// The coin's WIF version byte
+    version_byte: u8,
+    /// Whether the coin's WIFs are flagged as compressed
+    compressed: bool,
+}
+
+impl WifVault {
+    /// Creates (or truncates) a WIF vault, writing its header
+    pub fn create(path: &str, version_byte: u8, compressed: bool) -> io::Result<WifVault> {
+        File::create(path)?;
+        restrict_permissions(Path::new(path), 0o600)?;
+        append_line(path, "code,address,wif")?;
+        Ok(WifVault {
+            path: path.to_owned(),
+            version_byte,
+            compressed,
+        })
+    }
+}
+
+/// Appends a single line to a file
+fn append_line(path: &str, line: &str) -> io::Result<()> {
+    let mut file = OpenOptions::new().append(true).open(path)?;
+    writeln!(file, "{}", line)
+}
+
+/// Rewrites a JSON save with every promo so far, via a temporary file so a crash mid-write never
+/// leaves a truncated array
+fn rewrite_json(path: &str, saved: &[serde_json::Value]) -> io::Result<()> {
+    let tmp_file = format!("{}.tmp", path);
+    fs::write(&tmp_file, serde_json::to_string_pretty(saved)? + "\n")?;
+    fs::rename(&tmp_file, path)
+}
+
+/// Restricts a path's permissions, for anything holding secrets (a no-op outside of Unix)
+#[cfg(unix)]
+pub fn restrict_permissions(path: &Path, mode: u32) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+}
+
+#[cfg(not(unix))]
+pub fn restrict_permissions(_path: &Path, _mode: u32) -> io::Result<()> {
+    Ok(())
+}
+
+/// Checks a save file name is a plain base name, which can't escape the output (or working) directory.
+///
+/// # Returns
+///
+/// `Ok` if the name is safe, otherwise the reason it isn't.
+///
+pub fn validate_filename(name: &str) -> Result<(), String> {
+    if name.trim().is_empty() {
+        return Err(String::from("The file name can't be empty"));
+    }
+    if name.contains(['/', '\\', ':', '\0']) {
+        return Err(format!("\"{}\" isn't a plain file name (no paths or drive letters)", name));
+    }
+    if name == "." || name == ".." {
+        return Err(format!("\"{}\" isn't a valid file name", name));
+    }
+    Ok(())
+}
+
+/// Whether a path is gzip-compressed, going by its extension
+pub fn is_gzip(path: &str) -> bool {
+    path.ends_with(".gz")
+}
+
+/// Reads a text file, transparently decompressing it if it's gzipped (`.gz`)
+pub fn read_text(path: &str) -> io::Result<String> {
+    if !is_gzip(path) {
+        return fs::read_to_string(path);
+    }
+    let mut contents = String::new();
+    MultiGzDecoder::new(File::open(path)?).read_to_string(&mut contents)?;
+    Ok(contents)
+}
+
+/// Writes a text file, gzip-compressing it if requested
+pub fn write_text(path: &str, contents: &str, gzip: bool) -> io::Result<()> {
+    if !gzip {
+        return fs::write(path, contents);
+    }
+    let mut encoder = GzEncoder::new(File::create(path)?, Compression::default());
+    encoder.write_all(contents.as_bytes())?;
+    encoder.finish()?;
+    Ok(())
+}
+
+/// A CSV of every code that couldn't be funded, for manual follow-up.
+///
+/// It has `address` and `value` columns, so it can be retried as-is with the `fund` command. The
+/// file is only created on the first failure, so a clean run leaves nothing behind.
+///
+pub struct FailedCsv {
+    /// The path of the CSV
+    pub path: String,
+    /// How many failures have been written
+    pub count: usize,
+}
+
+impl FailedCsv {
+    /// Prepares a failed-codes CSV alongside a save file (or batch name), as `<name>_failed.csv`
+    pub fn alongside(name: &str) -> FailedCsv {
+        let stem = Path::new(name).with_extension("");
+        FailedCsv {
+            path: format!("{}_failed.csv", stem.to_string_lossy()),
+            count: 0,
+        }
+    }
+
+    /// Picks up the failed-codes CSV of an interrupted run, appending to it rather than starting over
+    pub fn continuing(name: &str) -> FailedCsv {
+        let mut failed = FailedCsv::alongside(name);
+        failed.count = fs::read_to_string(&failed.path)
+            .map_or(0, |contents| contents.lines().skip(1).filter(|line| !line.trim().is_empty()).count());
+        failed
+    }
+
+    /// Records a code that couldn't be funded, with its intended value and the last error
+    pub fn record(&mut self, promo: &OptimisedPromoKeypair, value: f64, error: &str) -> io::Result<()> {
+        let mut file = if self.count == 0 {
+            let mut file = File::create(&self.path)?;
+            writeln!(file, "code,address,value,error")?;
+            file
+        } else {
+            OpenOptions::new().append(true).open(&self.path)?
+        };
+        // The error is free text, so anything that would break the row (commas, newlines) is replaced
+        let error = error.replace(',', ";").replace(['\n', '\r'], " ");
+        writeln!(file, "{},{},{},{}", promo.code, promo.public, value, error)?;
+        self.count += 1;
+        Ok(())
+    }
+}
+
+/// Which optional columns a CSV has, decided before its header is written
+#[derive(Clone, Copy, Default)]
+pub struct CsvLayout {
+    /// A signature column, if codes are being signed
+    pub signature: bool,
+    /// A note column, if any batch has a note
+    pub note: bool,
+    /// A column of each WIF's SHA256, committing to the keys without revealing them
+    pub wif_hash: bool,
+    /// A column of when each code was completed
+    pub timestamp: bool,
+    /// A column of the derivation scheme's version, for codes not derived with v1
+    pub derivation: bool,
+    /// A column of the iteration target each code was derived with
+    pub target: bool,
+    /// A column of the run's derivation namespace, for namespaced runs
+    pub namespace: bool,
+    /// Columns of each code's TX ID and whether it's funded, for generate-only runs
+    pub funding_status: bool,
+    /// End the header with a comma, as older versions did
+    pub trailing_comma: bool,
+}
+
+/// Returns the CSV header line, with any optional columns in the layout.
+///
+/// Rows never end in a separator, so neither does the header, unless `trailing_comma` asks for
+/// the legacy layout (an empty final column) that older tooling may still expect.
+///
+pub fn csv_header(layout: CsvLayout) -> String {
+    let mut header = String::from("coin,value,code");
+    if layout.signature {
+        header.push_str(",signature");
+    }
+    if layout.note {
+        header.push_str(",note");
+    }
+    if layout.wif_hash {
+        header.push_str(",wif_hash");
+    }
+    if layout.timestamp {
+        header.push_str(",timestamp");
+    }
+    if layout.derivation {
+        header.push_str(",derivation");
+    }
+    if layout.target {
+        header.push_str(",target");
+    }
+    if layout.namespace {
+        header.push_str(",namespace");
+    }
+    if layout.funding_status {
+        header.push_str(",tx_id,funded");
+    }
+    if layout.trailing_comma {
+        header.push(',');
+    }
+    header
+}
+
+/// How values are written in CSVs, so the column is uniform whatever the float happens to print as
+#[derive(Clone, Copy)]
+pub struct ValueFormat {
+    /// The number of decimal places the coin supports
+    pub decimals: u32,
+    /// Trim trailing zeros (and a trailing decimal point), rather than always printing every decimal
+    pub trim_zeros: bool,
+}
+
+impl ValueFormat {
+    /// Formats a value to the coin's precision, e.g. `5.00000000` (or `5`, when trimming)
+    pub fn format(&self, value: f64) -> String {
+        let fixed = format!("{:.*}", self.decimals as usize, value);
+        if self.trim_zeros && fixed.contains('.') {
+            fixed.trim_end_matches('0').trim_end_matches('.').to_owned()
+        } else {
+            fixed
+        }
+    }
+}
+
+/// Returns a single CSV row for a promo, including its signature, note, WIF hash, derivation and target (if any)
+pub fn csv_row(promo: &OptimisedPromoKeypair, coin_ticker: &str, value_format: &ValueFormat) -> String {
+    // Store the selected coin ticker in the CSV
+    let mut row = format!(
+        "{},{},{}",
+        coin_ticker.to_lowercase(),
+        value_format.format(promo.value),
+        promo.code
+    );
+    if let Some(signature) = &promo.signature {
+        row.push_str(&format!(",{}", signature));
+    }
+    if let Some(note) = &promo.note {
+        row.push_str(&format!(",{}", note));
+    }
+    if let Some(wif_hash) = &promo.wif_hash {
+        row.push_str(&format!(",{}", wif_hash));
+    }
+    if let Some(timestamp) = &promo.timestamp {
+        row.push_str(&format!(",{}", timestamp));
+    }
+    // v1 rows are left as they always were, so only other schemes are marked
+    if promo.derivation != DerivationScheme::V1 {
+        row.push_str(&format!(",{}", promo.derivation.version()));
+    }
+    if let Some(target) = promo.target {
+        row.push_str(&format!(",{}", target));
+    }
+    row
+}
+
+/// Returns a promo as a single-line JSON object, for JSON Lines streaming
+pub fn promo_to_json(promo: &OptimisedPromoKeypair, coin_ticker: &str) -> serde_json::Value {
+    json!({
+        "coin": coin_ticker.to_lowercase(),
+        "value": promo.value,
+        "code": promo.code,
+        "address": promo.public,
+        "tx_id": promo.tx_id,
+        "signature": promo.signature.map(|s| s.to_string()),
+        "derivation": promo.derivation.version(),
+        "note": promo.note,
+        "wif_hash": promo.wif_hash,
+        "timestamp": promo.timestamp,
+        "target": promo.target,
+    })
+}
+
+/// A single promo, as read back from a saved CSV
+pub struct CsvRecord {
+    /// The coin ticker (lowercase)
+    pub coin: String,
+    /// The value the promo was filled with
+    pub value: f64,
+    /// The promo code
+    pub code: String,
+    /// The promo's address, if the CSV has an `address` column
+    pub address: Option<String>,
+    /// The SHA256 of the promo's WIF (as hex), if the CSV has a `wif_hash` column
+    pub wif_hash: Option<String>,
+}
+
+/// Reads back the promos of a JSON save, as the objects they were saved as
+pub fn read_json_save(path: &str) -> io::Result<Vec<serde_json::Value>> {
+    Ok(serde_json::from_str(&read_text(path)?)?)
+}
+
+/// Reads back every code of a save, in any format
+pub fn read_saved_codes(path: &str, format: OutputFormat) -> io::Result<Vec<String>> {
+    let objects = match format {
+        OutputFormat::Csv => return Ok(read_csv(path)?.into_iter().map(|record| record.code).collect()),
+        OutputFormat::Json => read_json_save(path)?,
+        OutputFormat::Jsonl => read_text(path)?
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(serde_json::from_str)
+            .collect::<Result<_, _>>()?,
+    };
+    Ok(objects
+        .iter()
+        .filter_map(|object| object["code"].as_str().map(str::to_owned))
+        .collect())
+}
+
+/// Reads the promos back from a CSV saved by Batcher.
+///
+/// The header is only used to find the optional `address` and `wif_hash` columns, any other extra
+/// columns (such as signatures) are skipped. Gzipped CSVs are read transparently.
+///
+/// # Returns
+///
+/// The records, or an error describing the first malformed line.
+///
+pub fn read_csv(path: &str) -> io::Result<Vec<CsvRecord>> {
+    let contents = read_text(path)?;
+    let find_col = |name: &str| {
+        contents
+            .lines()
+            .next()
+            .and_then(|header| header.split(',').position(|column| column.trim() == name))
+    };
+    let (address_col, wif_hash_col) = (find_col("address"), find_col("wif_hash"));
+    let mut records = Vec::new();
+    for (i, line) in contents.lines().enumerate().skip(1) {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.split(',').collect();
+        let malformed = || io::Error::new(io::ErrorKind::InvalidData, format!("line {} is malformed: \"{}\"", i + 1, line));
+        if fields.len() < 3 {
+            return Err(malformed());
+        }
+        records.push(CsvRecord {
+            coin: fields[0].to_owned(),
+            value: fields[1].parse().map_err(|_| malformed())?,
+            code: fields[2].to_owned(),
+            address: address_col.and_then(|col| fields.get(col)).map(|address| address.trim().to_owned()),
+            wif_hash: wif_hash_col.and_then(|col| fields.get(col)).map(|hash| hash.trim().to_owned()),
+        });
+    }
+    Ok(records)
+}
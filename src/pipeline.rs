@@ -0,0 +1,148 @@
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, Receiver},
+        Arc,
+    },
+    thread::Scope,
+};
+
+use crate::{
+    code::CodeFormat, coins::CoinParams, create_promo_key, derive::Deriver, double_check_promo, OptimisedPromoKeypair,
+};
+
+/// Promos derived in parallel by worker threads, handed out in the order they were submitted.
+///
+/// Workers claim job numbers from a shared counter, and send each finished promo down a bounded
+/// channel tagged with its number. They finish out of order, so completions are buffered until the
+/// next promo in order arrives: the single consumer (which signs, funds and writes each promo) always
+/// sees them in submission order, so outputs stay ordered however the workers interleave.
+///
+pub struct OrderedPromos {
+    /// Finished promos, as `(job, promo)`
+    receiver: Receiver<(u64, OptimisedPromoKeypair)>,
+    /// Promos which finished before an earlier job
+    buffered: BTreeMap<u64, OptimisedPromoKeypair>,
+    /// The next job to hand out
+    next: u64,
+}
+
+impl OrderedPromos {
+    /// Starts deriving promos on worker threads, within a thread scope.
+    ///
+    /// # Arguments
+    ///
+    /// * `scope` - The scope the workers run in, which outlives every promo being handed out.
+    /// * `total` - How many promos to derive, after which the workers stop.
+    /// * `threads` - How many workers to derive with.
+    /// * `format` - The format of the promo codes.
+    /// * `deriver` - The Deriver for the run's scheme.
+    /// * `coin_params` - A reference to the CoinParams for the selected coin.
+    ///
+    pub fn start<'scope, 'env>(
+        scope: &'scope Scope<'scope, 'env>,
+        total: u64,
+        threads: usize,
+        format: &'env CodeFormat,
+        deriver: &'env Deriver,
+        coin_params: &'env CoinParams,
+    ) -> OrderedPromos {
+        // Bounded, so workers never race far ahead of a consumer that's waiting on slow fills
+        let (sender, receiver) = mpsc::sync_channel(threads.max(1));
+        let claimed = Arc::new(AtomicU64::new(0));
+        for _ in 0..threads.max(1) {
+            let (sender, claimed) = (sender.clone(), Arc::clone(&claimed));
+            scope.spawn(move || loop {
+                let job = claimed.fetch_add(1, Ordering::Relaxed);
+                if job >= total {
+                    return;
+                }
+                let promo = create_promo_key(format, deriver, coin_params);
+                double_check_promo(&promo, deriver, coin_params);
+                // The consumer only hangs up once it's finished, so there's nothing left to do
+                if sender.send((job, promo)).is_err() {
+                    return;
+                }
+            });
+        }
+        OrderedPromos {
+            receiver,
+            buffered: BTreeMap::new(),
+            next: 0,
+        }
+    }
+
+    /// Waits for the next promo in submission order
+    pub fn next_promo(&mut self) -> OptimisedPromoKeypair {
+        loop {
+            if let Some(promo) = self.buffered.remove(&self.next) {
+                self.next += 1;
+                return promo;
+            }
+            let (job, promo) = self.receiver.recv().expect("every derivation worker stopped early");
+            self.buffered.insert(job, promo);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::derive::DerivationScheme;
+    use secp256k1::SecretKey;
+
+    /// A placeholder promo, told apart from others only by its code
+    fn promo(code: &str) -> OptimisedPromoKeypair {
+        OptimisedPromoKeypair {
+            private: SecretKey::from_slice(&[1; 32]).unwrap(),
+            public: String::new(),
+            code: code.to_string(),
+            value: 0.0,
+            signature: None,
+            tx_id: None,
+            derivation: DerivationScheme::V1,
+            note: None,
+            wif_hash: None,
+            timestamp: None,
+            target: None,
+        }
+    }
+
+    fn ordered(receiver: Receiver<(u64, OptimisedPromoKeypair)>) -> OrderedPromos {
+        OrderedPromos {
+            receiver,
+            buffered: BTreeMap::new(),
+            next: 0,
+        }
+    }
+
+    #[test]
+    fn hands_out_promos_in_submission_order() {
+        let (sender, receiver) = mpsc::sync_channel(4);
+        for job in [2, 0, 3, 1] {
+            sender.send((job, promo(&job.to_string()))).unwrap();
+        }
+        let mut promos = ordered(receiver);
+        let codes: Vec<String> = (0..4).map(|_| promos.next_promo().code).collect();
+        assert_eq!(codes, ["0", "1", "2", "3"]);
+        assert!(promos.buffered.is_empty());
+    }
+
+    #[test]
+    fn waits_for_a_late_job_across_threads() {
+        let (sender, receiver) = mpsc::sync_channel(1);
+        let mut promos = ordered(receiver);
+        std::thread::scope(|scope| {
+            // Every later job finishes first, so all of them are buffered until job 0 arrives
+            scope.spawn(move || {
+                for job in (0..8).rev() {
+                    sender.send((job, promo(&job.to_string()))).unwrap();
+                }
+            });
+            for job in 0..8 {
+                assert_eq!(promos.next_promo().code, job.to_string());
+            }
+        });
+    }
+}
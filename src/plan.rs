@@ -0,0 +1,329 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{coins::CoinParams, get_safe_rand, output::CsvRecord};
+
+/// A struct representing a promo batch request.
+///
+/// This struct contains the Value and the Quantity of the batch it represents, and an optional
+/// descriptive note (e.g. "Q1 giveaway") written alongside each of its codes.
+///
+/// With a `max_value`, the batch is randomised ("mystery box" style): each code is worth a random
+/// value between `value` and `max_value`, in multiples of `step` above `value`.
+///
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PromoBatch {
+    /// The value of the batch
+    pub value: f64,
+    /// The quantity of the batch
+    pub qty: u64,
+    /// A note describing the batch, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+    /// The maximum value of each code, if the batch has random values (with `value` as the minimum)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_value: Option<f64>,
+    /// The step between random values, instead of the coin's smallest unit
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub step: Option<f64>,
+}
+
+impl PromoBatch {
+    /// Checks the batch is generatable: at least one code, and a finite, non-negative value
+    pub fn validate(&self) -> Result<(), String> {
+        if self.qty == 0 {
+            return Err(String::from("a batch needs at least one code"));
+        }
+        if !self.value.is_finite() || self.value < 0.0 {
+            return Err(format!("\"{}\" is not a valid value", self.value));
+        }
+        if let Some(max_value) = self.max_value {
+            if !max_value.is_finite() || max_value < self.value {
+                return Err(format!("\"{}\" is not a valid maximum value (it must be at least {})", max_value, self.value));
+            }
+        }
+        if self.step.is_some_and(|step| !step.is_finite() || step <= 0.0) {
+            return Err(String::from("the random value step must be above zero"));
+        }
+        // Notes are written as a plain CSV column, so they can't contain anything that would break a row
+        if self.note.as_ref().is_some_and(|note| note.contains([',', '\n', '\r'])) {
+            return Err(String::from("a batch note can't contain commas or line breaks"));
+        }
+        Ok(())
+    }
+
+    /// The most a single code of the batch can be worth
+    pub fn max_value(&self) -> f64 {
+        self.max_value.unwrap_or(self.value)
+    }
+
+    /// Whether any of the batch's funded codes could be worth no more than the coin's dust threshold,
+    /// leaving funds that can never be economically redeemed
+    pub fn is_dust(&self, coin_params: &CoinParams) -> bool {
+        self.max_value() > 0.0 && self.value <= coin_params.dust_threshold
+    }
+
+    /// Picks the value of one of the batch's codes: the fixed value, or a random one within its range
+    pub fn pick_value(&self, coin_params: &CoinParams) -> f64 {
+        let Some(max_value) = self.max_value else {
+            return self.value;
+        };
+        let step = self.step.unwrap_or(10f64.powi(-(coin_params.decimals as i32)));
+        // The tolerance keeps a range that's an exact multiple of the step from losing its top value
+        let steps = ((max_value - self.value) / step + 1e-9).floor() as u64;
+        let random = u64::from_le_bytes(get_safe_rand(8).try_into().unwrap());
+        let value = self.value + (random % (steps + 1)) as f64 * step;
+        round_to_coin(value.min(max_value), coin_params).0
+    }
+}
+
+/// A full plan of promo batches, with all of the batch math in one place.
+///
+/// Every way of planning batches (interactive or otherwise) produces a `BatchPlan`.
+///
+#[derive(Default)]
+pub struct BatchPlan {
+    pub batches: Vec<PromoBatch>,
+}
+
+impl BatchPlan {
+    /// Adds a batch to the plan, if it's valid
+    pub fn push(&mut self, batch: PromoBatch) -> Result<(), String> {
+        batch.validate()?;
+        self.batches.push(batch);
+        Ok(())
+    }
+
+    /// The number of batches in the plan
+    pub fn len(&self) -> usize {
+        self.batches.len()
+    }
+
+    /// Whether the plan has no batches at all
+    pub fn is_empty(&self) -> bool {
+        self.batches.is_empty()
+    }
+
+    /// Checks every batch in the plan, and that there's at least one
+    pub fn validate(&self) -> Result<(), String> {
+        if self.is_empty() {
+            return Err(String::from("no batches are planned"));
+        }
+        for (i, batch) in self.batches.iter().enumerate() {
+            batch.validate().map_err(|e| format!("batch {}: {}", i + 1, e))?;
+        }
+        Ok(())
+    }
+
+    /// Whether any batch has a note, in which case every code gets a note column
+    pub fn has_notes(&self) -> bool {
+        self.batches.iter().any(|b| b.note.is_some())
+    }
+
+    /// The total number of codes across all batches
+    pub fn total_codes(&self) -> u64 {
+        self.batches.iter().map(|b| b.qty).sum()
+    }
+
+    /// The total value of all codes, excluding fees (at most, if any batch has random values)
+    pub fn total_value(&self) -> f64 {
+        self.batches.iter().map(|b| b.max_value() * b.qty as f64).sum()
+    }
+
+    /// The number of codes which will be funded (i.e. may have a value)
+    pub fn funded_codes(&self) -> u64 {
+        self.batches.iter().filter(|b| b.max_value() > 0.0).map(|b| b.qty).sum()
+    }
+
+    /// Whether any code in the plan needs funding
+    pub fn needs_funding(&self) -> bool {
+        self.funded_codes() > 0
+    }
+
+    /// The total redeemer fees added on top of each funded code
+    pub fn total_fees(&self, coin_params: &CoinParams) -> f64 {
+        self.funded_codes() as f64 * coin_params.promo_fee
+    }
+
+    /// The total value plus redeemer fees: the balance the run needs (before the wallet's own TX fees)
+    pub fn total_with_fees(&self, coin_params: &CoinParams) -> f64 {
+        self.total_value() + self.total_fees(coin_params)
+    }
+}
+
+/// Rounds a value to the number of decimals a coin supports.
+///
+/// # Returns
+///
+/// The rounded value, and whether any precision was lost.
+///
+pub fn round_to_coin(value: f64, coin_params: &CoinParams) -> (f64, bool) {
+    let scale = 10f64.powi(coin_params.decimals as i32);
+    let rounded = (value * scale).round() / scale;
+    (rounded, (rounded - value).abs() > f64::EPSILON * value.abs().max(1.0))
+}
+
+/// Splits a percentage of the remaining budget evenly across a batch's codes, rounding each code's
+/// value down to the coin's precision, so the batch never spends more than its share.
+///
+/// # Arguments
+///
+/// * `remaining` - The budget not yet planned for.
+/// * `percent` - The share of it to spend, from 0 to 100.
+/// * `qty` - The number of codes to split it across.
+/// * `coin_params` - A reference to the CoinParams for the selected coin.
+///
+/// # Returns
+///
+/// The value of each code.
+///
+pub fn share_of_budget(remaining: f64, percent: f64, qty: u64, coin_params: &CoinParams) -> f64 {
+    let scale = 10f64.powi(coin_params.decimals as i32);
+    // The small nudge keeps float error (e.g. 28999999.9999) from rounding down a whole unit
+    (remaining * percent / 100.0 / qty as f64 * scale + 1e-6).floor() / scale
+}
+
+/// Converts a value into whole units of the coin's smallest denomination (satoshis, for 8 decimal
+/// coins), so it can be compared and used as a key exactly
+pub fn to_sats(value: f64, coin_params: &CoinParams) -> u64 {
+    (value * 10f64.powi(coin_params.decimals as i32)).round() as u64
+}
+
+/// Converts whole units of the coin's smallest denomination back into a value, undoing `to_sats`
+pub fn from_sats(sats: u64, coin_params: &CoinParams) -> f64 {
+    sats as f64 / 10f64.powi(coin_params.decimals as i32)
+}
+
+/// Prints what running a plan on top of an existing CSV would add: per value, how many codes
+/// already exist, how many more would be generated, and the incremental cost.
+///
+/// # Arguments
+///
+/// * `plan` - The new batch plan.
+/// * `existing` - The records of the existing CSV.
+/// * `coin_params` - A reference to the CoinParams for the selected coin.
+///
+pub fn print_plan_diff(plan: &BatchPlan, existing: &[CsvRecord], coin_params: &CoinParams) {
+    // Tally up the planned and existing codes by value
+    let mut planned: BTreeMap<u64, u64> = BTreeMap::new();
+    for batch in &plan.batches {
+        *planned.entry(to_sats(batch.value, coin_params)).or_default() += batch.qty;
+    }
+    let mut have: BTreeMap<u64, u64> = BTreeMap::new();
+    for record in existing {
+        *have.entry(to_sats(record.value, coin_params)).or_default() += 1;
+    }
+
+    humanln!("----------------------------------------------");
+    let mut new_codes = 0;
+    let mut new_cost = 0.0;
+    for (sats, qty) in &planned {
+        let value = from_sats(*sats, coin_params);
+        let existing_qty = have.get(sats).copied().unwrap_or(0);
+        let missing = qty.saturating_sub(existing_qty);
+        if missing == 0 {
+            humanln!(
+                " - {} {}: already satisfied ({} planned, {} existing)",
+                value, coin_params.ticker, qty, existing_qty
+            );
+        } else {
+            humanln!(
+                " - {} {}: {} new codes ({} planned, {} existing)",
+                value, coin_params.ticker, missing, qty, existing_qty
+            );
+            new_codes += missing;
+            if value > 0.0 {
+                new_cost += missing as f64 * (value + coin_params.promo_fee);
+            }
+        }
+    }
+    for (sats, existing_qty) in have.iter().filter(|(sats, _)| !planned.contains_key(sats)) {
+        humanln!(
+            " - {} {}: {} existing codes, not part of the plan",
+            from_sats(*sats, coin_params),
+            coin_params.ticker,
+            existing_qty
+        );
+    }
+    humanln!(
+        "... a run would add {} codes, costing {} {} (including redeemer fees)",
+        new_codes, new_cost, coin_params.ticker
+    );
+    humanln!("----------------------------------------------");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coins::fixtures::piv;
+
+    fn batch(value: f64, qty: u64) -> PromoBatch {
+        PromoBatch { value, qty, note: None, max_value: None, step: None }
+    }
+
+    fn plan() -> BatchPlan {
+        let mut plan = BatchPlan::default();
+        plan.push(batch(5.0, 10)).unwrap();
+        plan.push(batch(0.0, 3)).unwrap();
+        plan.push(PromoBatch { max_value: Some(3.0), ..batch(1.0, 2) }).unwrap();
+        plan
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-9, "{} != {}", actual, expected);
+    }
+
+    #[test]
+    fn validate_rejects_empty_plans_and_bad_batches() {
+        assert!(BatchPlan::default().validate().is_err());
+        assert!(plan().validate().is_ok());
+
+        let bad = BatchPlan { batches: vec![batch(1.0, 1), batch(1.0, 0)] };
+        assert_eq!(bad.validate().unwrap_err(), "batch 2: a batch needs at least one code");
+        assert!(BatchPlan { batches: vec![batch(-1.0, 1)] }.validate().is_err());
+        assert!(BatchPlan { batches: vec![batch(f64::NAN, 1)] }.validate().is_err());
+        assert!(BatchPlan { batches: vec![PromoBatch { max_value: Some(0.5), ..batch(1.0, 1) }] }.validate().is_err());
+        assert!(BatchPlan { batches: vec![PromoBatch { note: Some(String::from("a,b")), ..batch(1.0, 1) }] }
+            .validate()
+            .is_err());
+    }
+
+    #[test]
+    fn totals_count_random_batches_at_their_maximum() {
+        let plan = plan();
+        assert_eq!(plan.total_codes(), 15);
+        assert_close(plan.total_value(), 56.0);
+    }
+
+    #[test]
+    fn funded_codes_skip_zero_value_batches() {
+        assert_eq!(plan().funded_codes(), 12);
+        assert!(!BatchPlan { batches: vec![batch(0.0, 5)] }.needs_funding());
+    }
+
+    #[test]
+    fn total_with_fees_adds_a_fee_per_funded_code() {
+        assert_close(plan().total_with_fees(&piv()), 56.0 + 12.0 * piv().promo_fee);
+    }
+
+    #[test]
+    fn share_of_budget_splits_evenly_and_rounds_down() {
+        let piv = piv();
+        assert_close(share_of_budget(100.0, 30.0, 3, &piv), 10.0);
+        assert_close(share_of_budget(10.0, 100.0, 3, &piv), 3.33333333);
+        // Float error must not cost a whole unit
+        assert_close(share_of_budget(290.0, 100.0, 10, &piv), 29.0);
+        assert!(share_of_budget(10.0, 100.0, 3, &piv) * 3.0 <= 10.0);
+    }
+
+    #[test]
+    fn to_sats_scales_by_the_coins_decimals() {
+        let piv = piv();
+        assert_eq!(to_sats(1.5, &piv), 150_000_000);
+        assert_eq!(to_sats(0.1 + 0.2, &piv), 30_000_000);
+        let cents = CoinParams { decimals: 2, ..piv };
+        assert_eq!(to_sats(1.5, &cents), 150);
+        assert_eq!(from_sats(to_sats(12.34, &cents), &cents), 12.34);
+    }
+}
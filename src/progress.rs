@@ -0,0 +1,74 @@
+use std::{
+    fs,
+    io::{self, Write},
+    time::Instant,
+};
+
+use serde_json::{json, Value};
+
+/// Emits a run's progress as JSON Lines events, for GUIs and orchestrators to render their own progress.
+///
+/// An event is written as each code finishes, for example:
+///
+/// ```json
+/// {"type":"progress","done":3,"total":10,"batch":1,"batches":2,"elapsed":42,"eta":98}
+/// ```
+///
+/// `elapsed` and `eta` are in seconds, with `eta` extrapolated from the average time per code so far.
+/// A final `done` event (of the same shape) is written once the run finishes.
+///
+pub struct ProgressReporter {
+    stream: Box<dyn Write>,
+    started: Instant,
+    done: u64,
+    total: u64,
+    batches: usize,
+}
+
+impl ProgressReporter {
+    /// Opens a progress stream to a file, or to stdout for "-" (which human output then stays off)
+    pub fn open(path: &str, total: u64, batches: usize) -> io::Result<Self> {
+        let stream: Box<dyn Write> = match path {
+            "-" => Box::new(io::stdout()),
+            path => Box::new(fs::File::create(path)?),
+        };
+        Ok(ProgressReporter {
+            stream,
+            started: Instant::now(),
+            done: 0,
+            total,
+            batches,
+        })
+    }
+
+    /// Records that a code of the given batch (counting from 1) has finished
+    pub fn code_done(&mut self, batch: usize) {
+        self.done += 1;
+        self.emit("progress", batch);
+    }
+
+    /// Records that the whole run has finished
+    pub fn finish(&mut self) {
+        self.emit("done", self.batches);
+    }
+
+    /// Writes a single event. Progress is only informational, so a failed write never stops the run.
+    fn emit(&mut self, kind: &str, batch: usize) {
+        let elapsed = self.started.elapsed().as_secs_f64();
+        let eta = match self.done {
+            0 => Value::Null,
+            done => json!((elapsed / done as f64 * self.total.saturating_sub(done) as f64).round() as u64),
+        };
+        let event = json!({
+            "type": kind,
+            "done": self.done,
+            "total": self.total,
+            "batch": batch,
+            "batches": self.batches,
+            "elapsed": elapsed.round() as u64,
+            "eta": eta,
+        });
+        writeln!(self.stream, "{}", event).ok();
+        self.stream.flush().ok();
+    }
+}
@@ -0,0 +1,92 @@
+use std::{fs, io, path::Path};
+
+use image::Luma;
+use qrcode::{render::svg, QrCode};
+
+use crate::output::restrict_permissions;
+
+/// The image format of exported QR codes
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+pub enum QrFormat {
+    /// Raster PNG images
+    #[default]
+    Png,
+    /// Scalable SVG images, for high-res printing
+    Svg,
+}
+
+impl QrFormat {
+    /// The file extension for this format
+    pub fn extension(&self) -> &'static str {
+        match self {
+            QrFormat::Png => "png",
+            QrFormat::Svg => "svg",
+        }
+    }
+}
+
+/// What the QR code of each promo encodes
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+pub enum QrPayload {
+    /// The promo code itself, for redeeming
+    #[default]
+    Code,
+    /// A payment URI of the promo's address and value, for scanning straight into a wallet
+    Uri,
+}
+
+/// The minimum size (in pixels, or SVG units) of an exported QR code
+pub const QR_MIN_DIMENSION: u32 = 256;
+
+/// Renders a payload as a QR code, saving it into a directory as `<name>.<ext>`.
+///
+/// # Arguments
+///
+/// * `dir` - The directory to save into, which is created if missing.
+/// * `name` - The file name, without an extension.
+/// * `payload` - The data encoded in the QR code.
+/// * `format` - The image format to save as.
+///
+/// # Returns
+///
+/// The path of the saved file.
+///
+pub fn save_qr(dir: &str, name: &str, payload: &str, format: QrFormat) -> io::Result<String> {
+    fs::create_dir_all(dir)?;
+    let path = Path::new(dir).join(format!("{}.{}", name, format.extension()));
+    let code = QrCode::new(payload.as_bytes()).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+    match format {
+        QrFormat::Png => code
+            .render::<Luma<u8>>()
+            .min_dimensions(QR_MIN_DIMENSION, QR_MIN_DIMENSION)
+            .build()
+            .save(&path)
+            .map_err(io::Error::other)?,
+        QrFormat::Svg => fs::write(
+            &path,
+            code.render::<svg::Color>()
+                .min_dimensions(QR_MIN_DIMENSION, QR_MIN_DIMENSION)
+                .build(),
+        )?,
+    }
+    Ok(path.to_string_lossy().into_owned())
+}
+
+/// Renders a secret (such as a WIF) as a QR code, for paper cold-storage backups.
+///
+/// This is `save_qr`, except the directory and file are restricted to the current user (`0700` and
+/// `0600`) on Unix, before anything secret is written to them.
+///
+/// # Returns
+///
+/// The path of the saved file.
+///
+pub fn save_secret_qr(dir: &str, name: &str, secret: &str, format: QrFormat) -> io::Result<String> {
+    fs::create_dir_all(dir)?;
+    restrict_permissions(Path::new(dir), 0o700)?;
+    let path = Path::new(dir).join(format!("{}.{}", name, format.extension()));
+    fs::File::create(&path)?;
+    restrict_permissions(&path, 0o600)?;
+    save_qr(dir, name, secret, format)
+}
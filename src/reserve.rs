@@ -0,0 +1,86 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::Path,
+};
+
+use crate::{
+    code::CodeFormat, coins::CoinParams, derive::Deriver, output::restrict_permissions, pipeline::OrderedPromos,
+    style,
+};
+
+/// An exclusive lock on a reserve file, held for the whole of a draw (or a reserve) so two draws never
+/// issue the same code, and no rows are lost to a concurrent rewrite. It's a `<path>.lock` file, removed
+/// once the lock is dropped.
+pub struct ReserveLock {
+    path: String,
+}
+
+impl ReserveLock {
+    /// Takes the lock, failing if another draw (or reserve) holds it
+    pub fn acquire(reserve: &str) -> io::Result<ReserveLock> {
+        let path = format!("{}.lock", reserve);
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(_) => Ok(ReserveLock { path }),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => Err(io::Error::new(
+                io::ErrorKind::WouldBlock,
+                format!("the reserve is in use by another draw or reserve (if none is running, one crashed: remove \"{}\")", path),
+            )),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl Drop for ReserveLock {
+    fn drop(&mut self) {
+        fs::remove_file(&self.path).ok();
+    }
+}
+
+/// The header of a reserve file: the leading `coin,value,code` columns keep it readable by `audit`
+pub const RESERVE_HEADER: &str = "coin,value,code,address,tx_id";
+
+/// Derives a pool of unfunded codes ahead of time, appending them to a reserve file, so they can later
+/// be funded instantly with `draw`.
+///
+/// Each code is written as soon as it's derived, with a value of 0 and no TX ID, so an interrupted
+/// run keeps every code it finished. The file holds codes, so it's only readable by its owner.
+///
+/// # Arguments
+///
+/// * `path` - The reserve file, created if missing (or added to, growing the pool).
+/// * `count` - How many codes to derive.
+/// * `threads` - How many workers to derive with.
+/// * `format` - The format of the promo codes.
+/// * `deriver` - The Deriver for the codes' scheme.
+/// * `coin_params` - A reference to the CoinParams for the selected coin.
+///
+pub fn generate_reserve(
+    path: &str,
+    count: u64,
+    threads: usize,
+    format: &CodeFormat,
+    deriver: &Deriver,
+    coin_params: &CoinParams,
+) -> io::Result<()> {
+    let _lock = ReserveLock::acquire(path)?;
+    let mut file = if Path::new(path).exists() {
+        OpenOptions::new().append(true).open(path)?
+    } else {
+        let mut file = File::create(path)?;
+        writeln!(file, "{}", RESERVE_HEADER)?;
+        file
+    };
+    restrict_permissions(Path::new(path), 0o600)?;
+
+    std::thread::scope(|scope| {
+        let mut derived = OrderedPromos::start(scope, count, threads, format, deriver, coin_params);
+        for i in 1..=count {
+            let promo = derived.next_promo();
+            writeln!(file, "{},0,{},{},", coin_params.ticker.to_lowercase(), promo.code, promo.public)?;
+            file.flush()?;
+            humanln!("Reserved code {} of {}: {}", i, count, style::code(&promo.code));
+        }
+        Ok(())
+    })
+}
@@ -0,0 +1,264 @@
+use std::{
+    fmt::Display,
+    time::Duration,
+};
+
+use base58::FromBase58;
+use pivx_rpc_rs::BitcoinRpcClient;
+use serde_json::{json, Value};
+
+use crate::{
+    coins::{get_supported_coins, CoinParams},
+    jsonrpc::{percent_encode, Endpoint},
+    style, RpcConfig,
+};
+
+/// The default number of retries made by the RPC client itself
+pub const DEFAULT_RPC_RETRIES: u32 = 4;
+
+/// The default delay between the RPC client's retries, in milliseconds (as `BitcoinRpcClient::new` takes it)
+pub const DEFAULT_RPC_RETRY_DELAY: u64 = 10;
+
+/// The default RPC request timeout, in milliseconds (as `BitcoinRpcClient::new` takes it)
+pub const DEFAULT_RPC_TIMEOUT: u64 = 1000;
+
+/// How many consecutive failed calls are tolerated before the client is rebuilt
+pub const RECONNECT_THRESHOLD: u32 = 3;
+
+/// A thin wrapper around `BitcoinRpcClient` which rebuilds the client after persistent failures.
+///
+/// If the daemon restarts mid-run, the original client may be left holding a dead connection, in
+/// which case every retry would fail forever. Instead, after `RECONNECT_THRESHOLD` consecutive
+/// failures, a fresh client is created from the stored `RpcConfig`.
+///
+pub struct ReconnectingRpc {
+    config: RpcConfig,
+    client: BitcoinRpcClient,
+    consecutive_failures: u32,
+}
+
+impl ReconnectingRpc {
+    /// Creates a new wrapper, connecting with the given config
+    pub fn new(config: RpcConfig) -> Self {
+        let client = connect(&config);
+        ReconnectingRpc {
+            config,
+            client,
+            consecutive_failures: 0,
+        }
+    }
+
+    /// Performs an RPC call, tracking failures and reconnecting once the threshold is hit.
+    ///
+    /// The result is returned as-is, so retry policies remain up to the caller.
+    ///
+    pub fn call<T, E: Display>(&mut self, f: impl FnOnce(&BitcoinRpcClient) -> Result<T, E>) -> Result<T, E> {
+        let result = f(&self.client);
+        match &result {
+            Ok(_) => self.consecutive_failures = 0,
+            Err(_) => {
+                self.consecutive_failures += 1;
+                if self.consecutive_failures >= RECONNECT_THRESHOLD {
+                    eprintln!(
+                        "{}",
+                        style::warning(format!(
+                            " - {} consecutive RPC failures, reconnecting to the daemon...",
+                            self.consecutive_failures
+                        ))
+                    );
+                    self.reconnect();
+                }
+            }
+        }
+        result
+    }
+
+    /// Calls an RPC method by name, for methods `BitcoinRpcClient` has no binding for (such as a
+    /// fork's own send method). Failures count towards reconnecting, like any other call.
+    ///
+    pub fn call_raw(&mut self, method: &str, params: Value) -> Result<Value, String> {
+        let config = self.config.clone();
+        self.call(|_| raw_call(&config, method, &params))
+    }
+
+    /// Drops the current client and builds a fresh one from the stored config
+    pub fn reconnect(&mut self) {
+        self.client = connect(&self.config);
+        self.consecutive_failures = 0;
+    }
+}
+
+/// The RPC methods every funding run relies on, besides the coin's send method: looking up sent TXs
+/// (to confirm fills, and find out whether a failed attempt was sent)
+const REQUIRED_RPC_METHODS: [&str; 2] = ["gettransaction", "listtransactions"];
+
+/// Checks that the daemon is reachable, recent enough for the selected coin, and has every RPC method
+/// funding needs, before any funding.
+///
+/// Problems are reported as warnings rather than errors, and left to the caller to decide on, since
+/// version ranges are only known for some coins, and forks may report versions differently.
+///
+/// # Returns
+///
+/// `false` if the daemon couldn't be queried, is older than the coin's `min_daemon_version`, or lacks
+/// a required method, otherwise `true`.
+///
+pub fn check_daemon_compat(rpc: &mut ReconnectingRpc, coin_params: &CoinParams) -> bool {
+    let info = match rpc.call(|c| c.getnetworkinfo()) {
+        Ok(info) => info,
+        Err(e) => {
+            eprintln!(
+                "{}",
+                style::warning(format!(
+                    "Warning: couldn't query the {} daemon with `getnetworkinfo`, is it running and up-to-date? - error: \"{}\"",
+                    coin_params.name, e
+                ))
+            );
+            return false;
+        }
+    };
+    humanln!("Connected to {} daemon: {} (version {})", coin_params.name, info.subversion, info.version);
+
+    if let Some(min_version) = coin_params.min_daemon_version {
+        if info.version < min_version {
+            eprintln!(
+                "{}",
+                style::warning(format!(
+                    "Warning: daemon version {} is older than {}, the oldest known to support Batcher's `sendtoaddress` usage!",
+                    info.version, min_version
+                ))
+            );
+            return false;
+        }
+    }
+
+    // `help <method>` answers for any method the daemon has, and names the ones it doesn't
+    let send_method = coin_params.send_rpc.as_ref().map_or("sendtoaddress", |send| send.method.as_str());
+    let mut compatible = true;
+    for method in std::iter::once(send_method).chain(REQUIRED_RPC_METHODS) {
+        match rpc.call_raw("help", json!([method])) {
+            Ok(Value::String(help)) if !help.contains("unknown command") => {}
+            Ok(_) => {
+                eprintln!(
+                    "{}",
+                    style::warning(format!("Warning: the daemon has no `{}` RPC, which funding needs!", method))
+                );
+                compatible = false;
+            }
+            Err(e) => {
+                eprintln!("{}", style::warning(format!("Warning: couldn't check for the `{}` RPC - error: \"{}\"", method, e)));
+                compatible = false;
+            }
+        }
+    }
+    compatible
+}
+
+/// Returns which chain the daemon is running on (such as "main", "test" or "regtest").
+///
+/// # Returns
+///
+/// The chain's name, or `None` if the daemon couldn't be queried.
+///
+pub fn daemon_chain(rpc: &mut ReconnectingRpc) -> Option<String> {
+    rpc.call(|c| c.getblockchaininfo()).ok().map(|info| info.chain.to_string())
+}
+
+/// Cross-checks the selected coin's address version byte against the daemon's own wallet addresses.
+///
+/// Daemons don't report their version bytes directly, so one of the wallet's legacy (Base58)
+/// addresses is decoded instead. The WIF byte can't be checked this way without dumping a private
+/// key, so it isn't.
+///
+/// # Returns
+///
+/// `false` if the daemon's addresses use a different version byte, otherwise `true` (including
+/// when there was nothing to compare against).
+///
+pub fn check_coin_params(rpc: &mut ReconnectingRpc, coin_params: &CoinParams) -> bool {
+    let addresses = match rpc.call(|c| c.listreceivedbyaddress(Some(0), Some(true))) {
+        Ok(addresses) => addresses,
+        Err(e) => {
+            eprintln!(
+                "{}",
+                style::warning(format!("Warning: couldn't list the wallet's addresses to check the coin params: \"{}\"", e))
+            );
+            return true;
+        }
+    };
+
+    // Only legacy addresses (a version byte, a 20-byte hash and a 4-byte checksum) tell us anything
+    let detected = addresses.iter().find_map(|entry| match entry.address.to_string().from_base58() {
+        Ok(bytes) if bytes.len() == 25 => Some(bytes[0]),
+        _ => None,
+    });
+    let Some(detected) = detected else {
+        humanln!("The wallet has no legacy addresses to check {}'s version byte against, skipping", coin_params.name);
+        return true;
+    };
+
+    if detected == coin_params.pub_key_byte {
+        humanln!("Daemon addresses match {}'s version byte ({})", coin_params.name, detected);
+        return true;
+    }
+    eprintln!(
+        "{}",
+        style::error(format!(
+            "The daemon's addresses use version byte {}, but {} is configured with {}: the coin params (or the daemon) are wrong!",
+            detected, coin_params.name, coin_params.pub_key_byte
+        ))
+    );
+    let matches: Vec<String> = get_supported_coins()
+        .into_iter()
+        .filter(|coin| coin.pub_key_byte == detected)
+        .map(|coin| format!("{} ({})", coin.name, coin.ticker))
+        .collect();
+    if !matches.is_empty() {
+        eprintln!("{}", style::error(format!("The daemon's version byte matches: {}", matches.join(", "))));
+    }
+    false
+}
+
+/// Returns the RPC URL for a local daemon, including the `/wallet/<name>` endpoint if a wallet is set
+pub fn rpc_url(config: &RpcConfig) -> String {
+    let mut url = format!("http://{}:{}", config.rpc_host, config.rpc_port);
+    if let Some(wallet) = &config.wallet {
+        url.push_str("/wallet/");
+        url.push_str(&percent_encode(wallet));
+    }
+    url
+}
+
+/// Makes a single JSON-RPC call over plain HTTP, returning its result or the daemon's error message.
+///
+/// It's bounded by the same request timeout as the typed client (`--rpc-timeout`).
+///
+fn raw_call(config: &RpcConfig, method: &str, params: &Value) -> Result<Value, String> {
+    let endpoint = Endpoint {
+        host: config.rpc_host.clone(),
+        port: config.rpc_port,
+        user: config.rpc_user.clone(),
+        pass: config.rpc_pass.clone(),
+        wallet: config.wallet.clone(),
+        timeout: Duration::from_millis(config.timeout),
+    };
+    endpoint.call(method, params)
+}
+
+/// Builds a `BitcoinRpcClient` for a local daemon from the given config.
+///
+/// The client holds no connection pool: each call is its own HTTP request, and every call is made from
+/// the main thread, one at a time. Its numeric arguments are the retry count (`--rpc-retries`), the
+/// delay between retries (`--rpc-retry-delay`) and the request timeout (`--rpc-timeout`), both in
+/// milliseconds.
+///
+pub fn connect(config: &RpcConfig) -> BitcoinRpcClient {
+    BitcoinRpcClient::new(
+        rpc_url(config),
+        Some(config.rpc_user.to_owned()),
+        Some(config.rpc_pass.to_owned()),
+        config.retries,
+        config.retry_delay,
+        config.timeout,
+    )
+}
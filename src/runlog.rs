@@ -0,0 +1,71 @@
+use std::{
+    fmt::Display,
+    fs::OpenOptions,
+    io::{self, Write},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde_json::json;
+
+use crate::style;
+
+/// An append-only audit trail of every funding RPC call made during a run.
+///
+/// Each attempt is written as a JSON object on its own line, with its timestamp, method, outputs
+/// and the full response (or error), so a run can be reconciled against the chain after the fact.
+/// This is separate from the console output, which is meant for the operator.
+///
+pub struct RunLog {
+    /// The file to append entries to
+    path: String,
+}
+
+impl RunLog {
+    /// Opens (or creates) a run log, exiting with a clear message if it can't be written to
+    pub fn open(path: &str) -> RunLog {
+        if let Err(e) = OpenOptions::new().create(true).append(true).open(path) {
+            eprintln!("{}", style::error(format!("Can't open the log file \"{}\": {}", path, e)));
+            std::process::exit(1);
+        }
+        RunLog { path: path.to_owned() }
+    }
+
+    /// Records a single RPC attempt and its response.
+    ///
+    /// A failure to log is only warned about: it must never interrupt a funding run part-way.
+    ///
+    /// # Arguments
+    ///
+    /// * `method` - The RPC method called.
+    /// * `outputs` - The address and amount of every output the call sends to.
+    /// * `result` - The TX ID on success, or the error.
+    ///
+    pub fn record<T: Display, E: Display>(&self, method: &str, outputs: &[(&str, f64)], result: &Result<T, E>) {
+        let (ok, response) = match result {
+            Ok(tx_id) => (true, tx_id.to_string()),
+            Err(e) => (false, e.to_string()),
+        };
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        let entry = json!({
+            "timestamp": timestamp,
+            "method": method,
+            "outputs": outputs
+                .iter()
+                .map(|(address, amount)| json!({ "address": address, "amount": amount }))
+                .collect::<Vec<_>>(),
+            "ok": ok,
+            "response": response,
+        });
+        if let Err(e) = self.append(&entry.to_string()) {
+            eprintln!(
+                "{}",
+                style::warning(format!(" - Couldn't write to the log file \"{}\": {}", self.path, e))
+            );
+        }
+    }
+
+    fn append(&self, line: &str) -> io::Result<()> {
+        let mut file = OpenOptions::new().append(true).open(&self.path)?;
+        writeln!(file, "{}", line)
+    }
+}
@@ -0,0 +1,249 @@
+use std::{fs, io, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cli::CliArgs,
+    fiat::FiatRate,
+    plan::{BatchPlan, PromoBatch},
+};
+
+/// The file an in-progress run's state is kept in, within the working directory
+pub const STATE_FILE: &str = "batcher-state.json";
+
+/// An in-progress run, saved after every finished code so that `--continue` can pick it up from a
+/// completely new process (after a reboot, for example).
+///
+/// Everything that decides what the remaining codes look like is kept, so the resumed codes are
+/// indistinguishable from the rest. The file is removed once the run completes.
+///
+#[derive(Serialize, Deserialize)]
+pub struct RunState {
+    /// The ticker of the run's coin
+    pub coin: String,
+    /// The promo code prefix
+    pub prefix: String,
+    /// The number of random characters in each code
+    pub code_length: usize,
+    /// Whether codes end in a check character
+    pub check_char: bool,
+    /// Whether codes are numeric-only
+    #[serde(default)]
+    pub numeric: bool,
+    /// The version of the derivation scheme
+    pub derivation: u8,
+    /// The derivation namespace, if any
+    pub namespace: Option<String>,
+    /// The file the batch is being saved to, if saving
+    pub save_file: Option<String>,
+    /// The format of the save file, as its extension
+    #[serde(default = "default_output_format")]
+    pub output_format: String,
+    /// Whether codes are signed (adding a signature column)
+    #[serde(default)]
+    pub sign_codes: bool,
+    /// Whether CSVs have a column of each WIF's SHA256
+    #[serde(default)]
+    pub wif_hash: bool,
+    /// Whether codes are timestamped (adding a timestamp column)
+    #[serde(default)]
+    pub timestamps: bool,
+    /// Whether each code's iteration target is recorded (adding a target column)
+    #[serde(default)]
+    pub record_target: bool,
+    /// Whether the CSV header ends with a trailing comma
+    #[serde(default)]
+    pub csv_trailing_comma: bool,
+    /// Whether codes are generated without funding (adding TX ID and funded columns)
+    #[serde(default)]
+    pub no_fill: bool,
+    /// The fiat rate the run reports in, with its source and when it was given, if one was given
+    #[serde(default)]
+    pub fiat_rate: Option<FiatRate>,
+    /// The full plan, as originally entered
+    pub batches: Vec<PromoBatch>,
+    /// How many codes of each batch are finished (saved, and funded if needed)
+    pub progress: Vec<u64>,
+}
+
+/// The save format of states from before it was recorded, which could only continue CSV saves safely
+fn default_output_format() -> String {
+    String::from("csv")
+}
+
+impl RunState {
+    /// Loads the state of an interrupted run
+    pub fn load() -> Result<RunState, String> {
+        let contents = fs::read_to_string(STATE_FILE).map_err(|e| format!("can't read \"{}\": {}", STATE_FILE, e))?;
+        let state: RunState =
+            serde_json::from_str(&contents).map_err(|e| format!("can't parse \"{}\": {}", STATE_FILE, e))?;
+        if state.progress.len() != state.batches.len() {
+            return Err(format!("\"{}\" has progress for a different number of batches", STATE_FILE));
+        }
+        Ok(state)
+    }
+
+    /// Checks that no flag contradicts the interrupted run, since its remaining codes (and the rows they're
+    /// saved as) must match the rest
+    pub fn check_flags(&self, cli: &CliArgs) -> Result<(), String> {
+        let conflict = if cli.coin.as_ref().is_some_and(|coin| !coin.eq_ignore_ascii_case(&self.coin)) {
+            "--coin"
+        } else if cli.prefix.as_ref().is_some_and(|prefix| *prefix != self.prefix) {
+            "--prefix"
+        } else if cli.code_length.is_some_and(|length| length != self.code_length) {
+            "--code-length"
+        } else if cli.check_char != self.check_char {
+            "--check-char"
+        } else if cli.numeric != self.numeric {
+            "--numeric"
+        } else if cli.derivation.version() != self.derivation {
+            "--derivation"
+        } else if cli.namespace != self.namespace {
+            "--namespace"
+        } else if cli.output_format.extension() != self.output_format {
+            "--output-format"
+        } else if cli.sign_codes != self.sign_codes {
+            "--sign"
+        } else if cli.wif_hash != self.wif_hash {
+            "--wif-hash"
+        } else if cli.timestamps != self.timestamps {
+            "--timestamps"
+        } else if cli.record_target != self.record_target {
+            "--record-target"
+        } else if cli.csv_trailing_comma != self.csv_trailing_comma {
+            "--csv-trailing-comma"
+        } else if cli.no_fill != self.no_fill {
+            "--no-fill"
+        } else {
+            return Ok(());
+        };
+        Err(format!("\"{}\" doesn't match the interrupted run, which must be continued with the same options", conflict))
+    }
+
+    /// Whether an interrupted run's state exists
+    pub fn exists() -> bool {
+        Path::new(STATE_FILE).exists()
+    }
+
+    /// Saves the state, via a temporary file so a crash mid-write never corrupts it
+    pub fn save(&self) -> io::Result<()> {
+        let tmp_file = format!("{}.tmp", STATE_FILE);
+        fs::write(&tmp_file, serde_json::to_string_pretty(self)?)?;
+        fs::rename(&tmp_file, STATE_FILE)
+    }
+
+    /// Removes the state of a completed run
+    pub fn remove() -> io::Result<()> {
+        match fs::remove_file(STATE_FILE) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    /// The codes still to be generated, as a plan (finished batches are kept with no codes left, so
+    /// batch numbers stay the same)
+    pub fn remaining(&self) -> BatchPlan {
+        BatchPlan {
+            batches: self
+                .batches
+                .iter()
+                .zip(&self.progress)
+                .map(|(batch, done)| PromoBatch {
+                    qty: batch.qty.saturating_sub(*done),
+                    ..batch.clone()
+                })
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{derive::DerivationScheme, output::OutputFormat};
+
+    fn batch(value: f64, qty: u64) -> PromoBatch {
+        PromoBatch {
+            value,
+            qty,
+            note: None,
+            max_value: None,
+            step: None,
+        }
+    }
+
+    /// An interrupted run matching the default flags
+    fn state() -> RunState {
+        RunState {
+            coin: String::from("PIV"),
+            prefix: String::from("promo"),
+            code_length: 10,
+            check_char: false,
+            numeric: false,
+            derivation: 1,
+            namespace: None,
+            save_file: Some(String::from("promos.csv")),
+            output_format: default_output_format(),
+            sign_codes: false,
+            wif_hash: false,
+            timestamps: false,
+            record_target: false,
+            csv_trailing_comma: false,
+            no_fill: false,
+            fiat_rate: None,
+            batches: vec![batch(1.0, 5), batch(2.5, 3), batch(10.0, 2)],
+            progress: vec![5, 1, 0],
+        }
+    }
+
+    #[test]
+    fn remaining_keeps_finished_batches_with_no_codes_left() {
+        let remaining = state().remaining();
+        let qtys: Vec<u64> = remaining.batches.iter().map(|batch| batch.qty).collect();
+        let values: Vec<f64> = remaining.batches.iter().map(|batch| batch.value).collect();
+        assert_eq!(qtys, [0, 2, 2]);
+        assert_eq!(values, [1.0, 2.5, 10.0]);
+    }
+
+    #[test]
+    fn remaining_never_underflows() {
+        let mut overdone = state();
+        overdone.progress = vec![9, 3, 2];
+        assert!(overdone.remaining().batches.iter().all(|batch| batch.qty == 0));
+    }
+
+    #[test]
+    fn check_flags_accepts_flags_left_unset() {
+        assert_eq!(state().check_flags(&CliArgs::default()), Ok(()));
+    }
+
+    #[test]
+    fn check_flags_accepts_repeated_flags() {
+        let cli = CliArgs {
+            coin: Some(String::from("piv")),
+            prefix: Some(String::from("promo")),
+            code_length: Some(10),
+            ..CliArgs::default()
+        };
+        assert_eq!(state().check_flags(&cli), Ok(()));
+    }
+
+    #[test]
+    fn check_flags_names_the_conflicting_flag() {
+        let conflicts = [
+            ("--coin", CliArgs { coin: Some(String::from("DOGE")), ..CliArgs::default() }),
+            ("--prefix", CliArgs { prefix: Some(String::from("other")), ..CliArgs::default() }),
+            ("--code-length", CliArgs { code_length: Some(12), ..CliArgs::default() }),
+            ("--check-char", CliArgs { check_char: true, ..CliArgs::default() }),
+            ("--derivation", CliArgs { derivation: DerivationScheme::V2, ..CliArgs::default() }),
+            ("--namespace", CliArgs { namespace: Some(String::from("ns")), ..CliArgs::default() }),
+            ("--output-format", CliArgs { output_format: OutputFormat::Json, ..CliArgs::default() }),
+            ("--record-target", CliArgs { record_target: true, ..CliArgs::default() }),
+            ("--no-fill", CliArgs { no_fill: true, ..CliArgs::default() }),
+        ];
+        for (flag, cli) in conflicts {
+            let error = state().check_flags(&cli).unwrap_err();
+            assert!(error.starts_with(&format!("\"{}\"", flag)), "{}", error);
+        }
+    }
+}
@@ -0,0 +1,145 @@
+use std::{
+    fmt::{Arguments, Display},
+    io::{self, IsTerminal, Write},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use owo_colors::OwoColorize;
+
+/// Whether terminal output should be colored, decided once at startup
+static COLORS_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Whether errors and warnings are emitted as JSON, decided once at startup
+static JSON_ERRORS: AtomicBool = AtomicBool::new(false);
+
+/// Whether codes and WIFs are masked in console output, decided once at startup
+static REDACT: AtomicBool = AtomicBool::new(false);
+
+/// Whether human-readable output goes to stderr, because a machine-readable stream has stdout
+static HUMAN_TO_STDERR: AtomicBool = AtomicBool::new(false);
+
+/// The number of trailing characters of a code left visible when redacting, enough to find its row
+const REDACTED_CODE_TAIL: usize = 3;
+
+/// Enables colors, unless disabled by flag, by the `NO_COLOR` convention, or because the output isn't a terminal
+pub fn init_colors(no_color: bool) {
+    let enabled = !no_color
+        && std::env::var_os("NO_COLOR").is_none()
+        && human_is_terminal()
+        && io::stderr().is_terminal();
+    COLORS_ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+fn colors_enabled() -> bool {
+    COLORS_ENABLED.load(Ordering::Relaxed)
+}
+
+/// Emits errors and warnings as JSON objects (`{"type": ..., "message": ...}`), for orchestrating programs
+pub fn init_json_errors(enabled: bool) {
+    JSON_ERRORS.store(enabled, Ordering::Relaxed);
+}
+
+/// Whether errors (and the final summary) are emitted as JSON
+pub fn json_errors() -> bool {
+    JSON_ERRORS.load(Ordering::Relaxed)
+}
+
+/// Sends human-readable output to stderr, leaving stdout to a machine-readable stream (`--progress-json -`).
+/// Call before `init_colors`.
+pub fn init_human_stderr(enabled: bool) {
+    HUMAN_TO_STDERR.store(enabled, Ordering::Relaxed);
+}
+
+/// Whether the stream human-readable output goes to is a terminal
+pub fn human_is_terminal() -> bool {
+    match HUMAN_TO_STDERR.load(Ordering::Relaxed) {
+        true => io::stderr().is_terminal(),
+        false => io::stdout().is_terminal(),
+    }
+}
+
+/// Writes human-readable output (the crate's `human!` and `humanln!`) to stdout, or to stderr once
+/// `init_human_stderr` has moved it there. A closed stream is ignored, like a closed terminal.
+pub fn print_human(args: Arguments) {
+    match HUMAN_TO_STDERR.load(Ordering::Relaxed) {
+        true => io::stderr().write_fmt(args).ok(),
+        false => io::stdout().write_fmt(args).ok(),
+    };
+}
+
+/// Flushes the stream human-readable output goes to, e.g. after a prompt
+pub fn flush_human() {
+    match HUMAN_TO_STDERR.load(Ordering::Relaxed) {
+        true => io::stderr().flush().ok(),
+        false => io::stdout().flush().ok(),
+    };
+}
+
+/// Masks codes and WIFs in console output, so runs can be watched (or logged) without leaking secrets
+pub fn init_redact(enabled: bool) {
+    REDACT.store(enabled, Ordering::Relaxed);
+}
+
+/// Formats a promo code for the console: in full, or (when redacting) masked down to its last few
+/// characters, which is enough to find its row in the saved file.
+pub fn code(code: &str) -> String {
+    if !REDACT.load(Ordering::Relaxed) {
+        return code.to_string();
+    }
+    let tail: String = code.chars().rev().take(REDACTED_CODE_TAIL).collect::<Vec<_>>().into_iter().rev().collect();
+    format!("****{}", tail)
+}
+
+/// Formats a WIF for the console: in full, or (when redacting) not at all
+pub fn wif(wif: &str) -> String {
+    if REDACT.load(Ordering::Relaxed) {
+        String::from("[redacted]")
+    } else {
+        wif.to_string()
+    }
+}
+
+/// Formats a message as a single-line JSON object of its type
+fn to_json(kind: &str, text: impl Display) -> String {
+    serde_json::json!({ "type": kind, "message": text.to_string().trim() }).to_string()
+}
+
+/// Styles a message as a success (green)
+pub fn success(text: impl Display) -> String {
+    if colors_enabled() {
+        text.green().to_string()
+    } else {
+        text.to_string()
+    }
+}
+
+/// Styles a message as a warning or retry (yellow)
+pub fn warning(text: impl Display) -> String {
+    if json_errors() {
+        to_json("warning", text)
+    } else if colors_enabled() {
+        text.yellow().to_string()
+    } else {
+        text.to_string()
+    }
+}
+
+/// Styles a message as an error (red)
+pub fn error(text: impl Display) -> String {
+    if json_errors() {
+        to_json("error", text)
+    } else if colors_enabled() {
+        text.red().to_string()
+    } else {
+        text.to_string()
+    }
+}
+
+/// Styles a message as an error in how Batcher was invoked (red, like any other error)
+pub fn usage_error(text: impl Display) -> String {
+    if json_errors() {
+        to_json("usage", text)
+    } else {
+        error(text)
+    }
+}
@@ -0,0 +1,80 @@
+use std::{fs, io, path::PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::config_dir,
+    plan::{BatchPlan, PromoBatch},
+};
+
+/// The directory (within Batcher's config directory) templates are stored in
+pub const TEMPLATES_DIR: &str = "templates";
+
+/// A saved batch plan, for recurring campaigns.
+///
+/// Stored as `<config dir>/templates/<name>.toml`, for example:
+///
+/// ```toml
+/// [[batches]]
+/// qty = 100
+/// value = 5.0
+///
+/// [[batches]]
+/// qty = 50
+/// value = 10.0
+/// ```
+///
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Template {
+    batches: Vec<PromoBatch>,
+}
+
+/// Returns the path of a named template, if the name is safe to use as a file name
+fn template_path(name: &str) -> Result<PathBuf, String> {
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return Err(format!(
+            "\"{}\" is not a valid template name (use letters, digits, '-' and '_')",
+            name
+        ));
+    }
+    Ok(config_dir().join(TEMPLATES_DIR).join(format!("{}.toml", name)))
+}
+
+/// Loads a named template as a batch plan, validating every batch
+pub fn load_template(name: &str) -> Result<BatchPlan, String> {
+    let path = template_path(name)?;
+    let contents = fs::read_to_string(&path).map_err(|e| format!("can't read \"{}\": {}", path.display(), e))?;
+    let template: Template = toml::from_str(&contents).map_err(|e| format!("can't parse \"{}\": {}", path.display(), e))?;
+
+    let mut plan = BatchPlan::default();
+    for batch in template.batches {
+        plan.push(batch)?;
+    }
+    plan.validate()?;
+    Ok(plan)
+}
+
+/// Saves a batch plan as a named template, overwriting any template of the same name
+pub fn save_template(name: &str, plan: &BatchPlan) -> io::Result<PathBuf> {
+    let path = template_path(name).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let template = Template {
+        batches: plan
+            .batches
+            .iter()
+            .map(|batch| PromoBatch {
+                value: batch.value,
+                qty: batch.qty,
+                note: batch.note.clone(),
+                max_value: batch.max_value,
+                step: batch.step,
+            })
+            .collect(),
+    };
+    let contents = toml::to_string(&template).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    fs::write(&path, contents)?;
+    Ok(path)
+}
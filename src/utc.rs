@@ -0,0 +1,36 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The current UNIX time, in seconds
+pub fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+/// Splits a UNIX time into its UTC date and time, as `(year, month, day, hour, minute, second)`
+fn civil(secs: u64) -> (u64, u64, u64, u64, u64, u64) {
+    let (days, time) = (secs / 86_400, secs % 86_400);
+
+    // Convert days since the epoch into a civil date (Howard Hinnant's `civil_from_days`)
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let day_of_era = z % 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+
+    (year, month, day, time / 3_600, time % 3_600 / 60, time % 60)
+}
+
+/// Formats a UNIX time as a UTC date and time, e.g. "2026-10-16 12:00:00 UTC"
+pub fn format_utc(secs: u64) -> String {
+    let (year, month, day, hour, minute, second) = civil(secs);
+    format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC", year, month, day, hour, minute, second)
+}
+
+/// Formats a UNIX time as an ISO 8601 UTC timestamp, e.g. "2026-10-16T12:00:00Z"
+pub fn format_iso8601(secs: u64) -> String {
+    let (year, month, day, hour, minute, second) = civil(secs);
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, hour, minute, second)
+}
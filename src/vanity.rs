@@ -0,0 +1,151 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc,
+    },
+    thread,
+};
+
+use crate::{
+    code::CodeFormat, coins::CoinParams, create_promo_key, derive::Deriver, double_check_promo, style,
+    OptimisedPromoKeypair,
+};
+
+/// The Base58 alphabet that addresses are encoded with
+const BASE58_ALPHABET: &str = "123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+/// Checks that a vanity prefix could ever appear at the start of one of the coin's addresses.
+///
+/// Besides being Base58, it has to agree with the coin's expected address prefix (if one is declared),
+/// which the version byte fixes: otherwise the search would never end.
+///
+pub fn validate_vanity(vanity: &str, coin_params: &CoinParams) -> Result<(), String> {
+    if let Some(c) = vanity.chars().find(|c| !BASE58_ALPHABET.contains(*c)) {
+        return Err(format!("'{}' can never appear in an address (not Base58)", c));
+    }
+    if vanity.is_empty() {
+        return Err(String::from("the vanity prefix can't be empty"));
+    }
+    match coin_params.expected_address_prefix {
+        Some(ref prefix) if vanity.chars().zip(prefix.chars()).any(|(a, b)| a != b) => Err(format!(
+            "{} addresses always start with \"{}\", so they can never start with \"{}\"",
+            coin_params.ticker, prefix, vanity
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Roughly how many codes must be derived, on average, to find an address starting with `vanity`.
+///
+/// The first character of an address is mostly fixed by the coin's version byte, so it's not
+/// counted, and every following character is treated as a 1-in-58 chance.
+///
+pub fn expected_attempts(vanity: &str) -> f64 {
+    58f64.powi(vanity.chars().count().saturating_sub(1) as i32)
+}
+
+/// Prints a loud warning about the cost of a vanity search
+pub fn warn_vanity_cost(vanity: &str, threads: usize) {
+    humanln!(
+        "{}",
+        style::warning(format!(
+            "Warning: vanity mode! Every attempt costs a FULL key derivation, and \"{}\" needs ~{:.0} attempts per code (across {} threads).",
+            vanity,
+            expected_attempts(vanity),
+            threads
+        ))
+    );
+    humanln!(
+        "{}",
+        style::warning("Only very short vanity prefixes are feasible. Note the first character is fixed by the coin.")
+    );
+}
+
+/// Generates promos in parallel until one's address starts with `vanity`.
+///
+/// Each thread derives codes independently; the first match wins, and the rest stop after their
+/// current attempt.
+///
+/// # Arguments
+///
+/// * `format` - A reference to the CodeFormat describing how to assemble the promotional code.
+/// * `deriver` - A reference to the Deriver for the selected derivation scheme.
+/// * `coin_params` - A reference to the CoinParams for the selected coin.
+/// * `vanity` - The prefix the address must start with.
+/// * `threads` - How many threads to search with.
+///
+/// # Returns
+///
+/// The matching promo.
+///
+pub fn find_vanity_promo(
+    format: &CodeFormat,
+    deriver: &Deriver,
+    coin_params: &CoinParams,
+    vanity: &str,
+    threads: usize,
+) -> OptimisedPromoKeypair {
+    let found = AtomicBool::new(false);
+    let attempts = AtomicU64::new(0);
+    let (sender, receiver) = mpsc::channel();
+
+    thread::scope(|scope| {
+        for _ in 0..threads.max(1) {
+            let sender = sender.clone();
+            let (found, attempts) = (&found, &attempts);
+            scope.spawn(move || {
+                while !found.load(Ordering::Relaxed) {
+                    let promo = create_promo_key(format, deriver, coin_params);
+                    let attempt = attempts.fetch_add(1, Ordering::Relaxed) + 1;
+                    if promo.public.starts_with(vanity) {
+                        // Only the match is ever funded, so only it needs double-checking
+                        double_check_promo(&promo, deriver, coin_params);
+                        found.store(true, Ordering::Relaxed);
+                        sender.send(promo).unwrap_or_default();
+                        return;
+                    }
+                    if attempt % 10 == 0 {
+                        humanln!(" - Vanity search: {} attempts so far...", attempt);
+                    }
+                }
+            });
+        }
+    });
+
+    // At least one thread only stops after sending a match, so there's always one here
+    receiver.recv().unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coins::fixtures::piv;
+
+    #[test]
+    fn validate_vanity_accepts_the_coins_prefix() {
+        assert!(validate_vanity("D", &piv()).is_ok());
+        assert!(validate_vanity("Dab", &piv()).is_ok());
+    }
+
+    #[test]
+    fn validate_vanity_rejects_a_first_character_the_coin_never_produces() {
+        assert_eq!(
+            validate_vanity("Z", &piv()),
+            Err(String::from("PIV addresses always start with \"D\", so they can never start with \"Z\""))
+        );
+        assert!(validate_vanity("ZD", &piv()).is_err());
+    }
+
+    #[test]
+    fn validate_vanity_rejects_non_base58_and_empty_prefixes() {
+        assert!(validate_vanity("D0", &piv()).is_err());
+        assert!(validate_vanity("Dl", &piv()).is_err());
+        assert!(validate_vanity("", &piv()).is_err());
+    }
+
+    #[test]
+    fn validate_vanity_only_checks_base58_without_an_expected_prefix() {
+        let coin = CoinParams { expected_address_prefix: None, ..piv() };
+        assert!(validate_vanity("Z", &coin).is_ok());
+    }
+}
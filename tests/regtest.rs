@@ -0,0 +1,232 @@
+//! End-to-end tests against a live regtest daemon, which fund real (regtest) coins through Batcher's
+//! own fill logic and check where they landed.
+//!
+//! Only built with the `regtest-tests` feature, and configured through environment variables:
+//!
+//! * `BATCHER_REGTEST_RPC_PORT`, `BATCHER_REGTEST_RPC_USER`, `BATCHER_REGTEST_RPC_PASS` - The daemon's RPC
+//!   port and credentials (required).
+//! * `BATCHER_REGTEST_DAEMON` - A daemon binary (e.g. `pivxd`) to spin up for the test, in a throwaway
+//!   data directory. Without it, an already-running regtest daemon is connected to.
+//! * `BATCHER_REGTEST_COIN` - The coin to run as (default: "PIV").
+//! * `BATCHER_REGTEST_VERSION_BYTES` - The regtest "<public>,<private>" version bytes (default: PIVX's "139,239").
+//!
+#![cfg(feature = "regtest-tests")]
+
+use std::{
+    collections::HashMap,
+    env, fs,
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    thread,
+    time::Duration,
+};
+
+use serde_json::{json, Value};
+
+// Batcher's own JSON-RPC client, so the tests talk to the daemon exactly as Batcher does
+#[path = "../src/jsonrpc.rs"]
+mod jsonrpc;
+
+/// How long a test's RPC call may take before the daemon is considered unresponsive
+const RPC_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The batches the test run is planned with, as a template
+const TEMPLATE: &str = "[[batches]]\nqty = 3\nvalue = 1.5\n\n[[batches]]\nqty = 2\nvalue = 0.25\n";
+
+/// The number of codes in `TEMPLATE`
+const TEMPLATE_CODES: usize = 5;
+
+/// How a regtest daemon is reached
+struct Regtest {
+    port: u16,
+    user: String,
+    pass: String,
+    coin: String,
+    version_bytes: String,
+    /// The daemon spun up for the test, if one was, killed once the test ends
+    daemon: Option<Child>,
+}
+
+impl Drop for Regtest {
+    fn drop(&mut self) {
+        if let Some(ref mut daemon) = self.daemon {
+            let _ = daemon.kill();
+            let _ = daemon.wait();
+        }
+    }
+}
+
+/// Reads a required environment variable, failing the test with a hint if it's missing
+fn required_env(name: &str) -> String {
+    env::var(name).unwrap_or_else(|_| panic!("{} must be set to run the regtest tests", name))
+}
+
+impl Regtest {
+    /// Connects to the configured regtest daemon (spinning one up first, if asked to)
+    fn from_env(work_dir: &Path) -> Self {
+        let mut regtest = Regtest {
+            port: required_env("BATCHER_REGTEST_RPC_PORT").parse().expect("BATCHER_REGTEST_RPC_PORT must be a port"),
+            user: required_env("BATCHER_REGTEST_RPC_USER"),
+            pass: required_env("BATCHER_REGTEST_RPC_PASS"),
+            coin: env::var("BATCHER_REGTEST_COIN").unwrap_or_else(|_| String::from("PIV")),
+            version_bytes: env::var("BATCHER_REGTEST_VERSION_BYTES").unwrap_or_else(|_| String::from("139,239")),
+            daemon: None,
+        };
+
+        if let Ok(daemon) = env::var("BATCHER_REGTEST_DAEMON") {
+            let data_dir = work_dir.join("daemon");
+            fs::create_dir_all(&data_dir).unwrap();
+            let child = Command::new(daemon)
+                .arg("-regtest")
+                .arg(format!("-datadir={}", data_dir.display()))
+                .arg(format!("-rpcport={}", regtest.port))
+                .arg(format!("-rpcuser={}", regtest.user))
+                .arg(format!("-rpcpassword={}", regtest.pass))
+                .arg("-listen=0")
+                .stdout(Stdio::null())
+                .spawn()
+                .expect("failed to start the regtest daemon");
+            regtest.daemon = Some(child);
+        }
+
+        // Wait for the daemon to answer (it may still be starting up)
+        for _ in 0..60 {
+            if regtest.rpc("getblockcount", json!([])).is_ok() {
+                return regtest;
+            }
+            thread::sleep(Duration::from_secs(1));
+        }
+        panic!("the regtest daemon never answered on port {}", regtest.port);
+    }
+
+    /// Makes a JSON-RPC call, returning its result or the daemon's error
+    fn rpc(&self, method: &str, params: Value) -> Result<Value, String> {
+        let endpoint = jsonrpc::Endpoint {
+            host: String::from("127.0.0.1"),
+            port: self.port,
+            user: self.user.clone(),
+            pass: self.pass.clone(),
+            wallet: None,
+            timeout: RPC_TIMEOUT,
+        };
+        endpoint.call(method, &params)
+    }
+
+    /// Mines blocks to the daemon's own wallet
+    fn generate(&self, blocks: u64) {
+        if self.rpc("generate", json!([blocks])).is_ok() {
+            return;
+        }
+        // Newer daemons only mine to a given address
+        let address = self.rpc("getnewaddress", json!([])).expect("failed to get a mining address");
+        self.rpc("generatetoaddress", json!([blocks, address])).expect("failed to mine blocks");
+    }
+
+    /// Writes the daemon's RPC details where Batcher looks for the coin's config, under `home`
+    fn write_coin_conf(&self, home: &Path) {
+        let conf_dir = home.join(format!(".{}", conf_dir_name(&self.coin)));
+        fs::create_dir_all(&conf_dir).unwrap();
+        fs::write(
+            conf_dir.join(conf_file_name(&self.coin)),
+            format!("rpcuser={}\nrpcpassword={}\nrpcport={}\n", self.user, self.pass, self.port),
+        )
+        .unwrap();
+    }
+}
+
+/// The (lowercase, Linux) config directory name of a supported coin
+fn conf_dir_name(ticker: &str) -> &'static str {
+    match ticker.to_uppercase().as_str() {
+        "PIV" => "pivx",
+        "DOGE" => "dogecoin",
+        "MRX" => "metrixcoin",
+        "PEP" => "pepecoin",
+        "SCC" => "stakecubecoin",
+        "NMNSC" => "nmnsc",
+        other => panic!("no regtest config known for {}", other),
+    }
+}
+
+/// The config file name of a supported coin
+fn conf_file_name(ticker: &str) -> String {
+    format!("{}.conf", conf_dir_name(ticker))
+}
+
+/// A fresh, empty directory for one test's files
+fn work_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("batcher-regtest-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Reads the `code` and `value` columns of a saved batch, by code
+fn read_values(csv: &Path) -> HashMap<String, f64> {
+    let contents = fs::read_to_string(csv).expect("the batch wasn't saved");
+    let mut lines = contents.lines();
+    let header: Vec<_> = lines.next().unwrap().split(',').collect();
+    let code_col = header.iter().position(|col| *col == "code").unwrap();
+    let value_col = header.iter().position(|col| *col == "value").unwrap();
+    lines
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            let fields: Vec<_> = line.split(',').collect();
+            (fields[code_col].to_owned(), fields[value_col].parse().unwrap())
+        })
+        .collect()
+}
+
+#[test]
+fn funds_every_code_with_its_value() {
+    let dir = work_dir("fill");
+    let regtest = Regtest::from_env(&dir);
+
+    // Fund the wallet, with enough mature coinbases to cover the batch and its fees
+    regtest.generate(101);
+    let balance = regtest.rpc("getbalance", json!([])).unwrap().as_f64().unwrap();
+    assert!(balance > 5.0, "the regtest wallet only holds {} after mining", balance);
+
+    // Batcher reads the daemon's config and its templates from its home directory
+    regtest.write_coin_conf(&dir);
+    let templates = dir.join(".config/batcher/templates");
+    fs::create_dir_all(&templates).unwrap();
+    fs::write(templates.join("regtest.toml"), TEMPLATE).unwrap();
+
+    // Run a small batch through the real fill logic, taking every prompt's default
+    let output_dir = dir.join("out");
+    let keys = dir.join("keys.json");
+    let status = Command::new(env!("CARGO_BIN_EXE_batcher"))
+        .args(["--coin", &regtest.coin, "--prefix", "regtest", "--template", "regtest", "--no-clear"])
+        .args(["--version-bytes", &regtest.version_bytes])
+        .arg("--output-dir")
+        .arg(&output_dir)
+        .arg("--export-keys")
+        .arg(&keys)
+        .env("HOME", &dir)
+        .current_dir(&dir)
+        .stdin(Stdio::null())
+        .status()
+        .expect("failed to run batcher");
+    assert!(status.success(), "batcher exited with {}", status);
+
+    // Every code's address (from the key export) should have received exactly the code's value
+    let values = read_values(&output_dir.join("codes.csv"));
+    assert_eq!(values.len(), TEMPLATE_CODES);
+    let imports: Value = serde_json::from_str(&fs::read_to_string(&keys).unwrap()).unwrap();
+    let imports = imports.as_array().unwrap();
+    assert_eq!(imports.len(), TEMPLATE_CODES);
+    for import in imports {
+        let code = import["label"].as_str().unwrap();
+        let address = import["scriptPubKey"]["address"].as_str().unwrap();
+        let value = values[code];
+        let received = regtest.rpc("getreceivedbyaddress", json!([address, 0])).unwrap().as_f64().unwrap();
+        assert!(
+            (received - value).abs() < 1e-8,
+            "'{}' ({}) received {}, expected {}",
+            code,
+            address,
+            received,
+            value
+        );
+    }
+}